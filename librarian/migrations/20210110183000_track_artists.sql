@@ -0,0 +1,30 @@
+-- artists credited on a specific track, separate from the release. a release
+-- can designate a single album artist, which is what the library is organized
+-- by (and what compilations are credited to)
+
+ALTER TABLE releases ADD COLUMN album_artist_id INTEGER NULL
+  REFERENCES artists(id);
+
+CREATE TABLE track_artists (
+  track_id INTEGER NOT NULL,
+  artist_id INTEGER NOT NULL,
+  role TEXT NOT NULL DEFAULT 'primary'
+    CHECK (role IN ('primary', 'featured', 'remixer', 'producer')),
+  FOREIGN KEY(track_id) REFERENCES tracks(id),
+  FOREIGN KEY(artist_id) REFERENCES artists(id),
+  PRIMARY KEY(track_id, artist_id, role)
+);
+
+-- existing tracks only had release artists to go on
+INSERT INTO track_artists (track_id, artist_id, role)
+SELECT tracks.id, artist_releases.artist_id, 'primary'
+FROM tracks
+JOIN artist_releases ON tracks.release_id = artist_releases.release_id;
+
+UPDATE releases
+SET album_artist_id = (
+  SELECT artist_id FROM artist_releases WHERE release_id = releases.id
+)
+WHERE (
+  SELECT COUNT(*) FROM artist_releases WHERE release_id = releases.id
+) = 1;
@@ -0,0 +1,5 @@
+-- track and disc totals are stored per track, the same way they're tagged.
+-- releases don't always agree with their tracks on these
+ALTER TABLE tracks ADD COLUMN disc_num INTEGER NULL;
+ALTER TABLE tracks ADD COLUMN disc_total INTEGER NULL;
+ALTER TABLE tracks ADD COLUMN track_total INTEGER NULL;
@@ -0,0 +1,15 @@
+CREATE TABLE labels (
+  id INTEGER PRIMARY KEY NOT NULL,
+  name TEXT UNIQUE NOT NULL,
+  created TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- fields used to tell apart releases that share an artist + name, e.g. an
+-- original and its remaster, or an album and a single with the same title
+ALTER TABLE releases ADD COLUMN label_id INTEGER NULL REFERENCES labels(id);
+ALTER TABLE releases ADD COLUMN catalog_num TEXT NULL;
+ALTER TABLE releases ADD COLUMN barcode TEXT NULL;
+-- album, ep, single, compilation etc. not constrained, tags vary too much
+ALTER TABLE releases ADD COLUMN release_type TEXT NULL;
+-- free text edition / disambiguation comment e.g. "2015 remaster"
+ALTER TABLE releases ADD COLUMN edition TEXT NULL;
@@ -0,0 +1,17 @@
+CREATE TABLE genres (
+  id INTEGER PRIMARY KEY NOT NULL,
+  name TEXT UNIQUE NOT NULL COLLATE NOCASE,
+  created TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- tracks are regularly tagged with more than one genre
+CREATE TABLE track_genres (
+  track_id INTEGER NOT NULL REFERENCES tracks(id),
+  genre_id INTEGER NOT NULL REFERENCES genres(id),
+  PRIMARY KEY (track_id, genre_id)
+);
+
+CREATE INDEX track_genres_genre_id ON track_genres(genre_id);
+
+-- free text as tagged, multiple composers are joined with "; "
+ALTER TABLE tracks ADD COLUMN composer TEXT NULL;
@@ -0,0 +1,5 @@
+ALTER TABLE tracks ADD COLUMN bpm REAL NULL;
+-- normalized to Camelot notation e.g. "8A", see `key::MusicalKey`
+ALTER TABLE tracks ADD COLUMN key TEXT NULL;
+
+CREATE INDEX tracks_key ON tracks(key);
@@ -0,0 +1,7 @@
+-- where the bpm came from. detected values never replace tagged ones
+ALTER TABLE tracks ADD COLUMN bpm_source TEXT NULL
+  CHECK (bpm_source IN ('tagged', 'detected'));
+-- 0 - 1, only set for detected values
+ALTER TABLE tracks ADD COLUMN bpm_confidence REAL NULL;
+
+UPDATE tracks SET bpm_source = 'tagged' WHERE bpm IS NOT NULL;
@@ -0,0 +1,7 @@
+-- same as bpm_source, detected keys never replace tagged ones
+ALTER TABLE tracks ADD COLUMN key_source TEXT NULL
+  CHECK (key_source IN ('tagged', 'detected'));
+-- 0 - 1, only set for detected values
+ALTER TABLE tracks ADD COLUMN key_confidence REAL NULL;
+
+UPDATE tracks SET key_source = 'tagged' WHERE key IS NOT NULL;
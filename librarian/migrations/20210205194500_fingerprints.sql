@@ -0,0 +1,2 @@
+-- see `fingerprint::Fingerprint`. little endian u16 per frame
+ALTER TABLE tracks ADD COLUMN fingerprint BLOB NULL;
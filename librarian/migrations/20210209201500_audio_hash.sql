@@ -0,0 +1,3 @@
+-- see `hash::audio_hash`. NULL for files that couldn't be hashed
+ALTER TABLE tracks ADD COLUMN audio_hash TEXT NULL;
+CREATE INDEX tracks_audio_hash ON tracks(audio_hash);
@@ -0,0 +1,4 @@
+-- cleared when the file can't be found, see `Library::scan_missing`
+ALTER TABLE tracks ADD COLUMN available BOOLEAN NOT NULL DEFAULT 1;
+-- bytes, helps find the file again if it moves
+ALTER TABLE tracks ADD COLUMN file_size INTEGER NULL;
@@ -0,0 +1,12 @@
+-- seconds since the unix epoch. with file_size, spots files changed on disk
+ALTER TABLE tracks ADD COLUMN file_mtime INTEGER NULL;
+-- comma separated fields edited by hand, which rescans leave alone
+ALTER TABLE tracks ADD COLUMN locked_fields TEXT NOT NULL DEFAULT '';
+
+-- folders whose tracks are referenced in place and rescanned for changes
+CREATE TABLE watched_dirs (
+  id INTEGER PRIMARY KEY NOT NULL,
+  path TEXT UNIQUE NOT NULL,
+  last_scanned TEXT NULL,
+  created TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,25 @@
+-- one row per user action. state is 'done', 'undone' or 'discarded' (undone
+-- and then replaced by a newer action, so no longer redoable)
+CREATE TABLE edit_groups (
+  id INTEGER PRIMARY KEY NOT NULL,
+  description TEXT NOT NULL,
+  state TEXT NOT NULL DEFAULT 'done'
+  CHECK (state IN ('done', 'undone', 'discarded')),
+  created TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- append only. values are the column's value as text, or one item per line
+-- for lists (genres, tags, artists)
+CREATE TABLE edit_log (
+  id INTEGER PRIMARY KEY NOT NULL,
+  group_id INTEGER NOT NULL,
+  entity TEXT NOT NULL CHECK (entity IN ('track', 'release', 'artist')),
+  entity_id INTEGER NOT NULL,
+  field TEXT NOT NULL,
+  before TEXT NULL,
+  after TEXT NULL,
+  created TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  FOREIGN KEY(group_id) REFERENCES edit_groups(id)
+);
+
+CREATE INDEX edit_log_group ON edit_log(group_id);
@@ -0,0 +1,18 @@
+-- tags can be nested, e.g. techno under electronic. querying a tag includes
+-- tracks tagged with anything under it
+ALTER TABLE tags ADD COLUMN parent_id INTEGER NULL REFERENCES tags(id);
+-- for UIs, e.g. "#ff8800"
+ALTER TABLE tags ADD COLUMN color TEXT NULL;
+
+-- nothing stopped duplicate names before, keep the oldest of each
+INSERT OR IGNORE INTO track_tags (track_id, tag_id, created)
+SELECT track_tags.track_id, keep.id, track_tags.created
+FROM track_tags
+JOIN tags ON track_tags.tag_id = tags.id
+JOIN (SELECT MIN(id) AS id, name FROM tags GROUP BY name) keep
+  ON keep.name = tags.name;
+DELETE FROM track_tags
+WHERE tag_id NOT IN (SELECT MIN(id) FROM tags GROUP BY name);
+DELETE FROM tags WHERE id NOT IN (SELECT MIN(id) FROM tags GROUP BY name);
+
+CREATE UNIQUE INDEX tags_name ON tags(name);
@@ -0,0 +1,19 @@
+-- e.g. "Beatles, The". artists are ordered by name when there isn't one
+ALTER TABLE artists ADD COLUMN sort_name TEXT NULL;
+-- the name case + diacritic folded, see models::fold_artist_name. filled in
+-- for existing artists on open, since sqlite can't fold unicode
+ALTER TABLE artists ADD COLUMN match_name TEXT NULL;
+CREATE INDEX artists_match_name ON artists(match_name);
+
+-- other names an artist is tagged with, matched on import. merged artists'
+-- names end up here
+CREATE TABLE artist_aliases (
+  id INTEGER PRIMARY KEY NOT NULL,
+  artist_id INTEGER NOT NULL,
+  name TEXT UNIQUE NOT NULL,
+  match_name TEXT NOT NULL,
+  created TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  FOREIGN KEY(artist_id) REFERENCES artists(id)
+);
+
+CREATE INDEX artist_aliases_match_name ON artist_aliases(match_name);
@@ -0,0 +1,18 @@
+-- 0 - 5, fractions allowed for half stars etc
+ALTER TABLE tracks ADD COLUMN rating REAL NULL
+  CHECK (rating IS NULL OR rating BETWEEN 0 AND 5);
+
+-- one row per listen. skipped if it stopped before the play threshold, see
+-- UserConfig::play_threshold
+CREATE TABLE plays (
+  id INTEGER PRIMARY KEY NOT NULL,
+  track_id INTEGER NOT NULL,
+  played_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  -- seconds actually played
+  listened REAL NOT NULL,
+  skipped BOOLEAN NOT NULL DEFAULT 0,
+  FOREIGN KEY(track_id) REFERENCES tracks(id)
+);
+
+CREATE INDEX plays_track ON plays(track_id);
+CREATE INDEX plays_played_at ON plays(played_at);
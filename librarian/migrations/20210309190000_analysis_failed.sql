@@ -0,0 +1,3 @@
+-- set when a track couldn't be decoded or estimated, so it isn't decoded
+-- again on every run. cleared when the audio changes
+ALTER TABLE tracks ADD COLUMN analysis_failed BOOLEAN NOT NULL DEFAULT 0;
@@ -0,0 +1,6 @@
+-- loose matching ignores a leading "the" too now, see
+-- models::fold_artist_name. both spellings were folded to "the ..." before
+UPDATE artists SET match_name = substr(match_name, 5)
+WHERE match_name LIKE 'the %';
+UPDATE artist_aliases SET match_name = substr(match_name, 5)
+WHERE match_name LIKE 'the %';
@@ -0,0 +1,3 @@
+-- the track's length in seconds when it was played, if the format said.
+-- .scrobbler.log entries need it and tracks don't store one
+ALTER TABLE plays ADD COLUMN length REAL NULL;
@@ -0,0 +1,19 @@
+-- tag names are unique ignoring case, see `Tag::rename`. keep the oldest of
+-- any names that differ only in case
+INSERT OR IGNORE INTO track_tags (track_id, tag_id, created)
+SELECT track_tags.track_id, keep.id, track_tags.created
+FROM track_tags
+JOIN tags ON track_tags.tag_id = tags.id
+JOIN (SELECT MIN(id) AS id, name FROM tags GROUP BY name COLLATE NOCASE) keep
+  ON keep.name = tags.name COLLATE NOCASE;
+DELETE FROM track_tags WHERE tag_id NOT IN
+  (SELECT MIN(id) FROM tags GROUP BY name COLLATE NOCASE);
+UPDATE tags SET parent_id = (
+  SELECT MIN(keep.id) FROM tags keep, tags parent
+  WHERE parent.id = tags.parent_id AND keep.name = parent.name COLLATE NOCASE
+) WHERE parent_id IS NOT NULL;
+DELETE FROM tags WHERE id NOT IN
+  (SELECT MIN(id) FROM tags GROUP BY name COLLATE NOCASE);
+
+DROP INDEX IF EXISTS tags_name;
+CREATE UNIQUE INDEX tags_name ON tags(name COLLATE NOCASE);
@@ -0,0 +1,18 @@
+-- tag renames, merges and deletes are logged for undo too. sqlite can't
+-- change a CHECK constraint in place, so the table is rebuilt
+CREATE TABLE edit_log_new (
+  id INTEGER PRIMARY KEY NOT NULL,
+  group_id INTEGER NOT NULL,
+  entity TEXT NOT NULL
+  CHECK (entity IN ('track', 'release', 'artist', 'tag')),
+  entity_id INTEGER NOT NULL,
+  field TEXT NOT NULL,
+  before TEXT NULL,
+  after TEXT NULL,
+  created TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  FOREIGN KEY(group_id) REFERENCES edit_groups(id)
+);
+INSERT INTO edit_log_new SELECT * FROM edit_log;
+DROP TABLE edit_log;
+ALTER TABLE edit_log_new RENAME TO edit_log;
+CREATE INDEX edit_log_group ON edit_log(group_id);
@@ -0,0 +1,590 @@
+use crate::fingerprint::Fingerprint;
+use crate::key::{Mode, MusicalKey};
+use crate::parse::{self, AudioFormat};
+use crate::playback::{self, SampleReceiver};
+use crate::waveform::{Waveform, WaveformBuilder};
+use log::{debug, warn};
+use std::cmp::Ordering;
+use std::path::PathBuf;
+use std::sync::mpsc::Receiver;
+
+// plenty for both onsets and pitch up to ~5kHz, and keeps a whole track
+// in memory at a reasonable size
+pub const ANALYSIS_SAMPLE_RATE: u32 = 11025;
+
+// onset envelope resolution. ~86 frames/sec at the analysis rate
+const TEMPO_HOP: usize = 128;
+// tempo search range before folding into the preferred range
+const TEMPO_MIN_BPM: f64 = 40.0;
+const TEMPO_MAX_BPM: f64 = 240.0;
+// multiples of one beat also correlate, so favour tempos people actually
+// tap along to. log normal, in octaves around 120 BPM
+const TEMPO_PRIOR_CENTER: f64 = 120.0;
+const TEMPO_PRIOR_WIDTH: f64 = 1.0;
+// beats used to refine the period, integer lags are too coarse at higher
+// tempos (~3 BPM apart at 120)
+const TEMPO_REFINE_BEATS: usize = 8;
+
+// ~0.75s frames at the analysis rate, enough to resolve semitones in the bass
+const CHROMA_FRAME: usize = 8192;
+// C2 - B6 as midi notes. most harmonic content sits in here
+const CHROMA_LOWEST_NOTE: u8 = 36;
+const CHROMA_HIGHEST_NOTE: u8 = 95;
+// recordings aren't always tuned to A440, so each note is also measured a
+// third of a semitone either side
+const CHROMA_DETUNE: [f64; 3] = [-1.0 / 3.0, 0.0, 1.0 / 3.0];
+
+// Krumhansl-Kessler key profiles, starting on the tonic
+const MAJOR_PROFILE: [f64; 12] = [
+    6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88,
+];
+const MINOR_PROFILE: [f64; 12] = [
+    6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17,
+];
+
+/// decoded audio mixed down to mono and downsampled for analysis
+pub struct MonoSignal {
+    pub samples: Vec<f32>,
+    pub sample_rate: u32,
+}
+
+// mixes channels down for the waveform at the source rate, then averages
+// every `factor` of those into one. a boxcar filter isn't much of a low pass,
+// but it's good enough for analysis
+fn drain_mono<T, F: Fn(T) -> f32>(
+    rx: Receiver<T>,
+    to_f32: F,
+    channels: usize,
+    factor: usize,
+    waveform: &mut WaveformBuilder,
+) -> Vec<f32> {
+    let mut samples = Vec::new();
+    let (mut frame_sum, mut frame_count) = (0.0, 0);
+    let (mut block_sum, mut block_count) = (0.0, 0);
+    for s in rx.iter() {
+        // a broken float stream would poison every sum after it
+        let s = to_f32(s);
+        frame_sum += if s.is_finite() { s } else { 0.0 };
+        frame_count += 1;
+        if frame_count < channels {
+            continue;
+        }
+
+        let mono = frame_sum / channels as f32;
+        waveform.push(mono);
+        frame_sum = 0.0;
+        frame_count = 0;
+
+        block_sum += mono;
+        block_count += 1;
+        if block_count == factor {
+            samples.push(block_sum / factor as f32);
+            block_sum = 0.0;
+            block_count = 0;
+        }
+    }
+
+    samples
+}
+
+/// decodes a track with the playback sample channels, building its waveform
+/// along the way. None for formats playback doesn't support
+pub fn decode_mono(
+    path: PathBuf,
+    waveform_bands: bool,
+) -> Option<(MonoSignal, Waveform)> {
+    match parse::detect_format(&path) {
+        Some(AudioFormat::Ogg) | None => {
+            warn!("unsupported format for analysis {:?}", path);
+            return None;
+        }
+        _ => (),
+    };
+
+    let (rx, decode_thread, meta) = match playback::create_sample_channel(path)
+    {
+        Ok(channel) => channel,
+        Err(e) => {
+            warn!("unable to decode track for analysis {:?}", e);
+            return None;
+        }
+    };
+    let channels = meta.channels.max(1) as usize;
+    let factor = (meta.sample_rate / ANALYSIS_SAMPLE_RATE).max(1) as usize;
+    debug!("decoding for analysis {:?} factor {}", meta, factor);
+
+    let mut waveform = WaveformBuilder::new(meta.sample_rate, waveform_bands);
+    let wf = &mut waveform;
+    let samples = match rx {
+        SampleReceiver::I16(rx) => {
+            drain_mono(rx, |s| s as f32 / 32768.0, channels, factor, wf)
+        }
+        // 20 + 24 bit samples are unpacked into the low 24 bits
+        SampleReceiver::I32(rx) => {
+            let scale = match meta.bit_depth {
+                b if b <= 24 => (1 << 23) as f32,
+                _ => (1u32 << 31) as f32,
+            };
+            drain_mono(rx, |s| s as f32 / scale, channels, factor, wf)
+        }
+        SampleReceiver::F32(rx) => drain_mono(rx, |s| s, channels, factor, wf),
+    };
+
+    if decode_thread.join().is_err() {
+        warn!("decoder thread panicked, analyzing partial signal");
+    }
+
+    let signal = MonoSignal {
+        samples,
+        sample_rate: meta.sample_rate / factor as u32,
+    };
+
+    Some((signal, waveform.finish()))
+}
+
+/// results of `analyze`, None when not requested or not estimable
+#[derive(Debug, Default)]
+pub struct Analysis {
+    pub tempo: Option<TempoEstimate>,
+    pub key: Option<KeyEstimate>,
+    pub fingerprint: Option<Fingerprint>,
+    /// always built, decoding is the expensive part
+    pub waveform: Option<Waveform>,
+}
+
+/// decodes the track once for every requested estimate
+pub fn analyze(
+    path: PathBuf,
+    tempo: bool,
+    key: bool,
+    fingerprint: bool,
+    preferred_bpm: (f64, f64),
+    waveform_bands: bool,
+) -> Option<Analysis> {
+    let (signal, waveform) = decode_mono(path, waveform_bands)?;
+    Some(Analysis {
+        waveform: Some(waveform),
+        tempo: match tempo {
+            true => estimate_tempo(&signal, preferred_bpm),
+            false => None,
+        },
+        key: match key {
+            true => estimate_key(&signal),
+            false => None,
+        },
+        fingerprint: match fingerprint {
+            true => Some(Fingerprint::from_signal(&signal)),
+            false => None,
+        },
+    })
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TempoEstimate {
+    pub bpm: f64,
+    /// 0 - 1, how strongly periodic the onsets are at the estimated tempo
+    pub confidence: f64,
+}
+
+// log energy rise per hop, with high frequencies emphasized so percussive
+// hits stand out from sustained bass
+fn onset_envelope(samples: &[f32]) -> Vec<f64> {
+    let mut prev_sample = 0.0;
+    let energies: Vec<f64> = samples
+        .chunks(TEMPO_HOP)
+        .map(|frame| {
+            let mut energy = 0.0;
+            for s in frame {
+                let emphasized = (*s - 0.97 * prev_sample) as f64;
+                prev_sample = *s;
+                energy += emphasized * emphasized;
+            }
+            (1.0 + 1000.0 * energy / frame.len() as f64).ln()
+        })
+        .collect();
+
+    let flux: Vec<f64> = energies
+        .windows(2)
+        .map(|w| (w[1] - w[0]).max(0.0))
+        .collect();
+
+    // subtract a ~0.5s moving average so only local peaks remain
+    let radius = 22;
+    (0..flux.len())
+        .map(|i| {
+            let from = i.saturating_sub(radius);
+            let to = (i + radius + 1).min(flux.len());
+            let mean = flux[from..to].iter().sum::<f64>() / (to - from) as f64;
+            (flux[i] - mean).max(0.0)
+        })
+        .collect()
+}
+
+fn autocorrelation(envelope: &[f64], lag: usize) -> f64 {
+    if lag >= envelope.len() {
+        return 0.0;
+    }
+    let n = envelope.len() - lag;
+    let sum: f64 = (0..n).map(|i| envelope[i] * envelope[i + lag]).sum();
+    sum / n as f64
+}
+
+/// doubles or halves `bpm` until it falls inside `preferred`, where possible.
+/// onsets alone can't tell 70 from 140 BPM, so this is a matter of taste
+pub fn fold_tempo(bpm: f64, preferred: (f64, f64)) -> f64 {
+    let (min, max) = preferred;
+    let mut bpm = bpm;
+    while bpm < min {
+        bpm *= 2.0;
+    }
+    while bpm > max && bpm / 2.0 >= min {
+        bpm /= 2.0;
+    }
+    bpm
+}
+
+/// estimates tempo by autocorrelating an onset envelope. the beat period
+/// should correlate strongly with itself at one and two beats apart
+pub fn estimate_tempo(
+    signal: &MonoSignal,
+    preferred: (f64, f64),
+) -> Option<TempoEstimate> {
+    let envelope = onset_envelope(&signal.samples);
+    let frame_rate = signal.sample_rate as f64 / TEMPO_HOP as f64;
+    let min_lag = (frame_rate * 60.0 / TEMPO_MAX_BPM).floor() as usize;
+    let max_lag = (frame_rate * 60.0 / TEMPO_MIN_BPM).ceil() as usize;
+
+    // need a few beats worth of envelope to say anything
+    if envelope.len() < max_lag * 4 {
+        debug!("signal too short for tempo estimation");
+        return None;
+    }
+
+    let energy = autocorrelation(&envelope, 0);
+    if energy <= 0.0 {
+        debug!("no onsets found for tempo estimation");
+        return None;
+    }
+
+    let acf = |lag: usize| autocorrelation(&envelope, lag) / energy;
+    let prior = |lag: usize| {
+        let bpm = 60.0 * frame_rate / lag as f64;
+        let octaves = (bpm / TEMPO_PRIOR_CENTER).log2() / TEMPO_PRIOR_WIDTH;
+        (-0.5 * octaves * octaves).exp()
+    };
+    let score = |lag: usize| (acf(lag) + 0.5 * acf(lag * 2)) * prior(lag);
+
+    let best_lag = (min_lag.max(1)..=max_lag).max_by(|a, b| {
+        score(*a).partial_cmp(&score(*b)).unwrap_or(Ordering::Equal)
+    })?;
+    let confidence = acf(best_lag).max(0.0).min(1.0);
+
+    // the peak n beats out pins down the period n times more precisely
+    let beats = (1..=TEMPO_REFINE_BEATS)
+        .rev()
+        .find(|n| (best_lag + 1) * n < envelope.len() / 2)
+        .unwrap_or(1);
+    let around = best_lag * beats;
+    let peak = (around - beats..=around + beats)
+        .max_by(|a, b| acf(*a).partial_cmp(&acf(*b)).unwrap_or(Ordering::Equal))
+        .unwrap_or(around);
+
+    // parabolic interpolation between neighbouring lags
+    let (l, c, r) = (acf(peak - 1), acf(peak), acf(peak + 1));
+    let denom = l - 2.0 * c + r;
+    let offset = match denom.abs() > std::f64::EPSILON {
+        true => (0.5 * (l - r) / denom).max(-0.5).min(0.5),
+        false => 0.0,
+    };
+    let lag = (peak as f64 + offset) / beats as f64;
+
+    let bpm = fold_tempo(60.0 * frame_rate / lag, preferred);
+
+    Some(TempoEstimate {
+        bpm: (bpm * 100.0).round() / 100.0,
+        confidence,
+    })
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct KeyEstimate {
+    pub key: MusicalKey,
+    /// 0 - 1, correlation between the track's pitch content and the key
+    pub confidence: f64,
+}
+
+// power of a single frequency over a frame
+fn goertzel(frame: &[f32], window: &[f32], freq: f64, sample_rate: f64) -> f64 {
+    let coeff = 2.0 * (2.0 * std::f64::consts::PI * freq / sample_rate).cos();
+    let (mut prev, mut prev2) = (0.0, 0.0);
+    for (s, w) in frame.iter().zip(window) {
+        let curr = (s * w) as f64 + coeff * prev - prev2;
+        prev2 = prev;
+        prev = curr;
+    }
+    prev * prev + prev2 * prev2 - coeff * prev * prev2
+}
+
+/// energy per pitch class (C = 0) for every `hop` samples, up to `max_frames`.
+/// frames are normalized to sum to 1 so loud passages don't dominate, silent
+/// ones are all 0
+pub fn chroma_frames(
+    signal: &MonoSignal,
+    hop: usize,
+    max_frames: usize,
+) -> Vec<[f64; 12]> {
+    let sample_rate = signal.sample_rate as f64;
+    let window: Vec<f32> = (0..CHROMA_FRAME)
+        .map(|i| {
+            let phase = i as f32 / (CHROMA_FRAME - 1) as f32;
+            0.5 - 0.5 * (2.0 * std::f32::consts::PI * phase).cos()
+        })
+        .collect();
+
+    // skip notes the signal can't represent
+    let notes: Vec<(usize, Vec<f64>)> = (CHROMA_LOWEST_NOTE
+        ..=CHROMA_HIGHEST_NOTE)
+        .map(|note| {
+            let freqs = CHROMA_DETUNE
+                .iter()
+                .map(|d| 440.0 * 2f64.powf((note as f64 + d - 69.0) / 12.0))
+                .collect();
+            (note as usize % 12, freqs)
+        })
+        .filter(|(_, freqs): &(usize, Vec<f64>)| {
+            freqs.iter().all(|f| *f < sample_rate / 2.0)
+        })
+        .collect();
+
+    let frame_count = match signal.samples.len() >= CHROMA_FRAME {
+        true => (signal.samples.len() - CHROMA_FRAME) / hop + 1,
+        false => 0,
+    };
+    let mut frames = Vec::new();
+    for i in 0..frame_count.min(max_frames) {
+        let frame = &signal.samples[i * hop..i * hop + CHROMA_FRAME];
+        let mut frame_chroma = [0.0; 12];
+        for (pitch_class, freqs) in notes.iter() {
+            let power = freqs
+                .iter()
+                .map(|f| goertzel(frame, &window, *f, sample_rate))
+                .fold(0.0, f64::max);
+            // log compression evens out the louder low notes
+            frame_chroma[*pitch_class] += (1.0 + power).ln();
+        }
+
+        let total: f64 = frame_chroma.iter().sum();
+        if total > 0.0 {
+            frame_chroma.iter_mut().for_each(|c| *c /= total);
+        }
+        frames.push(frame_chroma);
+    }
+
+    frames
+}
+
+/// energy per pitch class (C = 0) over the whole signal
+pub fn chromagram(signal: &MonoSignal) -> [f64; 12] {
+    let mut chroma = [0.0; 12];
+    for frame in chroma_frames(signal, CHROMA_FRAME, usize::MAX) {
+        for (c, f) in chroma.iter_mut().zip(frame.iter()) {
+            *c += f;
+        }
+    }
+
+    chroma
+}
+
+fn pearson(a: &[f64; 12], b: &[f64; 12]) -> f64 {
+    let mean_a = a.iter().sum::<f64>() / 12.0;
+    let mean_b = b.iter().sum::<f64>() / 12.0;
+    let (mut cov, mut var_a, mut var_b) = (0.0, 0.0, 0.0);
+    for (x, y) in a.iter().zip(b.iter()) {
+        cov += (x - mean_a) * (y - mean_b);
+        var_a += (x - mean_a) * (x - mean_a);
+        var_b += (y - mean_b) * (y - mean_b);
+    }
+    match var_a * var_b > 0.0 {
+        true => cov / (var_a * var_b).sqrt(),
+        false => 0.0,
+    }
+}
+
+/// matches a chromagram against major + minor key profiles in all 12 keys
+pub fn estimate_key(signal: &MonoSignal) -> Option<KeyEstimate> {
+    let chroma = chromagram(signal);
+    if chroma.iter().all(|c| *c == 0.0) {
+        debug!("no pitch content found for key estimation");
+        return None;
+    }
+
+    let mut best: Option<KeyEstimate> = None;
+    for tonic in 0..12 {
+        for (mode, profile) in
+            &[(Mode::Major, MAJOR_PROFILE), (Mode::Minor, MINOR_PROFILE)]
+        {
+            // rotate the profile so it starts on C
+            let mut rotated = [0.0; 12];
+            for (i, weight) in profile.iter().enumerate() {
+                rotated[(i + tonic) % 12] = *weight;
+            }
+
+            let correlation = pearson(&chroma, &rotated);
+            match best {
+                Some(b) if b.confidence >= correlation => (),
+                _ => {
+                    best = Some(KeyEstimate {
+                        key: MusicalKey::new(tonic as u8, *mode),
+                        confidence: correlation,
+                    })
+                }
+            }
+        }
+    }
+
+    best.map(|b| KeyEstimate {
+        confidence: b.confidence.max(0.0).min(1.0),
+        ..b
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frequency(note: u8) -> f64 {
+        440.0 * 2f64.powf((note as f64 - 69.0) / 12.0)
+    }
+
+    // equal parts sine waves at the given midi notes
+    fn chord(notes: &[u8], seconds: f64) -> Vec<f32> {
+        let rate = ANALYSIS_SAMPLE_RATE as f64;
+        (0..(rate * seconds) as usize)
+            .map(|i| {
+                let t = i as f64 / rate;
+                let sum: f64 = notes
+                    .iter()
+                    .map(|n| {
+                        (2.0 * std::f64::consts::PI * frequency(*n) * t).sin()
+                    })
+                    .sum();
+                (0.5 * sum / notes.len() as f64) as f32
+            })
+            .collect()
+    }
+
+    fn progression(chords: &[&[u8]]) -> MonoSignal {
+        MonoSignal {
+            samples: chords.iter().flat_map(|c| chord(c, 1.5)).collect(),
+            sample_rate: ANALYSIS_SAMPLE_RATE,
+        }
+    }
+
+    #[test]
+    fn goertzel_picks_out_frequency() {
+        let rate = ANALYSIS_SAMPLE_RATE as f64;
+        let frame = chord(&[69], CHROMA_FRAME as f64 / rate);
+        let window = vec![1.0; frame.len()];
+        let a = goertzel(&frame, &window, 440.0, rate);
+        let b_flat = goertzel(&frame, &window, frequency(70), rate);
+        assert!(a > 100.0 * b_flat);
+    }
+
+    #[test]
+    fn chroma_of_triad() {
+        // C E G
+        let signal = progression(&[&[60, 64, 67]]);
+        let frames = chroma_frames(&signal, CHROMA_FRAME, usize::MAX);
+        assert!(!frames.is_empty());
+        for frame in frames {
+            assert!((frame.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+            for (pitch_class, energy) in frame.iter().enumerate() {
+                match pitch_class {
+                    0 | 4 | 7 => assert!(*energy > 0.3),
+                    _ => assert!(*energy < 0.01),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn silence_has_no_chroma_or_key() {
+        let signal = MonoSignal {
+            samples: vec![0.0; CHROMA_FRAME * 2],
+            sample_rate: ANALYSIS_SAMPLE_RATE,
+        };
+        assert_eq!(chromagram(&signal), [0.0; 12]);
+        assert!(estimate_key(&signal).is_none());
+    }
+
+    #[test]
+    fn profiles_correlate() {
+        assert!((pearson(&MAJOR_PROFILE, &MAJOR_PROFILE) - 1.0).abs() < 1e-9);
+        assert_eq!(pearson(&MAJOR_PROFILE, &[1.0; 12]), 0.0);
+
+        // a fifth away shares all but one note
+        let mut g_major = [0.0; 12];
+        for (i, weight) in MAJOR_PROFILE.iter().enumerate() {
+            g_major[(i + 7) % 12] = *weight;
+        }
+        let mut f_sharp_major = [0.0; 12];
+        for (i, weight) in MAJOR_PROFILE.iter().enumerate() {
+            f_sharp_major[(i + 6) % 12] = *weight;
+        }
+        assert!(
+            pearson(&MAJOR_PROFILE, &g_major)
+                > pearson(&MAJOR_PROFILE, &f_sharp_major)
+        );
+    }
+
+    #[test]
+    fn key_of_major_progression() {
+        // C F G C
+        let signal = progression(&[
+            &[60, 64, 67],
+            &[65, 69, 72],
+            &[67, 71, 74],
+            &[60, 64, 67],
+        ]);
+        let estimate = estimate_key(&signal).unwrap();
+        assert_eq!(estimate.key, MusicalKey::new(0, Mode::Major));
+        assert!(estimate.confidence > 0.8);
+    }
+
+    #[test]
+    fn key_of_minor_progression() {
+        // Am Dm E Am
+        let signal = progression(&[
+            &[57, 60, 64],
+            &[62, 65, 69],
+            &[64, 68, 71],
+            &[57, 60, 64],
+        ]);
+        let estimate = estimate_key(&signal).unwrap();
+        assert_eq!(estimate.key, MusicalKey::new(9, Mode::Minor));
+        assert!(estimate.confidence > 0.8);
+    }
+
+    #[test]
+    fn tempo_folds_into_range() {
+        assert_eq!(fold_tempo(70.0, (90.0, 180.0)), 140.0);
+        assert_eq!(fold_tempo(35.0, (90.0, 180.0)), 140.0);
+        assert_eq!(fold_tempo(200.0, (90.0, 180.0)), 100.0);
+        assert_eq!(fold_tempo(128.0, (90.0, 180.0)), 128.0);
+        // halving would drop below the range, so it's left above
+        assert_eq!(fold_tempo(120.0, (100.0, 110.0)), 120.0);
+        assert_eq!(fold_tempo(60.0, (100.0, 110.0)), 120.0);
+    }
+
+    #[test]
+    fn non_finite_samples_dropped() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        for s in &[0.5, std::f32::NAN, 0.5, std::f32::INFINITY, 0.25, 0.25] {
+            tx.send(*s).unwrap();
+        }
+        drop(tx);
+        let mut waveform = WaveformBuilder::new(ANALYSIS_SAMPLE_RATE, false);
+        let samples = drain_mono(rx, |s| s, 2, 1, &mut waveform);
+        assert_eq!(samples, vec![0.25, 0.25, 0.25]);
+    }
+}
@@ -0,0 +1,315 @@
+use crate::{
+    key::MusicalKey,
+    models::{Artist, ArtistRole, DetailedTrack, Release, RowId, TrackEdit},
+};
+use regex::Regex;
+
+/// how a text field is changed for every track in a batch
+#[derive(Clone, Debug)]
+pub enum TextChange {
+    Set(String),
+    Clear,
+    /// added to the end of the current value, or set if there isn't one
+    Append(String),
+    /// `with` may refer to capture groups, e.g. "$1"
+    Replace {
+        find: Regex,
+        with: String,
+    },
+    Capitalize(Case),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Case {
+    Lower,
+    Upper,
+    /// first letter of every word
+    Title,
+    /// first letter only
+    Sentence,
+}
+
+/// names added to and removed from a list field like genres
+#[derive(Clone, Debug, Default)]
+pub struct ListChange {
+    pub add: Vec<String>,
+    pub remove: Vec<String>,
+}
+
+/// changes applied to every track in a batch. unset fields are left alone,
+/// `Some(None)` clears a value
+#[derive(Clone, Debug, Default)]
+pub struct BatchEdit {
+    pub name: Option<TextChange>,
+    pub composer: Option<TextChange>,
+    /// moves the tracks into this release
+    pub release: Option<Release>,
+    /// replaces the artist credits of every track
+    pub artists: Option<Vec<(Artist, ArtistRole)>>,
+    pub track_total: Option<Option<RowId>>,
+    pub disc_num: Option<Option<RowId>>,
+    pub disc_total: Option<Option<RowId>>,
+    pub bpm: Option<Option<f64>>,
+    pub key: Option<Option<MusicalKey>>,
+    pub genres: ListChange,
+    pub tags: ListChange,
+}
+
+/// a field's value before + after an edit, formatted for display
+#[derive(Clone, Debug, PartialEq)]
+pub struct FieldChange {
+    pub field: &'static str,
+    pub before: String,
+    pub after: String,
+}
+
+/// what a batch edit would change about one track
+#[derive(Clone, Debug)]
+pub struct TrackDiff {
+    pub track_id: RowId,
+    pub changes: Vec<FieldChange>,
+    /// to pass to `models::Track::update_many`
+    pub edit: TrackEdit,
+}
+
+fn capitalize(text: &str, case: Case) -> String {
+    // upper cases the first char, lower cases the rest
+    let word_case = |word: &str| {
+        let mut chars = word.chars();
+        match chars.next() {
+            Some(first) => first
+                .to_uppercase()
+                .chain(chars.flat_map(char::to_lowercase))
+                .collect(),
+            None => String::new(),
+        }
+    };
+
+    match case {
+        Case::Lower => text.to_lowercase(),
+        Case::Upper => text.to_uppercase(),
+        Case::Sentence => word_case(text),
+        // split on spaces only, so the original spacing is kept
+        Case::Title => {
+            text.split(' ').map(word_case).collect::<Vec<_>>().join(" ")
+        }
+    }
+}
+
+impl TextChange {
+    fn apply(&self, text: Option<&str>) -> Option<String> {
+        let changed = match (self, text) {
+            (TextChange::Set(t), _) => Some(t.clone()),
+            (TextChange::Clear, _) => None,
+            (TextChange::Append(t), Some(current)) => {
+                Some(format!("{}{}", current, t))
+            }
+            (TextChange::Append(t), None) => Some(t.clone()),
+            (TextChange::Replace { find, with }, Some(current)) => {
+                Some(find.replace_all(current, with.as_str()).into_owned())
+            }
+            (TextChange::Capitalize(case), Some(current)) => {
+                Some(capitalize(current, *case))
+            }
+            (_, None) => None,
+        };
+        changed.filter(|t| !t.is_empty())
+    }
+}
+
+impl ListChange {
+    // case insensitive, same as genre + tag names
+    fn apply(&self, names: &[String]) -> Vec<String> {
+        let removed = |name: &String| {
+            self.remove
+                .iter()
+                .any(|r| r.to_lowercase() == name.to_lowercase())
+        };
+        let mut changed: Vec<String> =
+            names.iter().filter(|n| !removed(n)).cloned().collect();
+        for name in self.add.iter() {
+            let lower = name.to_lowercase();
+            if !changed.iter().any(|n| n.to_lowercase() == lower) {
+                changed.push(name.clone());
+            }
+        }
+        changed.sort();
+        changed
+    }
+}
+
+fn show<T: ToString>(value: &Option<T>) -> String {
+    value.as_ref().map(|v| v.to_string()).unwrap_or_default()
+}
+
+fn show_credits<'a>(
+    credits: impl Iterator<Item = (&'a Artist, ArtistRole)>,
+) -> String {
+    credits
+        .map(|(artist, role)| match role {
+            ArtistRole::Primary => artist.name.clone(),
+            _ => format!("{} ({})", artist.name, role.as_str()),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl BatchEdit {
+    /// the track's edit + a diff of what it changes, empty if nothing does
+    pub fn apply(&self, track: &DetailedTrack) -> TrackDiff {
+        let before = TrackEdit::from(track);
+        let mut edit = before.clone();
+        let mut changes = Vec::new();
+        let mut change = |field, before: String, after: String| {
+            if before != after {
+                changes.push(FieldChange {
+                    field,
+                    before,
+                    after,
+                });
+            }
+        };
+
+        if let Some(c) = &self.name {
+            // tracks always have a name, an empty one is left alone
+            if let Some(name) = c.apply(Some(&before.name)) {
+                edit.name = name;
+            }
+        }
+        change("name", before.name.clone(), edit.name.clone());
+
+        if let Some(c) = &self.composer {
+            edit.composer = c.apply(before.composer.as_deref());
+        }
+        change("composer", show(&before.composer), show(&edit.composer));
+
+        if let Some(release) = &self.release {
+            edit.release_id = release.id;
+            if release.id != track.release.id {
+                change(
+                    "release",
+                    track.release.name.clone(),
+                    release.name.clone(),
+                );
+            }
+        }
+
+        if let Some(artists) = &self.artists {
+            edit.artists = artists.iter().map(|(a, r)| (a.id, *r)).collect();
+            if edit.artists != before.artists {
+                change(
+                    "artists",
+                    show_credits(
+                        track
+                            .track_artists
+                            .iter()
+                            .map(|ta| (&ta.artist, ta.role)),
+                    ),
+                    show_credits(artists.iter().map(|(a, r)| (a, *r))),
+                );
+            }
+        }
+
+        let numbers = [
+            ("track_total", &self.track_total, &mut edit.track_total),
+            ("disc_num", &self.disc_num, &mut edit.disc_num),
+            ("disc_total", &self.disc_total, &mut edit.disc_total),
+        ];
+        for (field, new, value) in numbers {
+            if let Some(new) = new {
+                let old = show(value);
+                *value = *new;
+                change(field, old, show(value));
+            }
+        }
+
+        if let Some(bpm) = self.bpm {
+            edit.bpm = bpm;
+        }
+        change("bpm", show(&before.bpm), show(&edit.bpm));
+
+        if let Some(key) = self.key {
+            edit.key = key;
+        }
+        change("key", show(&before.key), show(&edit.key));
+
+        edit.genres = self.genres.apply(&before.genres);
+        change("genres", before.genres.join(", "), edit.genres.join(", "));
+
+        edit.tags = self.tags.apply(&before.tags);
+        change("tags", before.tags.join(", "), edit.tags.join(", "));
+
+        TrackDiff {
+            track_id: track.id,
+            changes,
+            edit,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn capitalizes() {
+        let text = "the  QUICK brown élan";
+        assert_eq!(capitalize(text, Case::Lower), "the  quick brown élan");
+        assert_eq!(capitalize(text, Case::Upper), "THE  QUICK BROWN ÉLAN");
+        assert_eq!(capitalize(text, Case::Title), "The  Quick Brown Élan");
+        assert_eq!(capitalize(text, Case::Sentence), "The  quick brown élan");
+        assert_eq!(capitalize("", Case::Title), "");
+    }
+
+    #[test]
+    fn replaces_with_capture_groups() {
+        let change = TextChange::Replace {
+            find: Regex::new(r"^(.+) \((.+) Remix\)$").unwrap(),
+            with: "$1 [$2 Mix]".to_owned(),
+        };
+        assert_eq!(
+            change.apply(Some("Song (Someone Remix)")),
+            Some("Song [Someone Mix]".to_owned())
+        );
+        // no match leaves the text as it was
+        assert_eq!(change.apply(Some("Song")), Some("Song".to_owned()));
+        assert_eq!(change.apply(None), None);
+    }
+
+    #[test]
+    fn empty_results_clear() {
+        let change = TextChange::Replace {
+            find: Regex::new(".*").unwrap(),
+            with: String::new(),
+        };
+        assert_eq!(change.apply(Some("anything")), None);
+        assert_eq!(TextChange::Set(String::new()).apply(Some("a")), None);
+    }
+
+    #[test]
+    fn appends() {
+        let change = TextChange::Append(" (live)".to_owned());
+        assert_eq!(change.apply(Some("Song")), Some("Song (live)".to_owned()));
+        assert_eq!(change.apply(None), Some(" (live)".to_owned()));
+    }
+
+    #[test]
+    fn list_changes_ignore_case() {
+        let change = ListChange {
+            add: names(&["Techno", "ambient", "Dub"]),
+            remove: names(&["house"]),
+        };
+        assert_eq!(
+            change.apply(&names(&["Ambient", "House", "Electro"])),
+            names(&["Ambient", "Dub", "Electro", "Techno"])
+        );
+        assert_eq!(
+            ListChange::default().apply(&names(&["b", "a"])),
+            names(&["a", "b"])
+        );
+    }
+}
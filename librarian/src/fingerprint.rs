@@ -0,0 +1,201 @@
+use crate::analysis::{self, MonoSignal};
+use std::collections::HashMap;
+
+/// seconds from the start of a track that get fingerprinted
+pub const FINGERPRINT_SECONDS: usize = 120;
+// half a chroma frame, ~0.37s at the analysis rate
+const FINGERPRINT_HOP: usize = 4096;
+// how far apart the same audio can start in two files (e.g. trimmed silence,
+// encoder delay), in frames. ~6s
+const MAX_OFFSET: isize = 16;
+// aligned frames needed for a comparison to mean anything
+const MIN_OVERLAP: usize = 16;
+// pairs of consecutive frames two fingerprints have to share before they're
+// compared properly
+const MIN_SHARED_KEYS: usize = 4;
+// keys found in more fingerprints than this are too common to narrow
+// anything down, e.g. a held chord
+const MAX_KEY_TRACKS: usize = 64;
+
+/// a 12 bit code per frame, one bit per pitch class that stands out.
+/// survives re-encoding, resampling and gain changes, but not pitch shifts
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fingerprint(Vec<u16>);
+
+impl Fingerprint {
+    pub fn from_signal(signal: &MonoSignal) -> Self {
+        let max_frames =
+            FINGERPRINT_SECONDS * signal.sample_rate as usize / FINGERPRINT_HOP;
+        let codes =
+            analysis::chroma_frames(signal, FINGERPRINT_HOP, max_frames)
+                .iter()
+                .map(|chroma| {
+                    // frames sum to 1, silence is all 0
+                    chroma
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, c)| **c > 1.0 / 12.0)
+                        .fold(0u16, |code, (i, _)| code | 1 << i)
+                })
+                .collect();
+
+        Fingerprint(codes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        Fingerprint(
+            bytes
+                .chunks_exact(2)
+                .map(|b| u16::from_le_bytes([b[0], b[1]]))
+                .collect(),
+        )
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.0
+            .iter()
+            .flat_map(|c| c.to_le_bytes().to_vec())
+            .collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.iter().all(|c| *c == 0)
+    }
+
+    /// 0 - 1, the share of matching bits at the best alignment. unrelated
+    /// tracks tend to land around 0.6, the same recording above 0.9
+    pub fn similarity(&self, other: &Self) -> f64 {
+        let (a, b) = (&self.0, &other.0);
+        (-MAX_OFFSET..=MAX_OFFSET)
+            .filter_map(|offset| {
+                let pairs = (0..a.len()).filter_map(|i| {
+                    let j = i as isize + offset;
+                    match j >= 0 && (j as usize) < b.len() {
+                        true => Some((a[i], b[j as usize])),
+                        false => None,
+                    }
+                });
+
+                // silence matches silence, which says nothing
+                let (matching, compared) = pairs
+                    .filter(|(x, y)| *x != 0 && *y != 0)
+                    .fold((0, 0), |(matching, compared), (x, y)| {
+                        let differing = (x ^ y).count_ones() as usize;
+                        (matching + 12 - differing, compared + 1)
+                    });
+
+                match compared >= MIN_OVERLAP {
+                    true => Some(matching as f64 / (compared * 12) as f64),
+                    false => None,
+                }
+            })
+            .fold(0.0, f64::max)
+    }
+
+    // consecutive frame pairs, used to find candidates without comparing
+    // every fingerprint with every other one
+    fn index_keys(&self) -> Vec<u32> {
+        let mut keys: Vec<u32> = self
+            .0
+            .windows(2)
+            .filter(|w| w[0] != 0 && w[1] != 0)
+            .map(|w| (w[0] as u32) << 12 | w[1] as u32)
+            .collect();
+        keys.sort();
+        keys.dedup();
+        keys
+    }
+}
+
+/// tracks that are probably the same recording
+#[derive(Clone, Debug)]
+pub struct DuplicateGroup {
+    pub track_ids: Vec<i64>,
+    /// the weakest similarity linking the group together
+    pub score: f64,
+}
+
+fn find_root(parents: &mut HashMap<i64, i64>, id: i64) -> i64 {
+    let parent = *parents.get(&id).unwrap_or(&id);
+    match parent == id {
+        true => id,
+        false => {
+            let root = find_root(parents, parent);
+            parents.insert(id, root);
+            root
+        }
+    }
+}
+
+/// groups fingerprints scoring at least `min_score` with each other.
+/// matches are transitive, so a group may contain pairs scoring lower
+pub fn group_duplicates(
+    prints: &[(i64, Fingerprint)],
+    min_score: f64,
+) -> Vec<DuplicateGroup> {
+    let mut index: HashMap<u32, Vec<usize>> = HashMap::new();
+    for (idx, (_, print)) in prints.iter().enumerate() {
+        for key in print.index_keys() {
+            index.entry(key).or_insert(Vec::new()).push(idx);
+        }
+    }
+
+    let mut shared: HashMap<(usize, usize), usize> = HashMap::new();
+    for matches in index.values() {
+        if matches.len() > MAX_KEY_TRACKS {
+            continue;
+        }
+        for (n, a) in matches.iter().enumerate() {
+            for b in matches[n + 1..].iter() {
+                *shared.entry((*a, *b)).or_insert(0) += 1;
+            }
+        }
+    }
+
+    let mut parents = HashMap::new();
+    let mut scores: HashMap<i64, f64> = HashMap::new();
+    for ((a, b), count) in shared {
+        if count < MIN_SHARED_KEYS {
+            continue;
+        }
+
+        let ((id_a, print_a), (id_b, print_b)) = (&prints[a], &prints[b]);
+        let score = print_a.similarity(print_b);
+        if score < min_score {
+            continue;
+        }
+
+        let (root_a, root_b) = (
+            find_root(&mut parents, *id_a),
+            find_root(&mut parents, *id_b),
+        );
+        let merged_score = [scores.get(&root_a), scores.get(&root_b)]
+            .iter()
+            .filter_map(|s| s.copied())
+            .fold(score, f64::min);
+        if root_a != root_b {
+            parents.insert(root_b, root_a);
+            scores.remove(&root_b);
+        }
+        scores.insert(root_a, merged_score);
+    }
+
+    let mut groups: HashMap<i64, Vec<i64>> = HashMap::new();
+    for (id, _) in prints.iter() {
+        let root = find_root(&mut parents, *id);
+        if scores.contains_key(&root) {
+            groups.entry(root).or_insert(Vec::new()).push(*id);
+        }
+    }
+
+    groups
+        .into_iter()
+        .map(|(root, mut track_ids)| {
+            track_ids.sort();
+            DuplicateGroup {
+                track_ids,
+                score: scores[&root],
+            }
+        })
+        .collect()
+}
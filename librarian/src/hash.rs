@@ -0,0 +1,377 @@
+use crate::parse::{self, AudioFormat};
+use std::{
+    fs,
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+const ID3V1_LEN: u64 = 128;
+const APE_FOOTER_LEN: u64 = 32;
+// APE tags optionally start with a header the same size as the footer
+const APE_HAS_HEADER: u32 = 1 << 31;
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn read_at(file: &mut fs::File, at: u64, buf: &mut [u8]) -> io::Result<()> {
+    file.seek(SeekFrom::Start(at))?;
+    file.read_exact(buf)
+}
+
+// ID3v2 tags can be stacked, e.g. after a tagger prepends without removing
+fn skip_id3v2(file: &mut fs::File, len: u64) -> io::Result<u64> {
+    let mut start = 0;
+    let mut header = [0u8; 10];
+    while start + 10 <= len {
+        read_at(file, start, &mut header)?;
+        match parse::id3v2_len(&header) {
+            Some(tag_len) => start += tag_len,
+            None => break,
+        }
+    }
+    Ok(start)
+}
+
+// ID3v1 is always last, an APE tag can sit right before it
+fn trim_trailing_tags(
+    file: &mut fs::File,
+    start: u64,
+    end: u64,
+) -> io::Result<u64> {
+    let mut end = end;
+    let mut id3v1 = [0u8; 3];
+    if end >= start + ID3V1_LEN {
+        read_at(file, end - ID3V1_LEN, &mut id3v1)?;
+        if &id3v1 == b"TAG" {
+            end -= ID3V1_LEN;
+        }
+    }
+
+    let mut footer = [0u8; APE_FOOTER_LEN as usize];
+    if end >= start + APE_FOOTER_LEN {
+        read_at(file, end - APE_FOOTER_LEN, &mut footer)?;
+        if &footer[0..8] == b"APETAGEX" {
+            let le_u32 = |at: usize| {
+                u32::from_le_bytes([
+                    footer[at],
+                    footer[at + 1],
+                    footer[at + 2],
+                    footer[at + 3],
+                ])
+            };
+            // size covers the items and footer, not the header
+            let mut tag_len = le_u32(12) as u64;
+            if le_u32(20) & APE_HAS_HEADER != 0 {
+                tag_len += APE_FOOTER_LEN;
+            }
+            end = end.saturating_sub(tag_len).max(start);
+        }
+    }
+
+    Ok(end)
+}
+
+// frames start after the last metadata block. vorbis comments, pictures and
+// padding are all metadata blocks
+fn flac_ranges(file: &mut fs::File, len: u64) -> io::Result<Vec<(u64, u64)>> {
+    let start = skip_id3v2(file, len)?;
+    let mut magic = [0u8; 4];
+    read_at(file, start, &mut magic)?;
+    if &magic != b"fLaC" {
+        return Err(invalid("missing flac stream marker"));
+    }
+
+    let mut pos = start + 4;
+    let mut header = [0u8; 4];
+    loop {
+        read_at(file, pos, &mut header)?;
+        let block_len =
+            u32::from_be_bytes([0, header[1], header[2], header[3]]);
+        pos += 4 + block_len as u64;
+        if pos > len {
+            return Err(invalid("flac metadata runs past end of file"));
+        }
+        if header[0] & 0x80 != 0 {
+            break;
+        }
+    }
+
+    let end = trim_trailing_tags(file, pos, len)?;
+    Ok(vec![(pos, end - pos)])
+}
+
+fn mp3_ranges(file: &mut fs::File, len: u64) -> io::Result<Vec<(u64, u64)>> {
+    let start = skip_id3v2(file, len)?;
+    let end = trim_trailing_tags(file, start, len)?;
+    Ok(vec![(start, end - start)])
+}
+
+fn iff_ranges(path: &Path, big_endian: bool) -> io::Result<Vec<(u64, u64)>> {
+    match parse::read_iff_chunks(path, big_endian)?.audio_data {
+        Some(range) => Ok(vec![range]),
+        None => Err(invalid("no audio data chunk")),
+    }
+}
+
+// samples live in mdat atoms, tags in moov. taggers rewrite moov and may move
+// mdat around, but leave its contents alone
+fn mp4_ranges(file: &mut fs::File, len: u64) -> io::Result<Vec<(u64, u64)>> {
+    let mut ranges = Vec::new();
+    let mut pos = 0;
+    let mut header = [0u8; 8];
+    while pos + 8 <= len {
+        read_at(file, pos, &mut header)?;
+        let mut atom_len =
+            u32::from_be_bytes([header[0], header[1], header[2], header[3]])
+                as u64;
+        let mut header_len = 8;
+        match atom_len {
+            // runs to the end of the file
+            0 => atom_len = len - pos,
+            // 64 bit length follows the type
+            1 => {
+                let mut large = [0u8; 8];
+                file.read_exact(&mut large)?;
+                atom_len = u64::from_be_bytes(large);
+                header_len = 16;
+            }
+            _ => (),
+        }
+        if atom_len < header_len || pos + atom_len > len {
+            return Err(invalid("mp4 atom runs past end of file"));
+        }
+
+        if &header[4..8] == b"mdat" {
+            ranges.push((pos + header_len, atom_len - header_len));
+        }
+        pos += atom_len;
+    }
+
+    match ranges.is_empty() {
+        true => Err(invalid("no mdat atom")),
+        false => Ok(ranges),
+    }
+}
+
+/// hex digest of a file's audio data, skipping over tags + other metadata so
+/// retagging doesn't change it. the same audio in a different container
+/// hashes differently
+pub fn audio_hash(path: &Path, format: AudioFormat) -> io::Result<String> {
+    let mut file = fs::File::open(path)?;
+    let len = file.metadata()?.len();
+
+    let ranges = match format {
+        AudioFormat::Flac => flac_ranges(&mut file, len)?,
+        AudioFormat::Mp3 => mp3_ranges(&mut file, len)?,
+        AudioFormat::Wav => iff_ranges(path, false)?,
+        AudioFormat::Aiff => iff_ranges(path, true)?,
+        AudioFormat::Mp4 => mp4_ranges(&mut file, len)?,
+        AudioFormat::Ogg => {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "ogg hashing unsupported",
+            ))
+        }
+    };
+
+    let mut hasher = blake3::Hasher::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    for (offset, range_len) in ranges {
+        file.seek(SeekFrom::Start(offset))?;
+        let mut remaining = range_len;
+        while remaining > 0 {
+            let chunk = remaining.min(buf.len() as u64) as usize;
+            file.read_exact(&mut buf[..chunk])?;
+            hasher.update(&buf[..chunk]);
+            remaining -= chunk as u64;
+        }
+    }
+
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{fake_audio, flac_stream, temp_file};
+    use id3::TagLike;
+
+    // hashes files built around the same audio with different tags, then
+    // around different audio with the same tags
+    fn assert_tags_skipped<F>(name: &str, format: AudioFormat, build: F)
+    where
+        F: Fn(&str, &[u8]) -> Vec<u8>,
+    {
+        let audio = fake_audio(4099);
+        let mut other = audio.clone();
+        other[2048] ^= 1;
+        let hash = |n: usize, title: &str, audio: &[u8]| {
+            let name = format!("hash-{}-{}", n, name);
+            let path = temp_file(&name, &build(title, audio));
+            let hash = audio_hash(&path, format).unwrap();
+            fs::remove_file(&path).unwrap();
+            hash
+        };
+
+        let original = hash(0, "Old", &audio);
+        assert_eq!(hash(1, "A longer retagged title", &audio), original);
+        assert_ne!(hash(2, "Old", &other), original);
+    }
+
+    fn id3v2(title: &str) -> Vec<u8> {
+        let mut tag = id3::Tag::new();
+        tag.set_title(title);
+        let mut bytes = Vec::new();
+        tag.write_to(&mut bytes, id3::Version::Id3v24).unwrap();
+        bytes
+    }
+
+    fn id3v1(title: &str) -> Vec<u8> {
+        let mut tag = b"TAG".to_vec();
+        let mut field = title.as_bytes().to_vec();
+        field.resize(30, 0);
+        tag.extend_from_slice(&field);
+        tag.resize(128, 0);
+        tag
+    }
+
+    // APEv2 with both a header and a footer
+    fn ape(title: &str) -> Vec<u8> {
+        let mut items = Vec::new();
+        items.extend_from_slice(&(title.len() as u32).to_le_bytes());
+        items.extend_from_slice(&0u32.to_le_bytes());
+        items.extend_from_slice(b"Title\0");
+        items.extend_from_slice(title.as_bytes());
+        let header = |is_header: u32| {
+            let mut h = b"APETAGEX".to_vec();
+            h.extend_from_slice(&2000u32.to_le_bytes());
+            let size = items.len() as u32 + APE_FOOTER_LEN as u32;
+            h.extend_from_slice(&size.to_le_bytes());
+            h.extend_from_slice(&1u32.to_le_bytes());
+            let flags = APE_HAS_HEADER | is_header << 29;
+            h.extend_from_slice(&flags.to_le_bytes());
+            h.extend_from_slice(&[0; 8]);
+            h
+        };
+        let mut tag = header(1);
+        tag.extend_from_slice(&items);
+        tag.extend_from_slice(&header(0));
+        tag
+    }
+
+    fn chunk(id: &[u8; 4], data: &[u8], big_endian: bool) -> Vec<u8> {
+        let mut chunk = id.to_vec();
+        let len = data.len() as u32;
+        match big_endian {
+            true => chunk.extend_from_slice(&len.to_be_bytes()),
+            false => chunk.extend_from_slice(&len.to_le_bytes()),
+        }
+        chunk.extend_from_slice(data);
+        if data.len() % 2 == 1 {
+            chunk.push(0);
+        }
+        chunk
+    }
+
+    fn atom(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut atom = (data.len() as u32 + 8).to_be_bytes().to_vec();
+        atom.extend_from_slice(kind);
+        atom.extend_from_slice(data);
+        atom
+    }
+
+    #[test]
+    fn flac_tags_skipped() {
+        assert_tags_skipped("tags.flac", AudioFormat::Flac, |title, audio| {
+            [&flac_stream(title), audio].concat()
+        });
+    }
+
+    #[test]
+    fn flac_behind_id3_tags_skipped() {
+        assert_tags_skipped("id3.flac", AudioFormat::Flac, |title, audio| {
+            // stacked, as left by taggers that prepend without removing
+            [&id3v2(title), &id3v2(title), &flac_stream(title), audio].concat()
+        });
+    }
+
+    #[test]
+    fn mp3_tags_skipped() {
+        assert_tags_skipped("tags.mp3", AudioFormat::Mp3, |title, audio| {
+            let sync = [0xFF, 0xFB, 0x90, 0x64];
+            [&id3v2(title), &sync[..], audio, &ape(title), &id3v1(title)]
+                .concat()
+        });
+    }
+
+    #[test]
+    fn wav_tags_skipped() {
+        assert_tags_skipped("tags.wav", AudioFormat::Wav, |title, audio| {
+            let fmt = [1, 0, 2, 0, 68, 172, 0, 0, 16, 177, 2, 0, 4, 0, 16, 0];
+            let info = [&b"INFO"[..], &chunk(b"INAM", title.as_bytes(), false)]
+                .concat();
+            let chunks = [
+                chunk(b"fmt ", &fmt, false),
+                chunk(b"LIST", &info, false),
+                chunk(b"data", audio, false),
+                chunk(b"id3 ", &id3v2(title), false),
+            ]
+            .concat();
+            let len = chunks.len() as u32 + 4;
+            [&b"RIFF"[..], &len.to_le_bytes(), b"WAVE", &chunks].concat()
+        });
+    }
+
+    #[test]
+    fn aiff_tags_skipped() {
+        assert_tags_skipped("tags.aiff", AudioFormat::Aiff, |title, audio| {
+            // 2 channels, 16 bit, 44.1kHz as an 80 bit float
+            let comm = [
+                0, 2, 0, 0, 0, 0, 0, 16, 0x40, 0x0E, 0xAC, 0x44, 0, 0, 0, 0, 0,
+                0,
+            ];
+            // offset + block size come before the samples
+            let ssnd = [&[0; 8][..], audio].concat();
+            let chunks = [
+                chunk(b"COMM", &comm, true),
+                chunk(b"NAME", title.as_bytes(), true),
+                chunk(b"SSND", &ssnd, true),
+                chunk(b"ID3 ", &id3v2(title), true),
+            ]
+            .concat();
+            let len = chunks.len() as u32 + 4;
+            [&b"FORM"[..], &len.to_be_bytes(), b"AIFF", &chunks].concat()
+        });
+    }
+
+    #[test]
+    fn mp4_tags_skipped() {
+        let ftyp = atom(b"ftyp", b"M4A \0\0\0\0M4A mp42isom");
+        let moov = |title: &str| {
+            let nam = atom(
+                b"data",
+                &[&[0, 0, 0, 1, 0, 0, 0, 0], title.as_bytes()].concat(),
+            );
+            let ilst = atom(b"ilst", &atom(b"\xa9nam", &nam));
+            atom(b"moov", &atom(b"udta", &atom(b"meta", &ilst)))
+        };
+        assert_tags_skipped("tags.m4a", AudioFormat::Mp4, |title, audio| {
+            [&ftyp[..], &moov(title), &atom(b"mdat", audio)].concat()
+        });
+
+        // taggers may move mdat, and large files use 64 bit lengths
+        assert_tags_skipped("moved.m4a", AudioFormat::Mp4, |title, audio| {
+            let mut mdat = 1u32.to_be_bytes().to_vec();
+            mdat.extend_from_slice(b"mdat");
+            mdat.extend_from_slice(&(audio.len() as u64 + 16).to_be_bytes());
+            mdat.extend_from_slice(audio);
+            match title {
+                "Old" => {
+                    [&ftyp[..], &moov(title), &atom(b"mdat", audio)].concat()
+                }
+                _ => [&ftyp[..], &mdat, &moov(title)].concat(),
+            }
+        });
+    }
+}
@@ -0,0 +1,305 @@
+use std::fmt;
+
+// pitch classes, C = 0. tags mostly use these spellings
+const MAJOR_NAMES: [&'static str; 12] = [
+    "C", "Db", "D", "Eb", "E", "F", "F#", "G", "Ab", "A", "Bb", "B",
+];
+const MINOR_NAMES: [&'static str; 12] = [
+    "C", "C#", "D", "Eb", "E", "F", "F#", "G", "G#", "A", "Bb", "B",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Mode {
+    Major,
+    Minor,
+}
+
+/// a musical key. tags use a few different notations, all of which parse into
+/// this:
+/// - standard e.g. "Am", "F# minor", "Ebmaj"
+/// - Camelot e.g. "8A" (minor) / "8B" (major)
+/// - Open Key e.g. "1m" (minor) / "1d" (major)
+///
+/// keys are stored in Camelot notation, which sorts + compares the simplest
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MusicalKey {
+    /// pitch class of the tonic, C = 0
+    pitch: u8,
+    mode: Mode,
+}
+
+impl MusicalKey {
+    pub fn new(pitch: u8, mode: Mode) -> Self {
+        MusicalKey {
+            pitch: pitch % 12,
+            mode,
+        }
+    }
+
+    pub fn pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// tries every notation, returns None for empty or unrecognized keys
+    /// (including ID3's "o" for off key)
+    pub fn parse(key: &str) -> Option<Self> {
+        let key = key.trim();
+        Self::from_camelot(key)
+            .or_else(|| Self::from_open_key(key))
+            .or_else(|| Self::from_standard(key))
+    }
+
+    // wheel position + letter, shared by camelot + open key
+    fn split_wheel(key: &str) -> Option<(u8, char)> {
+        let last = key.chars().last()?;
+        let num: u8 = key[..key.len() - last.len_utf8()].trim().parse().ok()?;
+        let letter = last.to_ascii_lowercase();
+        match num {
+            1..=12 => Some((num, letter)),
+            _ => None,
+        }
+    }
+
+    pub fn from_camelot(key: &str) -> Option<Self> {
+        let (num, letter) = Self::split_wheel(key)?;
+        let mode = match letter {
+            'a' => Mode::Minor,
+            'b' => Mode::Major,
+            _ => return None,
+        };
+        // 8B is C major, every step is a fifth up
+        let major_pitch = ((num as u32 + 4) * 7 % 12) as u8;
+        Some(Self::from_relative_major(major_pitch, mode))
+    }
+
+    pub fn from_open_key(key: &str) -> Option<Self> {
+        let (num, letter) = Self::split_wheel(key)?;
+        let mode = match letter {
+            'm' => Mode::Minor,
+            'd' => Mode::Major,
+            _ => return None,
+        };
+        // 1d is C major
+        let major_pitch = ((num as u32 - 1) * 7 % 12) as u8;
+        Some(Self::from_relative_major(major_pitch, mode))
+    }
+
+    pub fn from_standard(key: &str) -> Option<Self> {
+        let mut chars = key.chars();
+        let natural = match chars.next()?.to_ascii_uppercase() {
+            'C' => 0,
+            'D' => 2,
+            'E' => 4,
+            'F' => 5,
+            'G' => 7,
+            'A' => 9,
+            'B' => 11,
+            _ => return None,
+        };
+
+        let rest = chars.as_str();
+        let accidental = rest.chars().next();
+        let pitch = match accidental {
+            Some('#') | Some('♯') => natural + 1,
+            Some('b') | Some('♭') => natural + 11,
+            _ => natural,
+        };
+        let rest = match pitch == natural {
+            true => rest,
+            false => &rest[accidental.unwrap().len_utf8()..],
+        };
+
+        // "m" is minor, "M" major
+        let mode = match rest.trim() {
+            "" | "M" => Mode::Major,
+            "m" => Mode::Minor,
+            r => match r.to_lowercase().as_str() {
+                "maj" | "major" => Mode::Major,
+                "min" | "minor" => Mode::Minor,
+                _ => return None,
+            },
+        };
+
+        Some(Self::new(pitch, mode))
+    }
+
+    fn from_relative_major(major_pitch: u8, mode: Mode) -> Self {
+        match mode {
+            Mode::Major => Self::new(major_pitch, mode),
+            Mode::Minor => Self::new(major_pitch + 9, mode),
+        }
+    }
+
+    fn relative_major_pitch(&self) -> u8 {
+        match self.mode {
+            Mode::Major => self.pitch,
+            Mode::Minor => (self.pitch + 3) % 12,
+        }
+    }
+
+    /// position on the Camelot / Open Key wheels is by circle of fifths
+    fn fifths(&self) -> u8 {
+        self.relative_major_pitch() * 7 % 12
+    }
+
+    /// 1 - 12
+    pub fn camelot_number(&self) -> u8 {
+        (self.fifths() + 7) % 12 + 1
+    }
+
+    pub fn to_camelot(&self) -> String {
+        let letter = match self.mode {
+            Mode::Major => 'B',
+            Mode::Minor => 'A',
+        };
+        format!("{}{}", self.camelot_number(), letter)
+    }
+
+    pub fn to_open_key(&self) -> String {
+        let letter = match self.mode {
+            Mode::Major => 'd',
+            Mode::Minor => 'm',
+        };
+        format!("{}{}", self.fifths() + 1, letter)
+    }
+
+    pub fn to_standard(&self) -> String {
+        match self.mode {
+            Mode::Major => MAJOR_NAMES[self.pitch as usize].to_owned(),
+            Mode::Minor => format!("{}m", MINOR_NAMES[self.pitch as usize]),
+        }
+    }
+
+    /// major <-> minor sharing the same notes e.g. C <-> Am
+    pub fn relative(&self) -> Self {
+        match self.mode {
+            Mode::Major => Self::new(self.pitch + 9, Mode::Minor),
+            Mode::Minor => Self::new(self.pitch + 3, Mode::Major),
+        }
+    }
+
+    /// keys that mix harmonically with this one: itself, a step either way on
+    /// the Camelot wheel, and its relative major / minor
+    pub fn compatible(&self) -> [Self; 4] {
+        [
+            *self,
+            // a fifth up
+            Self::new(self.pitch + 7, self.mode),
+            // a fifth down
+            Self::new(self.pitch + 5, self.mode),
+            self.relative(),
+        ]
+    }
+
+    pub fn is_compatible(&self, other: &Self) -> bool {
+        self.compatible().contains(other)
+    }
+}
+
+impl fmt::Display for MusicalKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_standard())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn all_keys() -> Vec<MusicalKey> {
+        (0..12)
+            .flat_map(|p| {
+                vec![
+                    MusicalKey::new(p, Mode::Major),
+                    MusicalKey::new(p, Mode::Minor),
+                ]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn camelot_notation() {
+        let parse = |k| MusicalKey::parse(k);
+        assert_eq!(parse("8B"), Some(MusicalKey::new(0, Mode::Major)));
+        assert_eq!(parse("8A"), Some(MusicalKey::new(9, Mode::Minor)));
+        assert_eq!(parse("8a"), Some(MusicalKey::new(9, Mode::Minor)));
+        assert_eq!(parse("1A"), Some(MusicalKey::new(8, Mode::Minor)));
+        assert_eq!(parse("12B"), Some(MusicalKey::new(4, Mode::Major)));
+        assert_eq!(parse("0A"), None);
+        assert_eq!(parse("13B"), None);
+        assert_eq!(parse("8C"), None);
+    }
+
+    #[test]
+    fn open_key_notation() {
+        let parse = |k| MusicalKey::parse(k);
+        assert_eq!(parse("1d"), Some(MusicalKey::new(0, Mode::Major)));
+        assert_eq!(parse("1m"), Some(MusicalKey::new(9, Mode::Minor)));
+        assert_eq!(parse("2m"), Some(MusicalKey::new(4, Mode::Minor)));
+        assert_eq!(parse("6D"), Some(MusicalKey::new(11, Mode::Major)));
+        assert_eq!(parse("13d"), None);
+    }
+
+    #[test]
+    fn standard_notation() {
+        let parse = |k| MusicalKey::parse(k);
+        assert_eq!(parse("Am"), Some(MusicalKey::new(9, Mode::Minor)));
+        assert_eq!(parse("F# minor"), Some(MusicalKey::new(6, Mode::Minor)));
+        assert_eq!(parse("Ebmaj"), Some(MusicalKey::new(3, Mode::Major)));
+        assert_eq!(parse("c#m"), Some(MusicalKey::new(1, Mode::Minor)));
+        assert_eq!(parse("B♭"), Some(MusicalKey::new(10, Mode::Major)));
+        assert_eq!(parse(" C "), Some(MusicalKey::new(0, Mode::Major)));
+        assert_eq!(parse("Cb"), Some(MusicalKey::new(11, Mode::Major)));
+        // off key
+        assert_eq!(parse("o"), None);
+        assert_eq!(parse(""), None);
+        assert_eq!(parse("H"), None);
+        assert_eq!(parse("Cdim"), None);
+    }
+
+    #[test]
+    fn notations_round_trip() {
+        for key in all_keys() {
+            let camelot = key.to_camelot();
+            assert_eq!(MusicalKey::parse(&camelot), Some(key), "{}", camelot);
+            let open_key = key.to_open_key();
+            assert_eq!(MusicalKey::parse(&open_key), Some(key), "{}", open_key);
+            let standard = key.to_standard();
+            assert_eq!(MusicalKey::parse(&standard), Some(key), "{}", standard);
+        }
+        assert_eq!(MusicalKey::new(0, Mode::Major).to_camelot(), "8B");
+        assert_eq!(MusicalKey::new(9, Mode::Minor).to_open_key(), "1m");
+        assert_eq!(MusicalKey::new(6, Mode::Major).to_standard(), "F#");
+        assert_eq!(MusicalKey::new(8, Mode::Minor).to_standard(), "G#m");
+    }
+
+    #[test]
+    fn compatible_keys() {
+        let am = MusicalKey::parse("8A").unwrap();
+        let compatible = am.compatible();
+        for k in &["8A", "9A", "7A", "8B"] {
+            let key = MusicalKey::parse(k).unwrap();
+            assert!(compatible.contains(&key), "{}", k);
+        }
+        assert!(!am.is_compatible(&MusicalKey::parse("10A").unwrap()));
+        assert!(!am.is_compatible(&MusicalKey::parse("9B").unwrap()));
+
+        // neighbours on the wheel, or across to the relative key
+        for key in all_keys() {
+            let number = key.camelot_number() as i32;
+            for other in key.compatible().iter() {
+                let step =
+                    (other.camelot_number() as i32 - number).rem_euclid(12);
+                match other.mode() == key.mode() {
+                    true => assert!(step == 0 || step == 1 || step == 11),
+                    false => assert_eq!(step, 0),
+                }
+                assert!(other.is_compatible(&key));
+            }
+        }
+    }
+}
@@ -1,34 +1,57 @@
 extern crate aiff;
+extern crate alac;
+extern crate blake3;
 extern crate chrono;
 extern crate claxon;
 extern crate cpal;
 extern crate directories_next;
 extern crate futures;
 extern crate hound;
+extern crate id3;
 extern crate log;
 extern crate minimp3;
-// TODO find a way to share or reuse reader from rtag
-extern crate rtag; // TODO use id3
+extern crate mp4ameta;
+extern crate notify;
+extern crate redlux;
+extern crate regex;
 extern crate serde;
 extern crate serde_derive;
+extern crate serde_json;
 extern crate sqlx;
 extern crate toml;
+extern crate unicode_normalization;
 
 use directories_next::BaseDirs;
 use futures::future::{self, FutureExt};
-use log::{debug, error, info, trace};
+use log::{debug, error, info, trace, warn};
 use sqlx::sqlite::{SqliteConnectOptions, SqlitePool};
 use std::{
+    collections::{HashMap, HashSet},
     fs,
     path::{Path, PathBuf},
 };
 use tokio::fs as async_fs;
+use tokio::sync::broadcast;
 use tokio::sync::mpsc as tokio_mpsc;
+use tokio::sync::oneshot;
 
+pub mod analysis;
+pub mod batch;
+pub mod fingerprint;
+pub mod hash;
+pub mod key;
 pub mod models;
+mod naming;
 pub mod parse;
 pub mod playback;
+mod relink;
+pub mod scrobble;
+pub mod tagwrite;
+#[cfg(test)]
+mod test_util;
 mod userconfig;
+pub mod watch;
+pub mod waveform;
 
 use playback::AudioStream;
 use userconfig::UserConfig;
@@ -57,10 +80,101 @@ pub fn parse_dir(
     Ok(())
 }
 
+// sends new files + files whose size or mtime differ from `known`, collects
+// every file found into `seen`
+fn rescan_dir(
+    tx: &tokio_mpsc::UnboundedSender<parse::ParseResult>,
+    path: &Path,
+    known: &HashMap<PathBuf, (Option<i64>, Option<i64>)>,
+    seen: &mut HashSet<PathBuf>,
+) {
+    let entries = match fs::read_dir(path) {
+        Ok(e) => e,
+        Err(e) => {
+            error!("failed to read dir {:?} {:?}", path, e);
+            return;
+        }
+    };
+
+    for entry_path in entries.filter_map(|e| e.ok()).map(|e| e.path()) {
+        if entry_path.is_dir() {
+            rescan_dir(tx, &entry_path, known, seen);
+            continue;
+        }
+
+        seen.insert(entry_path.clone());
+        let unchanged =
+            match (known.get(&entry_path), parse::file_stat(&entry_path)) {
+                (Some((Some(size), Some(mtime))), Some(stat)) => {
+                    (*size as u64, *mtime) == stat
+                }
+                _ => false,
+            };
+        if unchanged {
+            continue;
+        }
+
+        if let Some(result) = parse::parse_track(entry_path) {
+            if let Err(e) = tx.send(result) {
+                error!("sending parse result failed {:?}", e);
+            }
+        }
+    }
+}
+
+// paths are stored as text, files with names that aren't UTF-8 are skipped
+fn utf8_path(path: &Path) -> Option<&str> {
+    let utf8 = path.to_str();
+    if utf8.is_none() {
+        warn!("skipping path that isn't UTF-8 {:?}", path);
+    }
+    utf8
+}
+
+/// what `Library::rescan_watched` found
+#[derive(Debug, Default)]
+pub struct RescanReport {
+    pub added: Vec<models::DetailedTrack>,
+    /// ids of tracks refreshed from their changed files
+    pub updated: Vec<i64>,
+    /// tracks whose file is gone, now marked unavailable
+    pub removed: Vec<models::Track>,
+}
+
+/// what `Library::import_scrobbler_log` + `import_listening_history` did
+#[derive(Debug, Default)]
+pub struct ScrobbleImport {
+    pub imported: usize,
+    /// already recorded
+    pub duplicates: usize,
+    /// no track in the library matches, or the time is out of range
+    pub unmatched: Vec<scrobble::Scrobble>,
+}
+
+// notifications a slow subscriber can fall behind by before missing some
+const CHANGES_CAPACITY: usize = 64;
+
+/// sent to `Library::subscribe`rs whenever tracks change outside of a direct
+/// request, e.g. from watching the filesystem
+#[derive(Clone, Debug)]
+pub enum LibraryChange {
+    TracksAdded(Vec<i64>),
+    /// metadata or file location changed
+    TracksUpdated(Vec<i64>),
+    /// files are gone, the tracks are marked unavailable
+    TracksRemoved(Vec<i64>),
+    /// the playing track reached its end, see `Library::stream_finished`
+    StreamFinished(i64),
+}
+
 pub struct Library {
     db_pool: SqlitePool,
     stream: Option<AudioStream>,
+    // track id + when the stream started, UTC
+    now_playing: Option<(i64, String)>,
     config: UserConfig,
+    waveform_dir: PathBuf,
+    changes: broadcast::Sender<LibraryChange>,
 }
 
 impl Library {
@@ -108,11 +222,27 @@ impl Library {
         info!("connected to db");
 
         sqlx::migrate!("./migrations").run(&db_pool).await.unwrap();
+        let mut conn = db_pool.acquire().await.unwrap();
+        let filled = models::Artist::fill_match_names(&mut conn).await.unwrap();
+        if filled > 0 {
+            debug!("filled match names for {} artists", filled);
+        }
+        drop(conn);
+
+        let waveform_dir = config_dir.join("waveforms");
+        if !waveform_dir.exists() {
+            async_fs::create_dir_all(&waveform_dir)
+                .await
+                .expect("unable to create waveform cache dir");
+        }
 
         Library {
             db_pool,
             stream: None,
+            now_playing: None,
             config: UserConfig::load_from(config_dir.join("rpconfig.toml")),
+            waveform_dir,
+            changes: broadcast::channel(CHANGES_CAPACITY).0,
         }
     }
 
@@ -133,7 +263,20 @@ impl Library {
         let mut copies_idx = 0;
         let mut noncopies = Vec::new();
         let mut imported_tracks = Vec::new();
+        let mut import_hashes = HashSet::new();
+        let release_match = self.config.release_match();
+        let artist_match = self.config.artist_match();
         while let Some(msg) = rx.recv().await {
+            // the pool only has one conn, it can't be held onto
+            let duplicate = {
+                let mut conn = self.db_pool.acquire().await.unwrap();
+                self.is_duplicate(&mut conn, &msg, &mut import_hashes).await
+            };
+            if duplicate {
+                info!("skipping duplicate track {:?}", msg.path);
+                continue;
+            }
+
             // TODO handle artist and album unknown
             // is it crazy to store empty strings for unknown artist? seems cleaner.
             // but that means needeing to check for empty strings to decide
@@ -143,8 +286,14 @@ impl Library {
             // "Unknown Artist" and how the system internally handles the absence of a name
 
             if self.config.copy_on_import() {
+                let release_artist = match (&msg.album_artist, msg.compilation)
+                {
+                    (Some(a), _) => a.as_str(),
+                    (None, true) => parse::VARIOUS_ARTISTS,
+                    (None, false) => msg.artists[0].as_str(),
+                };
                 let mut release_path =
-                    self.config.library_dir().join(&msg.artists[0]);
+                    self.config.library_dir().join(release_artist);
                 release_path.push(&msg.album);
 
                 trace!("about to create dir if needed {:?}", &release_path);
@@ -156,9 +305,19 @@ impl Library {
                     (true, true) => (),
                 };
 
-                // TODO probably use track name + number as track name? expose config
                 let mut track_path = release_path;
-                track_path.push(&msg.path.file_name().unwrap());
+                match self.config.track_name_template() {
+                    Some(template) => {
+                        let mut name =
+                            naming::render_track_name(template, &msg);
+                        if let Some(ext) = msg.path.extension() {
+                            name.push('.');
+                            name.push_str(&ext.to_string_lossy());
+                        }
+                        track_path.push(name);
+                    }
+                    None => track_path.push(&msg.path.file_name().unwrap()),
+                };
 
                 if track_path.exists() {
                     error!("target track path exists, skipping import")
@@ -168,40 +327,32 @@ impl Library {
                     // TODO check fs handle limit with `ulimit -n`
                     // try to raise? need to figure out how many I can safely acquire
                     // FIXME remove file if db insert fails - or switch order?
-                    copies.push(
-                        async_fs::copy(msg.path.clone(), track_path.clone())
-                            .then(|res| match res {
-                                Ok(_) => {
-                                    debug!("getting lock");
-                                    self.db_pool.acquire()
-                                }
-                                Err(e) => {
-                                    panic!("failed to copy track {:?}", e)
-                                }
-                            })
-                            .then(|res| match res {
-                                Ok(c) => {
-                                    let mut msg = msg;
-                                    // update path to show import location
-                                    msg.path = track_path;
-                                    debug!("importing to db {:?}", msg);
-                                    models::import_from_parse_result(c, msg)
-                                }
-                                Err(e) => {
-                                    panic!("failed to acquire conn {:?}", e);
-                                }
-                            }),
-                    );
+                    copies.push(self.copy_and_import(
+                        msg,
+                        track_path,
+                        release_match,
+                        artist_match,
+                    ));
                     copies_idx += 1;
                 }
-            } else {
+            } else if utf8_path(&msg.path).is_some() {
                 trace!("not copying track on import");
-                noncopies.push(self.db_pool.acquire().then(|res| match res {
-                    Ok(c) => models::import_from_parse_result(c, msg),
-                    Err(e) => {
-                        panic!("failed to acquire conn {:?}", e);
-                    }
-                }));
+                noncopies.push(
+                    self.db_pool
+                        .acquire()
+                        .then(|res| match res {
+                            Ok(c) => models::import_from_parse_result(
+                                c,
+                                msg,
+                                release_match,
+                                artist_match,
+                            ),
+                            Err(e) => {
+                                panic!("failed to acquire conn {:?}", e);
+                            }
+                        })
+                        .map(Some),
+                );
             }
 
             if copies_idx > (fs_handle_limit - 1) {
@@ -230,7 +381,525 @@ impl Library {
         imported_tracks.append(&mut final_import_noncopies);
         debug!("final copies futures joined");
 
-        imported_tracks
+        // failed copies are skipped
+        let imported: Vec<_> = imported_tracks.into_iter().flatten().collect();
+        self.notify(LibraryChange::TracksAdded(
+            imported.iter().map(|t| t.id).collect(),
+        ));
+        imported
+    }
+
+    /// notifications of changes to tracks, see `LibraryChange`
+    pub fn subscribe(&self) -> broadcast::Receiver<LibraryChange> {
+        self.changes.subscribe()
+    }
+
+    fn notify(&self, change: LibraryChange) {
+        let empty = match &change {
+            LibraryChange::TracksAdded(ids)
+            | LibraryChange::TracksUpdated(ids)
+            | LibraryChange::TracksRemoved(ids) => ids.is_empty(),
+            LibraryChange::StreamFinished(_) => false,
+        };
+        // only fails without subscribers
+        if !empty && self.changes.send(change).is_err() {
+            trace!("no library change subscribers");
+        }
+    }
+
+    /// watches the library dir + watched dirs for changes to audio files.
+    /// changes should be passed to `apply_fs_change` on the same task as
+    /// imports, so files being copied in aren't picked up twice. watched
+    /// dirs added later need a new watcher
+    pub async fn watch(&self) -> notify::Result<watch::LibraryWatcher> {
+        let mut dirs = vec![self.config.library_dir().to_path_buf()];
+        for dir in self.get_watched_dirs().await {
+            dirs.push(PathBuf::from(dir.path));
+        }
+        dirs.retain(|d| match d.is_dir() {
+            true => true,
+            false => {
+                error!("can't watch missing dir {:?}", d);
+                false
+            }
+        });
+
+        watch::LibraryWatcher::new(&dirs)
+    }
+
+    /// brings the library up to date with a change from `watch`, notifying
+    /// subscribers
+    pub async fn apply_fs_change(&self, change: watch::FsChange) {
+        match change {
+            watch::FsChange::Changed(msg) => self.apply_changed(msg).await,
+            watch::FsChange::Removed(path) => {
+                let path = match utf8_path(&path) {
+                    Some(path) => path,
+                    None => return,
+                };
+                let mut conn = self.db_pool.acquire().await.unwrap();
+                // could be a dir, taking its tracks with it
+                let mut removed = Vec::new();
+                for track in models::Track::get_under_path(&mut conn, path)
+                    .await
+                    .unwrap()
+                {
+                    models::Track::set_available(&mut conn, track.id, false)
+                        .await
+                        .unwrap();
+                    removed.push(track.id);
+                }
+                self.notify(LibraryChange::TracksRemoved(removed));
+            }
+            watch::FsChange::Moved(from, msg) => {
+                let (from_path, to_path) =
+                    match (utf8_path(&from), utf8_path(&msg.path)) {
+                        (Some(from), Some(to)) => (from, to),
+                        _ => return,
+                    };
+                let mut conn = self.db_pool.acquire().await.unwrap();
+                let moved = models::Track::get_by_path(&mut conn, from_path)
+                    .await
+                    .unwrap();
+                let replaced = models::Track::get_by_path(&mut conn, to_path)
+                    .await
+                    .unwrap();
+                match (moved, replaced) {
+                    (Some(track), None) => {
+                        models::Track::relink(
+                            &mut conn,
+                            &[(track.id, to_path.to_owned())],
+                            None,
+                        )
+                        .await
+                        .unwrap();
+                        models::Track::update_from_parse(
+                            &mut conn, track.id, &msg,
+                        )
+                        .await
+                        .unwrap();
+                        self.notify(LibraryChange::TracksUpdated(vec![
+                            track.id,
+                        ]));
+                    }
+                    // e.g. saved over from a temp file, or one track's file
+                    // moved over another's
+                    (moved, _) => {
+                        if let Some(track) = moved {
+                            models::Track::set_available(
+                                &mut conn, track.id, false,
+                            )
+                            .await
+                            .unwrap();
+                            self.notify(LibraryChange::TracksRemoved(vec![
+                                track.id,
+                            ]));
+                        }
+                        drop(conn);
+                        self.apply_changed(msg).await;
+                    }
+                }
+            }
+            watch::FsChange::DirMoved(from, to) => {
+                let from_path = match utf8_path(&from) {
+                    Some(path) => path,
+                    None => return,
+                };
+                let mut conn = self.db_pool.acquire().await.unwrap();
+                let moves: Vec<_> =
+                    models::Track::get_under_path(&mut conn, from_path)
+                        .await
+                        .unwrap()
+                        .into_iter()
+                        .filter_map(|t| {
+                            let rest = Path::new(&t.file_path)
+                                .strip_prefix(&from)
+                                .ok()?
+                                .to_owned();
+                            let new_path = to.join(rest);
+                            Some((t.id, new_path.to_str()?.to_owned()))
+                        })
+                        .collect();
+                models::Track::relink(&mut conn, &moves, None)
+                    .await
+                    .unwrap();
+                self.notify(LibraryChange::TracksUpdated(
+                    moves.iter().map(|(id, _)| *id).collect(),
+                ));
+            }
+            watch::FsChange::Rescan => {
+                self.rescan_watched().await;
+            }
+        }
+    }
+
+    // a new file is imported in place, a known one is refreshed unless it
+    // hasn't actually changed
+    async fn apply_changed(&self, msg: parse::ParseResult) {
+        let path = match utf8_path(&msg.path) {
+            Some(path) => path,
+            None => return,
+        };
+        let mut conn = self.db_pool.acquire().await.unwrap();
+        match models::Track::get_by_path(&mut conn, path).await.unwrap() {
+            Some(track) => {
+                let stat = (track.file_size, track.file_mtime);
+                let new_stat =
+                    (msg.file_size.map(|s| s as i64), msg.file_mtime);
+                if stat == new_stat && track.available {
+                    return;
+                }
+                debug!("refreshing changed track {:?}", msg.path);
+                models::Track::update_from_parse(&mut conn, track.id, &msg)
+                    .await
+                    .unwrap();
+                self.notify(LibraryChange::TracksUpdated(vec![track.id]));
+            }
+            None => {
+                let mut hashes = HashSet::new();
+                if self.is_duplicate(&mut conn, &msg, &mut hashes).await {
+                    info!("skipping duplicate track {:?}", msg.path);
+                    return;
+                }
+                let added = models::import_from_parse_result(
+                    conn,
+                    msg,
+                    self.config.release_match(),
+                    self.config.artist_match(),
+                )
+                .await;
+                self.notify(LibraryChange::TracksAdded(vec![added.id]));
+            }
+        }
+    }
+
+    // the same file may already be in the library, or the same audio under
+    // another path / with different tags, including earlier in this import
+    async fn is_duplicate(
+        &self,
+        conn: &mut models::SqlitePoolConn,
+        msg: &parse::ParseResult,
+        import_hashes: &mut HashSet<String>,
+    ) -> bool {
+        // a path that isn't UTF-8 can't be in the library
+        if let Some(path) = msg.path.to_str() {
+            let existing =
+                models::Track::get_by_path(conn, path).await.unwrap();
+            if existing.is_some() {
+                return true;
+            }
+        }
+
+        match &msg.audio_hash {
+            Some(audio_hash) => {
+                let existing =
+                    models::Track::get_by_audio_hash(conn, audio_hash)
+                        .await
+                        .unwrap();
+                !existing.is_empty()
+                    || !import_hashes.insert(audio_hash.clone())
+            }
+            None => false,
+        }
+    }
+
+    /// `path` is scanned by `rescan_watched` from then on. its tracks are
+    /// referenced in place, never copied. None if the path isn't UTF-8
+    pub async fn add_watched_dir(
+        &self,
+        path: &Path,
+    ) -> Option<models::WatchedDir> {
+        let path = utf8_path(path)?;
+        let mut conn = self.db_pool.acquire().await.unwrap();
+        let dir = models::WatchedDir::find_or_create(&mut conn, path)
+            .await
+            .unwrap();
+        Some(dir)
+    }
+
+    pub async fn get_watched_dirs(&self) -> Vec<models::WatchedDir> {
+        let mut conn = self.db_pool.acquire().await.unwrap();
+        models::WatchedDir::get_all(&mut conn).await.unwrap()
+    }
+
+    /// stops watching, tracks already imported from the dir are kept
+    pub async fn remove_watched_dir(&self, id: i64) {
+        let mut conn = self.db_pool.acquire().await.unwrap();
+        models::WatchedDir::delete(&mut conn, id).await.unwrap()
+    }
+
+    /// imports new files in every watched dir and refreshes tracks whose file
+    /// changed size or mtime, without reparsing anything else. tracks whose
+    /// file is gone are marked unavailable, not removed. dirs that can't be
+    /// found (e.g. an unmounted drive) are skipped entirely
+    pub async fn rescan_watched(&self) -> RescanReport {
+        let (dirs, known) = {
+            let mut conn = self.db_pool.acquire().await.unwrap();
+            let dirs = models::WatchedDir::get_all(&mut conn).await.unwrap();
+            let known: HashMap<_, _> = models::Track::get_all(&mut conn)
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|t| (PathBuf::from(&t.file_path), t))
+                .collect();
+            (dirs, known)
+        };
+
+        let scan_dirs: Vec<_> = dirs
+            .iter()
+            .map(|d| PathBuf::from(&d.path))
+            .filter(|p| match p.is_dir() {
+                true => true,
+                false => {
+                    error!("watched dir missing, skipping {:?}", p);
+                    false
+                }
+            })
+            .collect();
+
+        let (tx, mut rx) = tokio_mpsc::unbounded_channel();
+        let stats: HashMap<_, _> = known
+            .iter()
+            .map(|(path, t)| (path.clone(), (t.file_size, t.file_mtime)))
+            .collect();
+        let thread_dirs = scan_dirs.clone();
+        let scan_thread = std::thread::spawn(move || {
+            let mut seen = HashSet::new();
+            for dir in thread_dirs.iter() {
+                debug!("rescanning {:?}", dir);
+                rescan_dir(&tx, dir, &stats, &mut seen);
+            }
+            seen
+        });
+
+        let mut report = RescanReport::default();
+        let mut import_hashes = HashSet::new();
+        let release_match = self.config.release_match();
+        let artist_match = self.config.artist_match();
+        while let Some(msg) = rx.recv().await {
+            let mut conn = self.db_pool.acquire().await.unwrap();
+            match known.get(&msg.path) {
+                Some(track) => {
+                    debug!("refreshing changed track {:?}", msg.path);
+                    models::Track::update_from_parse(&mut conn, track.id, &msg)
+                        .await
+                        .unwrap();
+                    report.updated.push(track.id);
+                }
+                None => {
+                    if utf8_path(&msg.path).is_none() {
+                        continue;
+                    }
+                    let duplicate = self
+                        .is_duplicate(&mut conn, &msg, &mut import_hashes)
+                        .await;
+                    if duplicate {
+                        info!("skipping duplicate track {:?}", msg.path);
+                        continue;
+                    }
+                    report.added.push(
+                        models::import_from_parse_result(
+                            conn,
+                            msg,
+                            release_match,
+                            artist_match,
+                        )
+                        .await,
+                    );
+                }
+            }
+        }
+
+        let seen = scan_thread.join().unwrap();
+        let mut conn = self.db_pool.acquire().await.unwrap();
+        for (path, track) in known.into_iter() {
+            let watched = scan_dirs.iter().any(|d| path.starts_with(d));
+            let found = seen.contains(&path);
+            if watched && found != track.available {
+                models::Track::set_available(&mut conn, track.id, found)
+                    .await
+                    .unwrap();
+            }
+            if watched && !found {
+                report.removed.push(track);
+            }
+        }
+
+        for dir in dirs.iter() {
+            models::WatchedDir::set_scanned(&mut conn, dir.id)
+                .await
+                .unwrap();
+        }
+
+        self.notify(LibraryChange::TracksAdded(
+            report.added.iter().map(|t| t.id).collect(),
+        ));
+        self.notify(LibraryChange::TracksUpdated(report.updated.clone()));
+        self.notify(LibraryChange::TracksRemoved(
+            report.removed.iter().map(|t| t.id).collect(),
+        ));
+        report
+    }
+
+    // the copy is removed if it fails, or if verification is on and the
+    // copied audio doesn't hash the same as the source
+    async fn copy_and_import(
+        &self,
+        msg: parse::ParseResult,
+        track_path: PathBuf,
+        release_match: models::ReleaseMatch,
+        artist_match: models::ArtistMatch,
+    ) -> Option<models::DetailedTrack> {
+        let copied = async_fs::copy(&msg.path, &track_path).await;
+        let verified = match (copied, &msg.audio_hash) {
+            (Err(e), _) => {
+                error!("failed to copy track {:?} {:?}", msg.path, e);
+                false
+            }
+            (Ok(_), Some(expected)) if self.config.verify_copies() => {
+                let (tx, rx) = oneshot::channel();
+                let copy_path = track_path.clone();
+                std::thread::spawn(move || {
+                    let copy_hash = parse::detect_format(&copy_path)
+                        .and_then(|f| hash::audio_hash(&copy_path, f).ok());
+                    tx.send(copy_hash).unwrap();
+                });
+
+                let matches = rx.await.unwrap().as_ref() == Some(expected);
+                if !matches {
+                    error!("copy doesn't match source {:?}", msg.path);
+                }
+                matches
+            }
+            (Ok(_), _) => true,
+        };
+
+        if !verified {
+            if let Err(e) = async_fs::remove_file(&track_path).await {
+                debug!("no copy to remove {:?} {:?}", track_path, e);
+            }
+            return None;
+        }
+
+        debug!("getting lock");
+        let conn = match self.db_pool.acquire().await {
+            Ok(c) => c,
+            Err(e) => panic!("failed to acquire conn {:?}", e),
+        };
+        let mut msg = msg;
+        // the copy has its own mtime, which rescans compare against
+        if let Some((size, mtime)) = parse::file_stat(&track_path) {
+            msg.file_size = Some(size);
+            msg.file_mtime = Some(mtime);
+        }
+        // update path to show import location
+        msg.path = track_path;
+        debug!("importing to db {:?}", msg);
+        Some(
+            models::import_from_parse_result(
+                conn,
+                msg,
+                release_match,
+                artist_match,
+            )
+            .await,
+        )
+    }
+
+    /// checks every track's file is still there, updating their `available`
+    /// flag. returns the missing tracks
+    pub async fn scan_missing(&self) -> Vec<models::Track> {
+        let mut conn = self.db_pool.acquire().await.unwrap();
+        let tracks = models::Track::get_all(&mut conn).await.unwrap();
+
+        let mut missing = Vec::new();
+        for mut track in tracks {
+            let available = Path::new(&track.file_path).is_file();
+            if available != track.available {
+                models::Track::set_available(&mut conn, track.id, available)
+                    .await
+                    .unwrap();
+                track.available = available;
+            }
+            if !available {
+                missing.push(track);
+            }
+        }
+
+        missing
+    }
+
+    /// searches `search_dirs` for the files of unavailable tracks, see
+    /// `relink::find_moved`, and updates the tracks found. returns their new
+    /// paths
+    pub async fn relink_missing(
+        &self,
+        search_dirs: Vec<PathBuf>,
+    ) -> Vec<(i64, PathBuf)> {
+        let mut conn = self.db_pool.acquire().await.unwrap();
+        let missing = models::Track::get_unavailable(&mut conn).await.unwrap();
+        if missing.is_empty() {
+            return Vec::new();
+        }
+        let known: HashSet<_> = models::Track::get_all(&mut conn)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|t| PathBuf::from(t.file_path))
+            .collect();
+
+        let (tx, rx) = oneshot::channel();
+        std::thread::spawn(move || {
+            let moved = relink::find_moved(&missing, &search_dirs, &known);
+            tx.send(moved).unwrap();
+        });
+        let moved = rx.await.unwrap();
+
+        let updates: Vec<_> = moved
+            .iter()
+            .filter_map(|(id, path)| Some((*id, utf8_path(path)?.to_owned())))
+            .collect();
+        models::Track::relink(
+            &mut conn,
+            &updates,
+            Some("relink missing tracks"),
+        )
+        .await
+        .unwrap();
+
+        moved
+    }
+
+    /// hashes the audio of tracks imported before hashing existed, on a
+    /// separate thread. returns how many were hashed
+    pub async fn hash_missing(&self) -> usize {
+        let mut conn = self.db_pool.acquire().await.unwrap();
+        let tracks = models::Track::get_unhashed(&mut conn).await.unwrap();
+
+        let (tx, mut rx) = tokio_mpsc::unbounded_channel();
+        let hash_thread = std::thread::spawn(move || {
+            for track in tracks {
+                let path = Path::new(&track.file_path);
+                let hashed = parse::detect_format(path)
+                    .map(|f| hash::audio_hash(path, f));
+                match hashed {
+                    Some(Ok(h)) => tx.send((track.id, h)).unwrap(),
+                    Some(Err(e)) => error!("hashing failed {:?} {:?}", path, e),
+                    None => error!("unknown format, not hashing {:?}", path),
+                }
+            }
+        });
+
+        let mut hashed = 0;
+        while let Some((track_id, audio_hash)) = rx.recv().await {
+            models::Track::set_audio_hash(&mut conn, track_id, &audio_hash)
+                .await
+                .unwrap();
+            hashed += 1;
+        }
+
+        hash_thread.join().unwrap();
+        hashed
     }
 
     pub async fn get_tracklist(&self) -> Vec<models::DetailedTrack> {
@@ -239,19 +908,1031 @@ impl Library {
         models::Track::get_all_detailed(&mut conn).await.unwrap()
     }
 
-    pub async fn play_track(&mut self, track_id: i64) {
+    pub async fn find_tracks(
+        &self,
+        query: &models::TrackQuery,
+    ) -> Vec<models::DetailedTrack> {
+        let mut conn = self.db_pool.acquire().await.unwrap();
+        models::Track::query_detailed(&mut conn, query)
+            .await
+            .unwrap()
+    }
+
+    /// estimates tempo and key and fingerprints every track missing any,
+    /// decoding on a separate thread. tagged values are never replaced, and
+    /// tracks that failed before are skipped until their audio changes.
+    /// returns the new estimates per track
+    pub async fn analyze_missing(&self) -> Vec<(i64, analysis::Analysis)> {
+        let mut conn = self.db_pool.acquire().await.unwrap();
+        let tracks = models::Track::get_unanalyzed(&mut conn).await.unwrap();
+        let preferred_bpm = self.config.preferred_bpm_range();
+        let waveform_bands = self.config.waveform_bands();
+
+        let (tx, mut rx) = tokio_mpsc::unbounded_channel();
+        let analysis_thread = std::thread::spawn(move || {
+            for track in tracks {
+                let path = PathBuf::from(&track.file_path);
+                if !path.is_file() {
+                    error!("missing file, not analyzing {:?}", path);
+                    continue;
+                }
+
+                debug!("analyzing {:?}", path);
+                let (tempo, key) = (track.bpm.is_none(), track.key.is_none());
+                // a bad file shouldn't stop the rest from being analyzed
+                let analyzed = std::panic::catch_unwind(|| {
+                    analysis::analyze(
+                        path,
+                        tempo,
+                        key,
+                        track.fingerprint.is_none(),
+                        preferred_bpm,
+                        waveform_bands,
+                    )
+                });
+                let found = match analyzed {
+                    Ok(Some(a)) => a,
+                    Ok(None) => {
+                        error!("analysis failed {:?}", track);
+                        tx.send((track.id, None, true)).unwrap();
+                        continue;
+                    }
+                    Err(_) => {
+                        error!("analysis panicked {:?}", track);
+                        tx.send((track.id, None, true)).unwrap();
+                        continue;
+                    }
+                };
+                // decoding again would give the same result
+                let failed = (tempo && found.tempo.is_none())
+                    || (key && found.key.is_none());
+                tx.send((track.id, Some(found), failed)).unwrap();
+            }
+        });
+
+        let mut analyzed = Vec::new();
+        while let Some((track_id, found, failed)) = rx.recv().await {
+            if failed {
+                models::Track::set_analysis_failed(&mut conn, track_id)
+                    .await
+                    .unwrap();
+            }
+            let mut found = match found {
+                Some(found) => found,
+                None => continue,
+            };
+
+            // already decoded, so the waveform comes for free. it isn't
+            // returned to avoid holding every one in memory
+            if let Some(w) = found.waveform.take() {
+                if let Err(e) = w.write_to(&self.waveform_path(track_id)) {
+                    error!("failed to cache waveform {:?} {:?}", track_id, e);
+                }
+            }
+
+            let mut stored = analysis::Analysis::default();
+            if let Some(print) = found.fingerprint {
+                models::Track::set_fingerprint(
+                    &mut conn,
+                    track_id,
+                    &print.to_bytes(),
+                )
+                .await
+                .unwrap();
+                stored.fingerprint = Some(print);
+            }
+            if let Some(tempo) = found.tempo {
+                let updated = models::Track::set_detected_bpm(
+                    &mut conn,
+                    track_id,
+                    tempo.bpm,
+                    tempo.confidence,
+                )
+                .await
+                .unwrap();
+                if updated {
+                    stored.tempo = Some(tempo);
+                }
+            }
+            if let Some(key) = found.key {
+                let updated = models::Track::set_detected_key(
+                    &mut conn,
+                    track_id,
+                    key.key,
+                    key.confidence,
+                )
+                .await
+                .unwrap();
+                if updated {
+                    stored.key = Some(key);
+                }
+            }
+            analyzed.push((track_id, stored));
+        }
+
+        if analysis_thread.join().is_err() {
+            error!("analysis thread panicked");
+        }
+
+        analyzed
+    }
+
+    fn waveform_path(&self, track_id: i64) -> PathBuf {
+        self.waveform_dir.join(format!("{}.wf", track_id))
+    }
+
+    /// cached waveform for a track, decoding it if there isn't one or the
+    /// file changed since. None if the track can't be decoded
+    pub async fn get_waveform(
+        &self,
+        track_id: i64,
+    ) -> Option<waveform::Waveform> {
+        let mut conn = self.db_pool.acquire().await.unwrap();
+        let track = models::Track::get(&mut conn, track_id).await.unwrap();
+        let track_path = PathBuf::from(track.file_path);
+        let cache_path = self.waveform_path(track_id);
+
+        let modified = |p: &Path| fs::metadata(p).and_then(|m| m.modified());
+        let is_fresh = match (modified(&cache_path), modified(&track_path)) {
+            (Ok(cached), Ok(track)) => cached >= track,
+            _ => false,
+        };
+        if is_fresh {
+            match waveform::Waveform::read_from(&cache_path) {
+                Ok(w) => return Some(w),
+                Err(e) => error!("bad waveform cache {:?} {:?}", cache_path, e),
+            }
+        }
+
+        debug!("generating waveform {:?}", track_path);
+        let bands = self.config.waveform_bands();
+        let (tx, rx) = oneshot::channel();
+        std::thread::spawn(move || {
+            let waveform = analysis::decode_mono(track_path, bands);
+            tx.send(waveform.map(|(_signal, w)| w)).unwrap();
+        });
+
+        // the decoder panicked if the sender was dropped
+        let waveform = rx.await.ok().flatten()?;
+        if let Err(e) = waveform.write_to(&cache_path) {
+            error!("failed to cache waveform {:?} {:?}", track_id, e);
+        }
+
+        Some(waveform)
+    }
+
+    /// `width` (min, max) pairs in -1 - 1 covering the whole track, e.g. one
+    /// per pixel. empty if the track can't be decoded
+    pub async fn waveform(
+        &self,
+        track_id: i64,
+        width: usize,
+    ) -> Vec<(f32, f32)> {
+        match self.get_waveform(track_id).await {
+            Some(w) => w.peaks(width),
+            None => Vec::new(),
+        }
+    }
+
+    /// groups of tracks that sound like the same recording, e.g. the same
+    /// song imported as flac + mp3. only tracks fingerprinted by
+    /// `analyze_missing` are considered. `min_score` 0.9 is a good start
+    pub async fn find_duplicates(
+        &self,
+        min_score: f64,
+    ) -> Vec<fingerprint::DuplicateGroup> {
+        let mut conn = self.db_pool.acquire().await.unwrap();
+        let prints: Vec<_> = models::Track::get_fingerprints(&mut conn)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|(id, bytes)| {
+                (id, fingerprint::Fingerprint::from_bytes(&bytes))
+            })
+            .filter(|(_, print)| !print.is_empty())
+            .collect();
+
+        fingerprint::group_duplicates(&prints, min_score)
+    }
+
+    /// merges duplicate tracks into the one with the best quality file,
+    /// see `models::Track::merge`. the other files are left on disk. can't
+    /// be undone. returns the id of the track kept, None for fewer than two
+    /// tracks
+    pub async fn merge_duplicates(&self, track_ids: &[i64]) -> Option<i64> {
+        let mut conn = self.db_pool.acquire().await.unwrap();
+        let kept = models::Track::merge(&mut conn, track_ids).await.unwrap()?;
+
+        for id in track_ids.iter().filter(|id| **id != kept) {
+            let cache_path = self.waveform_path(*id);
+            if cache_path.exists() {
+                if let Err(e) = fs::remove_file(&cache_path) {
+                    error!(
+                        "failed to remove waveform {:?} {:?}",
+                        cache_path, e
+                    );
+                }
+            }
+        }
+
+        Some(kept)
+    }
+
+    /// tracks that mix harmonically with `track_id`, within `bpm_range` BPM
+    /// of it. empty if the track has no key or BPM
+    pub async fn find_harmonic_matches(
+        &self,
+        track_id: i64,
+        bpm_range: f64,
+    ) -> Vec<models::DetailedTrack> {
+        let mut conn = self.db_pool.acquire().await.unwrap();
+        let track = models::Track::get(&mut conn, track_id).await.unwrap();
+        let key = track.key.as_deref().and_then(key::MusicalKey::from_camelot);
+        match (key, track.bpm) {
+            (Some(key), Some(bpm)) => models::Track::get_harmonic_matches(
+                &mut conn,
+                key,
+                bpm - bpm_range,
+                bpm + bpm_range,
+            )
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|t| t.id != track_id)
+            .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// applies a user's edit to a track, see `models::Track::update`, and
+    /// writes it to the file's tags if enabled
+    pub async fn update_track(
+        &self,
+        track_id: i64,
+        edit: &models::TrackEdit,
+    ) -> models::DetailedTrack {
+        let mut conn = self.db_pool.acquire().await.unwrap();
+        models::Track::update(&mut conn, track_id, edit)
+            .await
+            .unwrap();
+        drop(conn);
+
+        self.write_back(vec![track_id]).await;
+        self.notify(LibraryChange::TracksUpdated(vec![track_id]));
+        self.get_detailed(vec![track_id]).await.pop().unwrap()
+    }
+
+    pub async fn update_release(
+        &self,
+        release_id: i64,
+        edit: &models::ReleaseEdit,
+    ) -> models::Release {
+        let mut conn = self.db_pool.acquire().await.unwrap();
+        let release = models::Release::update(&mut conn, release_id, edit)
+            .await
+            .unwrap();
+        let track_ids: Vec<_> =
+            models::Track::get_release_tracks(&mut conn, release_id)
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|t| t.id)
+                .collect();
+        drop(conn);
+
+        self.write_back(track_ids.clone()).await;
+        self.notify(LibraryChange::TracksUpdated(track_ids));
+        release
+    }
+
+    /// what `apply_batch_edit` would change, for tracks it changes at all
+    pub async fn preview_batch_edit(
+        &self,
+        track_ids: Vec<i64>,
+        edit: &batch::BatchEdit,
+    ) -> Vec<batch::TrackDiff> {
+        self.get_detailed(track_ids)
+            .await
+            .iter()
+            .map(|t| edit.apply(t))
+            .filter(|diff| !diff.changes.is_empty())
+            .collect()
+    }
+
+    /// applies `edit` to every track in one transaction, then writes tags
+    /// back if enabled. returns what changed, see `preview_batch_edit`
+    pub async fn apply_batch_edit(
+        &self,
+        track_ids: Vec<i64>,
+        edit: &batch::BatchEdit,
+    ) -> Vec<batch::TrackDiff> {
+        let diffs = self.preview_batch_edit(track_ids, edit).await;
+        let edits: Vec<_> =
+            diffs.iter().map(|d| (d.track_id, d.edit.clone())).collect();
+        let changed: Vec<_> = edits.iter().map(|(id, _)| *id).collect();
+
+        let mut conn = self.db_pool.acquire().await.unwrap();
+        models::Track::update_many(&mut conn, &edits, "batch edit")
+            .await
+            .unwrap();
+        drop(conn);
+
+        self.write_back(changed.clone()).await;
+        self.notify(LibraryChange::TracksUpdated(changed));
+        diffs
+    }
+
+    /// None if another artist already has the name, see `merge_artists`
+    pub async fn rename_artist(
+        &self,
+        artist_id: i64,
+        name: &str,
+    ) -> Option<models::Artist> {
+        let mut conn = self.db_pool.acquire().await.unwrap();
+        if let Ok(other) = models::Artist::get_by_name(&mut conn, name).await {
+            if other.id != artist_id {
+                return None;
+            }
+        }
+        let artist = models::Artist::rename(&mut conn, artist_id, name)
+            .await
+            .unwrap();
+        let track_ids = models::Artist::get_track_ids(&mut conn, artist_id)
+            .await
+            .unwrap();
+        drop(conn);
+
+        self.write_back(track_ids.clone()).await;
+        self.notify(LibraryChange::TracksUpdated(track_ids));
+        Some(artist)
+    }
+
+    /// reverts the latest action in the edit history, see
+    /// `models::EditGroup::replay`. None if there's nothing to undo
+    pub async fn undo(&self) -> Option<models::EditGroup> {
+        let mut conn = self.db_pool.acquire().await.unwrap();
+        let group =
+            models::EditGroup::get_undoable(&mut conn).await.unwrap()?;
+        let changes = models::EditGroup::replay(&mut conn, group.id, true)
+            .await
+            .unwrap();
+        drop(conn);
+
+        self.edits_replayed(&changes).await;
+        Some(group)
+    }
+
+    /// reapplies the earliest undone action. None if there's nothing to redo
+    pub async fn redo(&self) -> Option<models::EditGroup> {
+        let mut conn = self.db_pool.acquire().await.unwrap();
+        let group =
+            models::EditGroup::get_redoable(&mut conn).await.unwrap()?;
+        let changes = models::EditGroup::replay(&mut conn, group.id, false)
+            .await
+            .unwrap();
+        drop(conn);
+
+        self.edits_replayed(&changes).await;
+        Some(group)
+    }
+
+    /// the latest `limit` actions, newest first
+    pub async fn get_edit_history(
+        &self,
+        limit: i64,
+    ) -> Vec<models::EditAction> {
+        let mut conn = self.db_pool.acquire().await.unwrap();
+        models::EditGroup::get_history(&mut conn, limit)
+            .await
+            .unwrap()
+    }
+
+    // tracks affected by undone / redone changes get their tags rewritten
+    // like any other edit. library tags aren't written to files, so tracks
+    // only affected by those are just notified
+    async fn edits_replayed(&self, changes: &[models::EditLogEntry]) {
+        let mut conn = self.db_pool.acquire().await.unwrap();
+        let mut track_ids = HashSet::new();
+        let mut tagged_ids = HashSet::new();
+        for change in changes {
+            let id = change.entity_id;
+            match change.entity.as_str() {
+                "track" => {
+                    track_ids.insert(id);
+                }
+                "release" => {
+                    let tracks =
+                        models::Track::get_release_tracks(&mut conn, id)
+                            .await
+                            .unwrap();
+                    track_ids.extend(tracks.into_iter().map(|t| t.id));
+                }
+                "artist" => {
+                    let ids = models::Artist::get_track_ids(&mut conn, id)
+                        .await
+                        .unwrap();
+                    track_ids.extend(ids);
+                }
+                "tag" => {
+                    let ids = models::Tag::get_track_ids(&mut conn, id)
+                        .await
+                        .unwrap();
+                    tagged_ids.extend(ids);
+                    // including tracks the tag was taken off
+                    if change.field == "tracks" {
+                        let logged = change.before.iter().chain(&change.after);
+                        tagged_ids.extend(
+                            logged
+                                .flat_map(|v| v.lines())
+                                .filter_map(|l| l.parse::<i64>().ok()),
+                        );
+                    }
+                }
+                _ => (),
+            }
+        }
+        drop(conn);
+
+        let track_ids: Vec<_> = track_ids.into_iter().collect();
+        self.write_back(track_ids.clone()).await;
+        tagged_ids.extend(track_ids);
+        let tagged_ids: Vec<_> = tagged_ids.into_iter().collect();
+        self.notify(LibraryChange::TracksUpdated(tagged_ids));
+    }
+
+    async fn get_detailed(&self, ids: Vec<i64>) -> Vec<models::DetailedTrack> {
+        self.find_tracks(&models::TrackQuery {
+            ids: Some(ids),
+            ..models::TrackQuery::default()
+        })
+        .await
+    }
+
+    // rewrites the tags of edited tracks' files if enabled, on a separate
+    // thread. the new size + mtime are stored so the change isn't picked up
+    // as an outside edit. failures are only logged, the edit stands either
+    // way
+    async fn write_back(&self, track_ids: Vec<i64>) {
+        if !self.config.write_tags() || track_ids.is_empty() {
+            return;
+        }
+
+        let tracks: Vec<_> = self
+            .get_detailed(track_ids)
+            .await
+            .into_iter()
+            .filter(|t| t.available)
+            .map(|t| {
+                (
+                    t.id,
+                    PathBuf::from(&t.file_path),
+                    tagwrite::TagValues::from(&t),
+                )
+            })
+            .collect();
+
+        let (tx, rx) = oneshot::channel();
+        std::thread::spawn(move || {
+            let mut written = Vec::new();
+            for (id, path, values) in tracks {
+                match tagwrite::write_tags(&path, &values) {
+                    Ok(()) => match parse::file_stat(&path) {
+                        Some((size, mtime)) => written.push((id, size, mtime)),
+                        None => error!("failed to stat {:?}", path),
+                    },
+                    Err(e) => error!("tag write failed {:?} {:?}", path, e),
+                }
+            }
+            tx.send(written).unwrap();
+        });
+
+        let mut conn = self.db_pool.acquire().await.unwrap();
+        for (id, size, mtime) in rx.await.unwrap() {
+            models::Track::set_file_stat(&mut conn, id, size as i64, mtime)
+                .await
+                .unwrap();
+        }
+    }
+
+    pub async fn get_genres(&self) -> Vec<models::Genre> {
+        let mut conn = self.db_pool.acquire().await.unwrap();
+        models::Genre::get_all(&mut conn).await.unwrap()
+    }
+
+    /// by sort name
+    pub async fn get_artists(&self) -> Vec<models::Artist> {
+        let mut conn = self.db_pool.acquire().await.unwrap();
+        models::Artist::get_all(&mut conn).await.unwrap()
+    }
+
+    /// credits `keep_id` everywhere `merge_id` was. the merged name becomes
+    /// an alias. None if they're the same artist
+    pub async fn merge_artists(
+        &self,
+        keep_id: i64,
+        merge_id: i64,
+    ) -> Option<models::Artist> {
+        let mut conn = self.db_pool.acquire().await.unwrap();
+        let track_ids = models::Artist::get_track_ids(&mut conn, merge_id)
+            .await
+            .unwrap();
+        let artist = models::Artist::merge(&mut conn, keep_id, merge_id)
+            .await
+            .unwrap()?;
+        drop(conn);
+
+        self.write_back(track_ids.clone()).await;
+        self.notify(LibraryChange::TracksUpdated(track_ids));
+        Some(artist)
+    }
+
+    /// tracklists are ordered by it, so its tracks are updated
+    pub async fn set_artist_sort_name(
+        &self,
+        artist_id: i64,
+        sort_name: Option<&str>,
+    ) {
+        let mut conn = self.db_pool.acquire().await.unwrap();
+        models::Artist::set_sort_name(&mut conn, artist_id, sort_name)
+            .await
+            .unwrap();
+        let track_ids = models::Artist::get_track_ids(&mut conn, artist_id)
+            .await
+            .unwrap();
+        drop(conn);
+
+        self.notify(LibraryChange::TracksUpdated(track_ids));
+    }
+
+    pub async fn get_artist_aliases(
+        &self,
+        artist_id: i64,
+    ) -> Vec<models::ArtistAlias> {
+        let mut conn = self.db_pool.acquire().await.unwrap();
+        models::ArtistAlias::get_artist_aliases(&mut conn, artist_id)
+            .await
+            .unwrap()
+    }
+
+    /// imported tracks tagged with `name` are credited to the artist. None
+    /// if it's already an alias
+    pub async fn add_artist_alias(
+        &self,
+        artist_id: i64,
+        name: &str,
+    ) -> Option<i64> {
+        let mut conn = self.db_pool.acquire().await.unwrap();
+        models::ArtistAlias::create(&mut conn, artist_id, name)
+            .await
+            .unwrap()
+    }
+
+    pub async fn remove_artist_alias(&self, alias_id: i64) {
+        let mut conn = self.db_pool.acquire().await.unwrap();
+        models::ArtistAlias::delete(&mut conn, alias_id)
+            .await
+            .unwrap()
+    }
+
+    /// false, leaving the rating as it was, outside 0 - 5
+    pub async fn set_rating(&self, track_id: i64, rating: Option<f64>) -> bool {
+        let mut conn = self.db_pool.acquire().await.unwrap();
+        let rated = models::Track::set_rating(&mut conn, track_id, rating)
+            .await
+            .unwrap();
+        drop(conn);
+        if !rated {
+            return false;
+        }
+
+        self.notify(LibraryChange::TracksUpdated(vec![track_id]));
+        true
+    }
+
+    /// plays + skips, most recent first
+    pub async fn get_listening_history(&self, limit: i64) -> Vec<models::Play> {
+        let mut conn = self.db_pool.acquire().await.unwrap();
+        models::Play::get_history(&mut conn, limit).await.unwrap()
+    }
+
+    /// tracks + play counts, counting plays at or after `since` if given,
+    /// e.g. "2021-03-01" for this month
+    pub async fn get_most_played(
+        &self,
+        since: Option<&str>,
+        limit: i64,
+    ) -> Vec<(models::DetailedTrack, i64)> {
+        let mut conn = self.db_pool.acquire().await.unwrap();
+        let counts = models::Play::get_most_played(&mut conn, since, limit)
+            .await
+            .unwrap();
+        drop(conn);
+
+        let ids = counts.iter().map(|(id, _)| *id).collect();
+        let mut tracks: HashMap<i64, models::DetailedTrack> = self
+            .get_detailed(ids)
+            .await
+            .into_iter()
+            .map(|t| (t.id, t))
+            .collect();
+        counts
+            .into_iter()
+            .filter_map(|(id, count)| tracks.remove(&id).map(|t| (t, count)))
+            .collect()
+    }
+
+    /// writes every play + skip to `path` as a .scrobbler.log, see
+    /// `scrobble::write_scrobbler_log`. returns how many
+    pub async fn export_scrobbler_log(
+        &self,
+        path: &Path,
+    ) -> std::io::Result<usize> {
+        let scrobbles = self.get_scrobbles().await;
+        let mut out = std::io::BufWriter::new(fs::File::create(path)?);
+        let client = format!("librarian {}", env!("CARGO_PKG_VERSION"));
+        scrobble::write_scrobbler_log(&mut out, &scrobbles, &client)?;
+        std::io::Write::flush(&mut out)?;
+        Ok(scrobbles.len())
+    }
+
+    /// writes every play + skip to `path` as JSON lines, see
+    /// `scrobble::write_json_lines`. returns how many
+    pub async fn export_listening_history(
+        &self,
+        path: &Path,
+    ) -> std::io::Result<usize> {
+        let scrobbles = self.get_scrobbles().await;
+        let mut out = std::io::BufWriter::new(fs::File::create(path)?);
+        scrobble::write_json_lines(&mut out, &scrobbles)?;
+        std::io::Write::flush(&mut out)?;
+        Ok(scrobbles.len())
+    }
+
+    /// nothing is imported if the file can't be read
+    pub async fn import_scrobbler_log(
+        &self,
+        path: &Path,
+    ) -> std::io::Result<ScrobbleImport> {
+        let file = std::io::BufReader::new(fs::File::open(path)?);
+        let scrobbles = scrobble::read_scrobbler_log(file)?;
+        Ok(self.import_scrobbles(scrobbles).await)
+    }
+
+    /// nothing is imported if the file can't be read or a line isn't a
+    /// `scrobble::Scrobble`
+    pub async fn import_listening_history(
+        &self,
+        path: &Path,
+    ) -> std::io::Result<ScrobbleImport> {
+        let file = std::io::BufReader::new(fs::File::open(path)?);
+        let scrobbles = scrobble::read_json_lines(file)?;
+        Ok(self.import_scrobbles(scrobbles).await)
+    }
+
+    async fn get_scrobbles(&self) -> Vec<scrobble::Scrobble> {
+        let mut conn = self.db_pool.acquire().await.unwrap();
+        models::Play::get_all_credited(&mut conn)
+            .await
+            .unwrap()
+            .into_iter()
+            .filter_map(|p| {
+                Some(scrobble::Scrobble {
+                    timestamp: scrobble::Scrobble::timestamp_from(
+                        &p.play.played_at,
+                    )?,
+                    artist: p.artist,
+                    album: Some(p.album).filter(|a| !a.is_empty()),
+                    title: p.title,
+                    track_num: p.track_num,
+                    listened: p.play.listened,
+                    skipped: p.play.skipped,
+                    length: p.play.length,
+                })
+            })
+            .collect()
+    }
+
+    // matched to tracks by credit. listens already recorded for the same
+    // track + time are skipped, so importing the same file twice is harmless
+    async fn import_scrobbles(
+        &self,
+        scrobbles: Vec<scrobble::Scrobble>,
+    ) -> ScrobbleImport {
+        let mut report = ScrobbleImport::default();
+        let mut track_ids = HashSet::new();
+        let mut conn = self.db_pool.acquire().await.unwrap();
+        for s in scrobbles {
+            let track_id = models::Track::find_by_credit(
+                &mut conn,
+                &s.artist,
+                &s.title,
+                s.album.as_deref(),
+            )
+            .await
+            .unwrap();
+            let track_id = match track_id {
+                Some(id) => id,
+                None => {
+                    report.unmatched.push(s);
+                    continue;
+                }
+            };
+
+            let played_at = match s.played_at() {
+                Some(played_at) => played_at,
+                None => {
+                    report.unmatched.push(s);
+                    continue;
+                }
+            };
+            if models::Play::exists(&mut conn, track_id, &played_at)
+                .await
+                .unwrap()
+            {
+                report.duplicates += 1;
+                continue;
+            }
+            models::Play::create(
+                &mut conn, track_id, &played_at, s.listened, s.skipped,
+                s.length,
+            )
+            .await
+            .unwrap();
+            report.imported += 1;
+            track_ids.insert(track_id);
+        }
+        drop(conn);
+
+        if !track_ids.is_empty() {
+            let updated = track_ids.into_iter().collect();
+            self.notify(LibraryChange::TracksUpdated(updated));
+        }
+        report
+    }
+
+    /// every tag with its track count
+    pub async fn get_tags(&self) -> Vec<models::TagUsage> {
+        let mut conn = self.db_pool.acquire().await.unwrap();
+        models::Tag::get_all_usage(&mut conn).await.unwrap()
+    }
+
+    /// the existing tag if one has the name, ignoring case
+    pub async fn create_tag(&self, name: &str) -> models::Tag {
+        let mut conn = self.db_pool.acquire().await.unwrap();
+        let tag_id =
+            models::Tag::find_or_create(&mut conn, name).await.unwrap();
+        models::Tag::get(&mut conn, tag_id).await.unwrap()
+    }
+
+    /// None if another tag already has the name, see `merge_tags`
+    pub async fn rename_tag(
+        &self,
+        tag_id: i64,
+        name: &str,
+    ) -> Option<models::Tag> {
+        let mut conn = self.db_pool.acquire().await.unwrap();
+        let other = models::Tag::get_by_name(&mut conn, name).await.unwrap();
+        if other.map_or(false, |t| t.id != tag_id) {
+            return None;
+        }
+        let tag = models::Tag::rename(&mut conn, tag_id, name).await.unwrap();
+        let track_ids =
+            models::Tag::get_track_ids(&mut conn, tag_id).await.unwrap();
+        drop(conn);
+
+        self.notify(LibraryChange::TracksUpdated(track_ids));
+        Some(tag)
+    }
+
+    /// tags every track of `merge_id` with `keep_id` instead. None if
+    /// they're the same tag
+    pub async fn merge_tags(
+        &self,
+        keep_id: i64,
+        merge_id: i64,
+    ) -> Option<models::Tag> {
+        let mut conn = self.db_pool.acquire().await.unwrap();
+        let track_ids = models::Tag::get_track_ids(&mut conn, merge_id)
+            .await
+            .unwrap();
+        let tag = models::Tag::merge(&mut conn, keep_id, merge_id)
+            .await
+            .unwrap()?;
+        drop(conn);
+
+        self.notify(LibraryChange::TracksUpdated(track_ids));
+        Some(tag)
+    }
+
+    /// returns how many tracks were untagged
+    pub async fn delete_tag(&self, tag_id: i64) -> i64 {
+        let mut conn = self.db_pool.acquire().await.unwrap();
+        let track_ids =
+            models::Tag::get_track_ids(&mut conn, tag_id).await.unwrap();
+        let untagged = models::Tag::delete(&mut conn, tag_id).await.unwrap();
+        drop(conn);
+
+        self.notify(LibraryChange::TracksUpdated(track_ids));
+        untagged
+    }
+
+    /// false if it would nest the tag under itself
+    pub async fn set_tag_parent(
+        &self,
+        tag_id: i64,
+        parent_id: Option<i64>,
+    ) -> bool {
+        let mut conn = self.db_pool.acquire().await.unwrap();
+        models::Tag::set_parent(&mut conn, tag_id, parent_id)
+            .await
+            .unwrap()
+    }
+
+    pub async fn set_tag_color(&self, tag_id: i64, color: Option<&str>) {
+        let mut conn = self.db_pool.acquire().await.unwrap();
+        models::Tag::set_color(&mut conn, tag_id, color)
+            .await
+            .unwrap()
+    }
+
+    /// adds the tags to every track, creating any that don't exist
+    pub async fn tag_tracks(&self, track_ids: Vec<i64>, tags: &[String]) {
+        let edit = batch::BatchEdit {
+            tags: batch::ListChange {
+                add: tags.to_vec(),
+                remove: vec![],
+            },
+            ..Default::default()
+        };
+        self.edit_tags(track_ids, &edit, "tag tracks").await
+    }
+
+    pub async fn untag_tracks(&self, track_ids: Vec<i64>, tags: &[String]) {
+        let edit = batch::BatchEdit {
+            tags: batch::ListChange {
+                add: vec![],
+                remove: tags.to_vec(),
+            },
+            ..Default::default()
+        };
+        self.edit_tags(track_ids, &edit, "untag tracks").await
+    }
+
+    // journaled like a batch edit, but tags aren't written to files
+    async fn edit_tags(
+        &self,
+        track_ids: Vec<i64>,
+        edit: &batch::BatchEdit,
+        description: &str,
+    ) {
+        let edits: Vec<_> = self
+            .preview_batch_edit(track_ids, edit)
+            .await
+            .into_iter()
+            .map(|d| (d.track_id, d.edit))
+            .collect();
+        if edits.is_empty() {
+            return;
+        }
+        let changed: Vec<_> = edits.iter().map(|(id, _)| *id).collect();
+
+        let mut conn = self.db_pool.acquire().await.unwrap();
+        models::Track::update_many(&mut conn, &edits, description)
+            .await
+            .unwrap();
+        drop(conn);
+
+        self.notify(LibraryChange::TracksUpdated(changed));
+    }
+
+    /// moves all tracks of `merge_id` into `keep_id`. None if they're the
+    /// same release
+    pub async fn merge_releases(
+        &self,
+        keep_id: i64,
+        merge_id: i64,
+    ) -> Option<models::Release> {
+        let mut conn = self.db_pool.acquire().await.unwrap();
+        let track_ids = models::Release::get_track_ids(&mut conn, merge_id)
+            .await
+            .unwrap();
+        let release = models::Release::merge(&mut conn, keep_id, merge_id)
+            .await
+            .unwrap()?;
+        drop(conn);
+
+        self.write_back(track_ids.clone()).await;
+        self.notify(LibraryChange::TracksUpdated(track_ids));
+        Some(release)
+    }
+
+    /// moves `track_ids` out of `release_id` into a new release. None if
+    /// there are no tracks or they aren't all on `release_id`
+    pub async fn split_release(
+        &self,
+        release_id: i64,
+        track_ids: &[i64],
+        name: &str,
+        details: &models::ReleaseDetails,
+    ) -> Option<models::Release> {
+        let mut conn = self.db_pool.acquire().await.unwrap();
+        let release = models::Release::split(
+            &mut conn, release_id, track_ids, name, details,
+        )
+        .await
+        .unwrap()?;
+        drop(conn);
+
+        self.write_back(track_ids.to_vec()).await;
+        self.notify(LibraryChange::TracksUpdated(track_ids.to_vec()));
+        Some(release)
+    }
+
+    /// false if the track's file is missing, which also marks it unavailable
+    pub async fn play_track(&mut self, track_id: i64) -> bool {
         let mut conn = self.db_pool.acquire().await.unwrap();
         let track = crate::models::Track::get(&mut conn, track_id)
             .await
             .unwrap();
 
         let track_path = PathBuf::from(track.file_path);
+        if !track_path.is_file() {
+            error!("track file missing {:?}", track_path);
+            models::Track::set_available(&mut conn, track_id, false)
+                .await
+                .unwrap();
+            return false;
+        }
 
-        if self.stream.is_some() {
-            self.stream.as_ref().unwrap().stop();
+        drop(conn);
+        self.stop_stream().await;
+
+        let changes = self.changes.clone();
+        self.stream = Some(AudioStream::from_path(track_path, move || {
+            // only fails without subscribers
+            let _ = changes.send(LibraryChange::StreamFinished(track_id));
+        }));
+        let started = chrono::Utc::now().format("%Y-%m-%d %H:%M:%S");
+        self.now_playing = Some((track_id, started.to_string()));
+        true
+    }
+
+    /// records the play once `track_id`'s stream has played through. does
+    /// nothing if another track has started since
+    pub async fn stream_finished(&mut self, track_id: i64) {
+        let finished = match (&self.stream, &self.now_playing) {
+            (Some(stream), Some((playing, _))) => {
+                *playing == track_id && stream.listened().finished
+            }
+            _ => false,
+        };
+        if finished {
+            self.stop_stream().await;
         }
+    }
+
+    /// records the listen as a play, or a skip if it didn't reach the play
+    /// threshold. call before exiting so the current listen isn't lost
+    pub async fn stop_stream(&mut self) {
+        let stream = match self.stream.take() {
+            Some(s) => s,
+            None => return,
+        };
+        stream.stop();
+
+        let (track_id, started) = match self.now_playing.take() {
+            Some(playing) => playing,
+            None => return,
+        };
+        let listened = stream.listened();
+        // e.g. the file couldn't be decoded
+        if listened.played.as_secs_f64() == 0.0 {
+            return;
+        }
+
+        let (percent, secs) = self.config.play_threshold();
+        let played = listened.played.as_secs_f64();
+        let reached_percent = listened
+            .length
+            .map(|l| played >= l.as_secs_f64() * percent / 100.0)
+            .unwrap_or(false);
+        let counted =
+            listened.finished || played >= secs as f64 || reached_percent;
+        debug!(
+            "listened to {} {:?} counted {}",
+            track_id, listened, counted
+        );
+
+        let mut conn = self.db_pool.acquire().await.unwrap();
+        let length = listened.length.map(|l| l.as_secs_f64());
+        models::Play::create(
+            &mut conn, track_id, &started, played, !counted, length,
+        )
+        .await
+        .unwrap();
+        drop(conn);
 
-        self.stream = Some(AudioStream::from_path(track_path));
+        self.notify(LibraryChange::TracksUpdated(vec![track_id]));
     }
 
     pub fn play_stream(&self) {
@@ -1,10 +1,14 @@
 // use chrono::{DateTime, Utc};
+use super::key::MusicalKey;
 use super::parse;
 use core::borrow::BorrowMut;
-use sqlx::{pool::PoolConnection, sqlite::Sqlite};
+use serde_derive::{Deserialize, Serialize};
+use sqlx::{pool::PoolConnection, sqlite::Sqlite, Connection};
 use std::collections::HashMap;
+use unicode_normalization::UnicodeNormalization;
 
 pub type SqlitePoolConn = PoolConnection<Sqlite>;
+pub type SqliteTx<'c> = sqlx::Transaction<'c, Sqlite>;
 pub type RowId = i64;
 // type Timestamptz = DateTime<Utc>; // TODO figure out string conversion
 
@@ -16,10 +20,52 @@ const SQLITE_UNIQUE_VIOLATION: &'static str = "2067";
 // separate higher level composed fns from base layer?
 // TODO don't look up created tracks, just return last_insert_rowid
 
+/// how names on imported tracks are matched to existing artists + aliases
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArtistMatch {
+    /// the name exactly as tagged
+    Exact,
+    IgnoreCase,
+    /// also ignores diacritics, spacing and a leading or trailing "The"
+    Loose,
+}
+
+// lower cased without diacritics, extra spaces or a leading / trailing "The",
+// so "The Beatles", "Beatles, The" and "Beatles" are the same. what
+// `ArtistMatch::Loose` compares
+fn fold_artist_name(name: &str) -> String {
+    let folded: String = name
+        .nfd()
+        .filter(|c| !unicode_normalization::char::is_combining_mark(*c))
+        .flat_map(char::to_lowercase)
+        .collect();
+    let folded = folded.split_whitespace().collect::<Vec<_>>().join(" ");
+    match folded
+        .strip_prefix("the ")
+        .or_else(|| folded.strip_suffix(", the"))
+    {
+        Some(rest) => rest.to_owned(),
+        None => folded,
+    }
+}
+
+// "The Beatles" -> "Beatles, The". no sort name if it'd be the same as the name
+fn default_sort_name(name: &str) -> Option<String> {
+    match name.get(..4) {
+        Some(article) if article.eq_ignore_ascii_case("the ") => {
+            Some(format!("{}, {}", name[4..].trim(), name[..3].to_owned()))
+        }
+        _ => None,
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Artist {
     pub id: RowId,
     pub name: String,
+    /// ordered by name if None
+    pub sort_name: Option<String>,
     pub created: String, // TODO parse date
 }
 
@@ -28,28 +74,439 @@ impl Artist {
         conn: &mut SqlitePoolConn,
         name: &str,
     ) -> Result<Self, sqlx::Error> {
-        let id = sqlx::query("INSERT INTO artists (name) VALUES (?);")
-            .bind(name)
-            .execute(conn.borrow_mut())
-            .await?
-            .last_insert_rowid();
+        let id = sqlx::query(
+            "INSERT INTO artists (name, sort_name, match_name)
+            VALUES (?, ?, ?);",
+        )
+        .bind(name)
+        .bind(default_sort_name(name))
+        .bind(fold_artist_name(name))
+        .execute(conn.borrow_mut())
+        .await?
+        .last_insert_rowid();
+
+        Self::get(conn, id).await
+    }
+
+    pub async fn get_by_name(
+        conn: &mut SqlitePoolConn,
+        name: &str,
+    ) -> Result<Self, sqlx::Error> {
+        sqlx::query_as!(
+            Self,
+            "SELECT id, name, sort_name, created FROM artists WHERE name = ?",
+            name
+        )
+        .fetch_one(conn)
+        .await
+    }
+
+    // artists will usually be reused on import -> insert + fallback on the
+    // unique violation avoids a race between the read and the insert
+    pub async fn find_or_create(
+        conn: &mut SqlitePoolConn,
+        name: &str,
+    ) -> Result<Self, sqlx::Error> {
+        match Self::create(conn, name).await {
+            Ok(a) => Ok(a),
+            Err(sqlx::Error::Database(d)) => match d.code() {
+                Some(code) if code == SQLITE_UNIQUE_VIOLATION => {
+                    Self::get_by_name(conn, name).await
+                }
+                _ => Err(sqlx::Error::Database(d)),
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    /// the artist with the name, or an alias of it. exact matches win, then
+    /// the oldest artist
+    pub async fn find_matching(
+        conn: &mut SqlitePoolConn,
+        name: &str,
+        strictness: ArtistMatch,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        let match_name = match strictness {
+            ArtistMatch::Exact => None,
+            _ => Some(fold_artist_name(name)),
+        };
+        let candidates = sqlx::query!(
+            "SELECT
+                artists.id,
+                artists.name,
+                artists.sort_name,
+                artists.created,
+                matched.name AS matched_name,
+                matched.exact AS \"exact!: bool\"
+            FROM (
+                SELECT id AS artist_id, name, name = ? AS exact
+                FROM artists
+                WHERE name = ? OR match_name = ?
+                UNION ALL
+                SELECT artist_id, name, name = ? AS exact
+                FROM artist_aliases
+                WHERE name = ? OR match_name = ?
+            ) matched
+            JOIN artists ON matched.artist_id = artists.id
+            ORDER BY matched.exact DESC, artists.id",
+            name,
+            name,
+            match_name,
+            name,
+            name,
+            match_name
+        )
+        .fetch_all(conn)
+        .await?;
+
+        let lower = name.to_lowercase();
+        Ok(candidates
+            .into_iter()
+            .find(|row| match strictness {
+                ArtistMatch::Exact => row.exact,
+                ArtistMatch::IgnoreCase => {
+                    row.matched_name.to_lowercase() == lower
+                }
+                ArtistMatch::Loose => true,
+            })
+            .map(|row| Artist {
+                id: row.id,
+                name: row.name,
+                sort_name: row.sort_name,
+                created: row.created,
+            }))
+    }
+
+    /// how import finds artists, see `ArtistMatch`
+    pub async fn find_matching_or_create(
+        conn: &mut SqlitePoolConn,
+        name: &str,
+        strictness: ArtistMatch,
+    ) -> Result<Self, sqlx::Error> {
+        match Self::find_matching(conn, name, strictness).await? {
+            Some(a) => Ok(a),
+            None => Self::find_or_create(conn, name).await,
+        }
+    }
 
+    pub async fn get(
+        conn: &mut SqlitePoolConn,
+        id: RowId,
+    ) -> Result<Self, sqlx::Error> {
         sqlx::query_as!(
             Self,
-            "SELECT id, name, created FROM artists WHERE id = ?;",
+            "SELECT id, name, sort_name, created FROM artists WHERE id = ?",
             id
         )
-        .fetch_one(conn.borrow_mut())
+        .fetch_one(conn)
         .await
     }
 
-    pub async fn get_by_name(
+    /// by sort name, or name if there isn't one
+    pub async fn get_all(
+        conn: &mut SqlitePoolConn,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as!(
+            Self,
+            "SELECT id, name, sort_name, created FROM artists
+            ORDER BY COALESCE(sort_name, name) COLLATE NOCASE"
+        )
+        .fetch_all(conn)
+        .await
+    }
+
+    /// artists from before match names were stored. returns how many
+    pub async fn fill_match_names(
         conn: &mut SqlitePoolConn,
+    ) -> Result<usize, sqlx::Error> {
+        let unfilled = sqlx::query!(
+            "SELECT id, name FROM artists WHERE match_name IS NULL"
+        )
+        .fetch_all(conn.borrow_mut())
+        .await?;
+
+        let mut tx = conn.begin().await?;
+        for row in unfilled.iter() {
+            sqlx::query("UPDATE artists SET match_name = ? WHERE id = ?")
+                .bind(fold_artist_name(&row.name))
+                .bind(row.id)
+                .execute(&mut tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(unfilled.len())
+    }
+
+    /// fails with a unique violation if another artist has the name.
+    /// logged for undo
+    pub async fn rename(
+        conn: &mut SqlitePoolConn,
+        id: RowId,
         name: &str,
     ) -> Result<Self, sqlx::Error> {
-        sqlx::query_as!(Self, "SELECT * FROM artists WHERE name = ?", name)
-            .fetch_one(conn)
+        let artist = Self::get(conn, id).await?;
+
+        let mut tx = conn.begin().await?;
+        let group_id = EditGroup::create(&mut tx, "rename artist").await?;
+        Self::set_name(&mut tx, id, name).await?;
+        EditLogEntry::record(
+            &mut tx,
+            group_id,
+            "artist",
+            id,
+            "name",
+            Some(artist.name),
+            Some(name.to_owned()),
+        )
+        .await?;
+        tx.commit().await?;
+
+        Self::get(conn, id).await
+    }
+
+    async fn set_name(
+        tx: &mut SqliteTx<'_>,
+        id: RowId,
+        name: &str,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE artists SET name = ?, match_name = ? WHERE id = ?")
+            .bind(name)
+            .bind(fold_artist_name(name))
+            .bind(id)
+            .execute(&mut *tx)
             .await
+            .map(|_done| ())
+    }
+
+    /// logged for undo
+    pub async fn set_sort_name(
+        conn: &mut SqlitePoolConn,
+        id: RowId,
+        sort_name: Option<&str>,
+    ) -> Result<(), sqlx::Error> {
+        let artist = Self::get(conn, id).await?;
+
+        let mut tx = conn.begin().await?;
+        let group_id = EditGroup::create(&mut tx, "set sort name").await?;
+        sqlx::query("UPDATE artists SET sort_name = ? WHERE id = ?")
+            .bind(sort_name)
+            .bind(id)
+            .execute(&mut tx)
+            .await?;
+        EditLogEntry::record(
+            &mut tx,
+            group_id,
+            "artist",
+            id,
+            "sort_name",
+            artist.sort_name,
+            log_text(sort_name),
+        )
+        .await?;
+        tx.commit().await
+    }
+
+    /// credits `keep_id` wherever `merge_id` was, then deletes it. its name
+    /// becomes an alias, so future imports match `keep_id`. logged for undo.
+    /// None if they're the same artist
+    pub async fn merge(
+        conn: &mut SqlitePoolConn,
+        keep_id: RowId,
+        merge_id: RowId,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        if keep_id == merge_id {
+            return Ok(None);
+        }
+        let keep = Self::get(conn, keep_id).await?;
+        let merge = Self::get(conn, merge_id).await?;
+
+        let mut tx = conn.begin().await?;
+        let group_id = EditGroup::create(&mut tx, "merge artists").await?;
+        let merged_id = |id: RowId| match id == merge_id {
+            true => keep_id,
+            false => id,
+        };
+        let mut changes = Vec::new();
+
+        // credits are logged like track edits, "artist_id:role"
+        let mut credits: Vec<(RowId, Vec<String>, Vec<String>)> = Vec::new();
+        for row in sqlx::query!(
+            "SELECT track_id, artist_id, role FROM track_artists
+            WHERE track_id IN (
+                SELECT track_id FROM track_artists WHERE artist_id = ?
+            )
+            ORDER BY track_id",
+            merge_id
+        )
+        .fetch_all(&mut tx)
+        .await?
+        {
+            if credits.last().map(|c| c.0) != Some(row.track_id) {
+                credits.push((row.track_id, Vec::new(), Vec::new()));
+            }
+            let (_, before, after) = credits.last_mut().unwrap();
+            before.push(format!("{}:{}", row.artist_id, row.role));
+            let merged = format!("{}:{}", merged_id(row.artist_id), row.role);
+            if !after.contains(&merged) {
+                after.push(merged);
+            }
+        }
+        for (track_id, before, after) in credits {
+            changes.push((
+                "track",
+                track_id,
+                "artists",
+                log_list(&before),
+                log_list(&after),
+            ));
+        }
+
+        let mut release_artists: Vec<(RowId, Vec<RowId>, Vec<RowId>)> =
+            Vec::new();
+        for row in sqlx::query!(
+            "SELECT release_id, artist_id FROM artist_releases
+            WHERE release_id IN (
+                SELECT release_id FROM artist_releases WHERE artist_id = ?
+            )
+            ORDER BY release_id",
+            merge_id
+        )
+        .fetch_all(&mut tx)
+        .await?
+        {
+            if release_artists.last().map(|r| r.0) != Some(row.release_id) {
+                release_artists.push((row.release_id, Vec::new(), Vec::new()));
+            }
+            let (_, before, after) = release_artists.last_mut().unwrap();
+            before.push(row.artist_id);
+            if !after.contains(&merged_id(row.artist_id)) {
+                after.push(merged_id(row.artist_id));
+            }
+        }
+        for (release_id, before, after) in release_artists {
+            changes.push((
+                "release",
+                release_id,
+                "artists",
+                log_list(&before),
+                log_list(&after),
+            ));
+        }
+
+        for row in sqlx::query!(
+            "SELECT id FROM releases WHERE album_artist_id = ?",
+            merge_id
+        )
+        .fetch_all(&mut tx)
+        .await?
+        {
+            changes.push((
+                "release",
+                row.id,
+                "album_artist_id",
+                log_text(Some(merge_id)),
+                log_text(Some(keep_id)),
+            ));
+        }
+
+        let aliases = sqlx::query!(
+            "SELECT artist_id, name FROM artist_aliases
+            WHERE artist_id IN (?, ?) OR name = ?
+            ORDER BY id",
+            keep_id,
+            merge_id,
+            merge.name
+        )
+        .fetch_all(&mut tx)
+        .await?;
+        let alias_names = |artist_id: RowId| -> Vec<String> {
+            aliases
+                .iter()
+                .filter(|a| a.artist_id == artist_id)
+                .map(|a| a.name.clone())
+                .collect()
+        };
+        let (keep_aliases, merge_aliases) =
+            (alias_names(keep_id), alias_names(merge_id));
+        let mut merged_aliases = keep_aliases.clone();
+        merged_aliases.extend(merge_aliases.iter().cloned());
+        // unless another artist already has it as an alias
+        if !aliases.iter().any(|a| a.name == merge.name) {
+            merged_aliases.push(merge.name.clone());
+        }
+        changes.push((
+            "artist",
+            keep_id,
+            "aliases",
+            log_list(&keep_aliases),
+            log_list(&merged_aliases),
+        ));
+        changes.push((
+            "artist",
+            merge_id,
+            "aliases",
+            log_list(&merge_aliases),
+            None,
+        ));
+
+        changes.push((
+            "artist",
+            keep_id,
+            "sort_name",
+            keep.sort_name.clone(),
+            keep.sort_name.clone().or_else(|| merge.sort_name.clone()),
+        ));
+        // the row itself goes last, so undo brings it back first
+        changes.push((
+            "artist",
+            merge_id,
+            "sort_name",
+            merge.sort_name.clone(),
+            None,
+        ));
+        changes.push(("artist", merge_id, "row", Some(merge.name), None));
+
+        // applied the same way as a redo
+        for (entity, id, field, before, after) in changes {
+            EditLogEntry::apply_value(
+                &mut tx,
+                entity,
+                id,
+                field,
+                after.as_deref(),
+            )
+            .await?;
+            EditLogEntry::record(
+                &mut tx, group_id, entity, id, field, before, after,
+            )
+            .await?;
+        }
+        tx.commit().await?;
+
+        Self::get(conn, keep_id).await.map(Some)
+    }
+
+    /// tracks crediting the artist, on the track or its release
+    pub async fn get_track_ids(
+        conn: &mut SqlitePoolConn,
+        id: RowId,
+    ) -> Result<Vec<RowId>, sqlx::Error> {
+        Ok(sqlx::query!(
+            "SELECT id FROM tracks
+            WHERE id IN (
+                SELECT track_id FROM track_artists WHERE artist_id = ?
+            )
+            OR release_id IN (
+                SELECT release_id FROM artist_releases WHERE artist_id = ?
+            )",
+            id,
+            id
+        )
+        .fetch_all(conn)
+        .await?
+        .into_iter()
+        .map(|row| row.id)
+        .collect())
     }
 
     pub async fn get_release_artists(
@@ -58,7 +515,7 @@ impl Artist {
     ) -> Result<Vec<Self>, sqlx::Error> {
         sqlx::query_as!(
             Self,
-            "SELECT * from artists
+            "SELECT id, name, sort_name, created from artists
             WHERE id IN (
                 SELECT artist_id
                 FROM artist_releases
@@ -71,74 +528,853 @@ impl Artist {
     }
 }
 
-#[derive(Clone, Debug, sqlx::FromRow)]
-pub struct Release {
+#[derive(Clone, Debug)]
+pub struct Label {
     pub id: RowId,
     pub name: String,
-    pub date: Option<String>,
     pub created: String, // TODO parse date
 }
 
-impl Release {
+impl Label {
+    pub async fn get(
+        conn: &mut SqlitePoolConn,
+        id: RowId,
+    ) -> Result<Self, sqlx::Error> {
+        sqlx::query_as!(Self, "SELECT * FROM labels WHERE id = ?", id)
+            .fetch_one(conn)
+            .await
+    }
+
     pub async fn create(
         conn: &mut SqlitePoolConn,
         name: &str,
-        date: Option<&str>,
-        artist_ids: Vec<RowId>,
     ) -> Result<Self, sqlx::Error> {
-        let mut conn = conn;
-        let release_id = sqlx::query(
-            "INSERT INTO releases (name, date)
-            VALUES (?, ?)",
-        )
-        .bind(name)
-        .bind(date)
-        .execute(conn.borrow_mut())
-        .await?
-        .last_insert_rowid();
+        let id = sqlx::query("INSERT INTO labels (name) VALUES (?);")
+            .bind(name)
+            .execute(conn.borrow_mut())
+            .await?
+            .last_insert_rowid();
 
-        for id in artist_ids {
-            ArtistRelease::create(&mut conn, id, release_id).await?;
+        sqlx::query_as!(Self, "SELECT * FROM labels WHERE id = ?;", id)
+            .fetch_one(conn.borrow_mut())
+            .await
+    }
+
+    pub async fn get_by_name(
+        conn: &mut SqlitePoolConn,
+        name: &str,
+    ) -> Result<Self, sqlx::Error> {
+        sqlx::query_as!(Self, "SELECT * FROM labels WHERE name = ?", name)
+            .fetch_one(conn)
+            .await
+    }
+
+    pub async fn find_or_create(
+        conn: &mut SqlitePoolConn,
+        name: &str,
+    ) -> Result<Self, sqlx::Error> {
+        match Self::create(conn, name).await {
+            Ok(l) => Ok(l),
+            Err(sqlx::Error::Database(d)) => match d.code() {
+                Some(code) if code == SQLITE_UNIQUE_VIOLATION => {
+                    Self::get_by_name(conn, name).await
+                }
+                _ => Err(sqlx::Error::Database(d)),
+            },
+            Err(e) => Err(e),
         }
+    }
+}
 
-        sqlx::query_as!(
-            Self,
-            "SELECT * FROM releases WHERE id = ?;",
-            release_id
-        )
-        .fetch_one(conn.borrow_mut())
-        .await
+#[derive(Clone, Debug)]
+pub struct Genre {
+    pub id: RowId,
+    pub name: String,
+    pub created: String, // TODO parse date
+}
+
+impl Genre {
+    pub async fn create(
+        conn: &mut SqlitePoolConn,
+        name: &str,
+    ) -> Result<Self, sqlx::Error> {
+        let id = sqlx::query("INSERT INTO genres (name) VALUES (?);")
+            .bind(name)
+            .execute(conn.borrow_mut())
+            .await?
+            .last_insert_rowid();
+
+        sqlx::query_as!(Self, "SELECT * FROM genres WHERE id = ?;", id)
+            .fetch_one(conn.borrow_mut())
+            .await
     }
 
+    /// case insensitive
     pub async fn get_by_name(
         conn: &mut SqlitePoolConn,
         name: &str,
+    ) -> Result<Self, sqlx::Error> {
+        sqlx::query_as!(Self, "SELECT * FROM genres WHERE name = ?", name)
+            .fetch_one(conn)
+            .await
+    }
+
+    pub async fn find_or_create(
+        conn: &mut SqlitePoolConn,
+        name: &str,
+    ) -> Result<Self, sqlx::Error> {
+        match Self::create(conn, name).await {
+            Ok(g) => Ok(g),
+            Err(sqlx::Error::Database(d)) => match d.code() {
+                Some(code) if code == SQLITE_UNIQUE_VIOLATION => {
+                    Self::get_by_name(conn, name).await
+                }
+                _ => Err(sqlx::Error::Database(d)),
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    pub async fn get_all(
+        conn: &mut SqlitePoolConn,
     ) -> Result<Vec<Self>, sqlx::Error> {
-        sqlx::query_as!(Self, "SELECT * FROM releases WHERE name = ?", name)
+        sqlx::query_as!(Self, "SELECT * FROM genres ORDER BY name;")
             .fetch_all(conn)
             .await
     }
 
-    pub async fn get_artist_releases(
+    pub async fn get_track_genres(
         conn: &mut SqlitePoolConn,
-        artist_id: RowId,
+        track_id: RowId,
     ) -> Result<Vec<Self>, sqlx::Error> {
         sqlx::query_as!(
             Self,
-            "SELECT * from releases
-             WHERE id IN (
-                SELECT release_id
-                FROM artist_releases
-                WHERE artist_id = ?
-            )",
-            artist_id
+            "SELECT genres.* FROM track_genres
+            JOIN genres ON track_genres.genre_id = genres.id
+            WHERE track_genres.track_id = ?",
+            track_id
         )
         .fetch_all(conn)
         .await
     }
 }
 
-// does this need to be pub? only used internally
+pub struct TrackGenre;
+
+impl TrackGenre {
+    pub async fn create(
+        conn: &mut SqlitePoolConn,
+        track_id: RowId,
+        genre_id: RowId,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT OR IGNORE INTO track_genres (track_id, genre_id)
+            VALUES (?, ?)",
+        )
+        .bind(track_id)
+        .bind(genre_id)
+        .execute(conn)
+        .await
+        .map(|_done| ())
+    }
+
+    /// creates the genre if needed, matching names case insensitively
+    pub async fn create_by_name(
+        tx: &mut SqliteTx<'_>,
+        track_id: RowId,
+        name: &str,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query("INSERT OR IGNORE INTO genres (name) VALUES (?)")
+            .bind(name)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            "INSERT OR IGNORE INTO track_genres (track_id, genre_id)
+            SELECT ?, id FROM genres WHERE name = ?",
+        )
+        .bind(track_id)
+        .bind(name)
+        .execute(&mut *tx)
+        .await
+        .map(|_done| ())
+    }
+}
+
+/// how strictly an imported track's release info has to match an existing
+/// release (with the same album artist + name) to be grouped into it
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReleaseMatch {
+    /// artist + name only
+    Loose,
+    /// fields known on both sides have to agree
+    Normal,
+    /// fields have to agree, and be known on both sides or neither
+    Strict,
+}
+
+fn field_matches<T: PartialEq>(
+    a: Option<T>,
+    b: Option<T>,
+    strictness: ReleaseMatch,
+) -> bool {
+    match (a, b, strictness) {
+        (_, _, ReleaseMatch::Loose) => true,
+        (Some(a), Some(b), _) => a == b,
+        (None, None, _) => true,
+        (_, _, ReleaseMatch::Normal) => true,
+        (_, _, ReleaseMatch::Strict) => false,
+    }
+}
+
+// dates are tagged with wildly different precision, so only compare years
+fn date_year(date: &str) -> &str {
+    date.trim().get(0..4).unwrap_or(date)
+}
+
+// catalog numbers and barcodes get formatted inconsistently between sources
+fn normalize_identifier(id: &str) -> String {
+    id.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(|c| c.to_uppercase())
+        .collect()
+}
+
+/// the parts of a release used to tell apart releases with the same name
+#[derive(Clone, Debug, Default)]
+pub struct ReleaseDetails {
+    pub date: Option<String>,
+    pub label_id: Option<RowId>,
+    pub catalog_num: Option<String>,
+    pub barcode: Option<String>,
+    pub release_type: Option<String>,
+    pub edition: Option<String>,
+}
+
+#[derive(Clone, Debug, sqlx::FromRow)]
+pub struct Release {
+    pub id: RowId,
+    pub name: String,
+    pub date: Option<String>,
+    pub album_artist_id: Option<RowId>,
+    pub label_id: Option<RowId>,
+    pub catalog_num: Option<String>,
+    pub barcode: Option<String>,
+    pub release_type: Option<String>,
+    pub edition: Option<String>,
+    pub created: String, // TODO parse date
+}
+
+/// every editable part of a release, see `Release::update`
+#[derive(Clone, Debug)]
+pub struct ReleaseEdit {
+    pub name: String,
+    pub details: ReleaseDetails,
+    pub album_artist_id: Option<RowId>,
+    /// credited on the release, usually just the album artist
+    pub artist_ids: Vec<RowId>,
+}
+
+impl Release {
+    pub async fn create(
+        conn: &mut SqlitePoolConn,
+        name: &str,
+        details: &ReleaseDetails,
+        album_artist_id: Option<RowId>,
+        artist_ids: Vec<RowId>,
+    ) -> Result<Self, sqlx::Error> {
+        let mut conn = conn;
+        let release_id = sqlx::query(
+            "INSERT INTO releases
+            (name, date, album_artist_id, label_id, catalog_num, barcode,
+            release_type, edition)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(name)
+        .bind(&details.date)
+        .bind(album_artist_id)
+        .bind(details.label_id)
+        .bind(&details.catalog_num)
+        .bind(&details.barcode)
+        .bind(&details.release_type)
+        .bind(&details.edition)
+        .execute(conn.borrow_mut())
+        .await?
+        .last_insert_rowid();
+
+        for id in artist_ids {
+            ArtistRelease::create(&mut conn, id, release_id).await?;
+        }
+
+        Self::get(conn, release_id).await
+    }
+
+    pub async fn get(
+        conn: &mut SqlitePoolConn,
+        id: RowId,
+    ) -> Result<Self, sqlx::Error> {
+        sqlx::query_as!(Self, "SELECT * FROM releases WHERE id = ?;", id)
+            .fetch_one(conn)
+            .await
+    }
+
+    pub fn details(&self) -> ReleaseDetails {
+        ReleaseDetails {
+            date: self.date.clone(),
+            label_id: self.label_id,
+            catalog_num: self.catalog_num.clone(),
+            barcode: self.barcode.clone(),
+            release_type: self.release_type.clone(),
+            edition: self.edition.clone(),
+        }
+    }
+
+    /// whether a track with this release name + details belongs to this
+    /// release. the artist is expected to have been matched already
+    pub fn matches(
+        &self,
+        name: &str,
+        details: &ReleaseDetails,
+        strictness: ReleaseMatch,
+    ) -> bool {
+        let s = strictness;
+        self.name == name
+            && field_matches(
+                self.date.as_deref().map(date_year),
+                details.date.as_deref().map(date_year),
+                s,
+            )
+            && field_matches(self.label_id, details.label_id, s)
+            && field_matches(
+                self.catalog_num.as_deref().map(normalize_identifier),
+                details.catalog_num.as_deref().map(normalize_identifier),
+                s,
+            )
+            && field_matches(
+                self.barcode.as_deref().map(normalize_identifier),
+                details.barcode.as_deref().map(normalize_identifier),
+                s,
+            )
+            && field_matches(
+                self.release_type.as_deref(),
+                details.release_type.as_deref(),
+                s,
+            )
+            && field_matches(
+                self.edition.as_deref().map(str::to_lowercase),
+                details.edition.as_deref().map(str::to_lowercase),
+                s,
+            )
+    }
+
+    pub async fn get_track_ids(
+        conn: &mut SqlitePoolConn,
+        id: RowId,
+    ) -> Result<Vec<RowId>, sqlx::Error> {
+        Ok(
+            sqlx::query!("SELECT id FROM tracks WHERE release_id = ?", id)
+                .fetch_all(conn)
+                .await?
+                .into_iter()
+                .map(|row| row.id)
+                .collect(),
+        )
+    }
+
+    // artists credited on the release, inside a transaction
+    async fn get_artist_ids(
+        tx: &mut SqliteTx<'_>,
+        id: RowId,
+    ) -> Result<Vec<RowId>, sqlx::Error> {
+        Ok(sqlx::query!(
+            "SELECT artist_id FROM artist_releases WHERE release_id = ?",
+            id
+        )
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .map(|row| row.artist_id)
+        .collect())
+    }
+
+    /// moves all tracks + artists from `merge_id` into `keep_id` and deletes
+    /// `merge_id`. details missing on the kept release are taken from the
+    /// merged one. logged for undo. None if they're the same release
+    pub async fn merge(
+        conn: &mut SqlitePoolConn,
+        keep_id: RowId,
+        merge_id: RowId,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        if keep_id == merge_id {
+            return Ok(None);
+        }
+        let keep = Self::get(conn, keep_id).await?;
+        let merged = Self::get(conn, merge_id).await?;
+        let track_ids = Self::get_track_ids(conn, merge_id).await?;
+
+        let mut tx = conn.begin().await?;
+        let group_id = EditGroup::create(&mut tx, "merge releases").await?;
+        let keep_artists = Self::get_artist_ids(&mut tx, keep_id).await?;
+        let mut artist_ids = keep_artists.clone();
+        for id in Self::get_artist_ids(&mut tx, merge_id).await? {
+            if !artist_ids.contains(&id) {
+                artist_ids.push(id);
+            }
+        }
+        let edit = ReleaseEdit {
+            name: keep.name.clone(),
+            details: ReleaseDetails {
+                date: keep.date.clone().or(merged.date),
+                label_id: keep.label_id.or(merged.label_id),
+                catalog_num: keep.catalog_num.clone().or(merged.catalog_num),
+                barcode: keep.barcode.clone().or(merged.barcode),
+                release_type: keep.release_type.clone().or(merged.release_type),
+                edition: keep.edition.clone().or(merged.edition),
+            },
+            album_artist_id: keep.album_artist_id.or(merged.album_artist_id),
+            artist_ids,
+        };
+
+        let mut changes = Vec::new();
+        for id in track_ids {
+            let (before, after) =
+                (log_text(Some(merge_id)), log_text(Some(keep_id)));
+            changes.push(("track", id, "release_id", before, after));
+        }
+        for (field, before, after) in
+            keep.log_columns(&edit.details, Some(&edit))
+        {
+            changes.push(("release", keep_id, field, before, after));
+        }
+        changes.push((
+            "release",
+            keep_id,
+            "artists",
+            log_list(&keep_artists),
+            log_list(&edit.artist_ids),
+        ));
+
+        // applied the same way as a redo
+        for (entity, id, field, before, after) in changes {
+            EditLogEntry::apply_value(
+                &mut tx,
+                entity,
+                id,
+                field,
+                after.as_deref(),
+            )
+            .await?;
+            EditLogEntry::record(
+                &mut tx, group_id, entity, id, field, before, after,
+            )
+            .await?;
+        }
+        Self::delete_if_empty(&mut tx, group_id, merge_id).await?;
+        tx.commit().await?;
+
+        Self::get(conn, keep_id).await.map(Some)
+    }
+
+    /// moves `track_ids` out of `release_id` into a new release. the new
+    /// release keeps the same artists, the old one is deleted if it's left
+    /// empty. logged for undo. None if `track_ids` is empty or any of them
+    /// is on another release
+    pub async fn split(
+        conn: &mut SqlitePoolConn,
+        release_id: RowId,
+        track_ids: &[RowId],
+        name: &str,
+        details: &ReleaseDetails,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        let on_release = Self::get_track_ids(conn, release_id).await?;
+        if track_ids.is_empty()
+            || track_ids.iter().any(|id| !on_release.contains(id))
+        {
+            return Ok(None);
+        }
+        let mut track_ids = track_ids.to_vec();
+        track_ids.sort();
+        track_ids.dedup();
+        let original = Self::get(conn, release_id).await?;
+
+        let mut tx = conn.begin().await?;
+        let group_id = EditGroup::create(&mut tx, "split release").await?;
+
+        let new_id = sqlx::query(
+            "INSERT INTO releases
+            (name, date, album_artist_id, label_id, catalog_num, barcode,
+            release_type, edition)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(name)
+        .bind(&details.date)
+        .bind(original.album_artist_id)
+        .bind(details.label_id)
+        .bind(&details.catalog_num)
+        .bind(&details.barcode)
+        .bind(&details.release_type)
+        .bind(&details.edition)
+        .execute(&mut tx)
+        .await?
+        .last_insert_rowid();
+
+        sqlx::query(
+            "INSERT INTO artist_releases (artist_id, release_id)
+            SELECT artist_id, ? FROM artist_releases WHERE release_id = ?",
+        )
+        .bind(new_id)
+        .bind(release_id)
+        .execute(&mut tx)
+        .await?;
+
+        // the reverse of `delete_if_empty`, the row goes first so undo
+        // removes it last
+        let created = sqlx::query_as!(
+            Self,
+            "SELECT * FROM releases WHERE id = ?",
+            new_id
+        )
+        .fetch_one(&mut tx)
+        .await?;
+        let mut changes = vec![("row", None, Some(name.to_owned()))];
+        for (field, value, _) in
+            created.log_columns(&ReleaseDetails::default(), None)
+        {
+            if field != "name" {
+                changes.push((field, None, value));
+            }
+        }
+        let artist_ids = Self::get_artist_ids(&mut tx, new_id).await?;
+        changes.push(("artists", None, log_list(&artist_ids)));
+        for (field, before, after) in changes {
+            EditLogEntry::record(
+                &mut tx, group_id, "release", new_id, field, before, after,
+            )
+            .await?;
+        }
+
+        for track_id in track_ids {
+            EditLogEntry::record(
+                &mut tx,
+                group_id,
+                "track",
+                track_id,
+                "release_id",
+                log_text(Some(release_id)),
+                log_text(Some(new_id)),
+            )
+            .await?;
+            sqlx::query("UPDATE tracks SET release_id = ? WHERE id = ?")
+                .bind(new_id)
+                .bind(track_id)
+                .execute(&mut tx)
+                .await?;
+        }
+        Self::delete_if_empty(&mut tx, group_id, release_id).await?;
+
+        tx.commit().await?;
+
+        Self::get(conn, new_id).await.map(Some)
+    }
+
+    /// removes a release left without tracks, along with its artist
+    /// credits. logged so undoing the move out of it brings it back
+    pub async fn delete_if_empty(
+        tx: &mut SqliteTx<'_>,
+        group_id: RowId,
+        id: RowId,
+    ) -> Result<(), sqlx::Error> {
+        let tracks = sqlx::query!(
+            "SELECT COUNT(*) AS count FROM tracks WHERE release_id = ?",
+            id
+        )
+        .fetch_one(&mut *tx)
+        .await?
+        .count;
+        if tracks > 0 {
+            return Ok(());
+        }
+
+        let release =
+            sqlx::query_as!(Self, "SELECT * FROM releases WHERE id = ?", id)
+                .fetch_one(&mut *tx)
+                .await?;
+        let artist_ids = Self::get_artist_ids(tx, id).await?;
+
+        // the row itself goes last, so undo brings it back first
+        let mut deleted = release.log_columns(&ReleaseDetails::default(), None);
+        deleted.retain(|(field, _, _)| *field != "name");
+        deleted.push(("artists", log_list(&artist_ids), None));
+        deleted.push(("row", Some(release.name.clone()), None));
+        for (field, before, after) in deleted {
+            EditLogEntry::record(
+                tx, group_id, "release", id, field, before, after,
+            )
+            .await?;
+        }
+
+        sqlx::query("DELETE FROM artist_releases WHERE release_id = ?")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM releases WHERE id = ?")
+            .bind(id)
+            .execute(&mut *tx)
+            .await
+            .map(|_done| ())
+    }
+
+    // (field, before, after) for each of `RELEASE_LOGGED`
+    fn log_columns(
+        &self,
+        details: &ReleaseDetails,
+        edit: Option<&ReleaseEdit>,
+    ) -> Vec<(&'static str, Option<String>, Option<String>)> {
+        vec![
+            (
+                "name",
+                Some(self.name.clone()),
+                edit.map(|e| e.name.clone()),
+            ),
+            ("date", self.date.clone(), details.date.clone()),
+            (
+                "album_artist_id",
+                log_text(self.album_artist_id),
+                log_text(edit.and_then(|e| e.album_artist_id)),
+            ),
+            (
+                "label_id",
+                log_text(self.label_id),
+                log_text(details.label_id),
+            ),
+            (
+                "catalog_num",
+                self.catalog_num.clone(),
+                details.catalog_num.clone(),
+            ),
+            ("barcode", self.barcode.clone(), details.barcode.clone()),
+            (
+                "release_type",
+                self.release_type.clone(),
+                details.release_type.clone(),
+            ),
+            ("edition", self.edition.clone(), details.edition.clone()),
+        ]
+    }
+
+    /// replaces everything about the release with `edit`, logged for undo
+    pub async fn update(
+        conn: &mut SqlitePoolConn,
+        id: RowId,
+        edit: &ReleaseEdit,
+    ) -> Result<Self, sqlx::Error> {
+        let details = &edit.details;
+        let release = Self::get(conn, id).await?;
+        let artist_ids: Vec<_> = Artist::get_release_artists(conn, id)
+            .await?
+            .into_iter()
+            .map(|a| a.id)
+            .collect();
+
+        let mut tx = conn.begin().await?;
+        let group_id = EditGroup::create(&mut tx, "edit release").await?;
+        let mut changes = release.log_columns(details, Some(edit));
+        changes.push((
+            "artists",
+            log_list(&artist_ids),
+            log_list(&edit.artist_ids),
+        ));
+        for (field, before, after) in changes {
+            EditLogEntry::record(
+                &mut tx, group_id, "release", id, field, before, after,
+            )
+            .await?;
+        }
+
+        sqlx::query(
+            "UPDATE releases SET
+                name = ?, date = ?, album_artist_id = ?, label_id = ?,
+                catalog_num = ?, barcode = ?, release_type = ?, edition = ?
+            WHERE id = ?",
+        )
+        .bind(&edit.name)
+        .bind(&details.date)
+        .bind(edit.album_artist_id)
+        .bind(details.label_id)
+        .bind(&details.catalog_num)
+        .bind(&details.barcode)
+        .bind(&details.release_type)
+        .bind(&details.edition)
+        .bind(id)
+        .execute(&mut tx)
+        .await?;
+
+        sqlx::query("DELETE FROM artist_releases WHERE release_id = ?")
+            .bind(id)
+            .execute(&mut tx)
+            .await?;
+        for artist_id in edit.artist_ids.iter() {
+            sqlx::query(
+                "INSERT OR IGNORE INTO artist_releases (artist_id, release_id)
+                VALUES (?, ?)",
+            )
+            .bind(artist_id)
+            .bind(id)
+            .execute(&mut tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        Self::get(conn, id).await
+    }
+
+    pub async fn get_by_name(
+        conn: &mut SqlitePoolConn,
+        name: &str,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as!(Self, "SELECT * FROM releases WHERE name = ?", name)
+            .fetch_all(conn)
+            .await
+    }
+
+    pub async fn get_artist_releases(
+        conn: &mut SqlitePoolConn,
+        artist_id: RowId,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as!(
+            Self,
+            "SELECT * from releases
+             WHERE id IN (
+                SELECT release_id
+                FROM artist_releases
+                WHERE artist_id = ?
+            )",
+            artist_id
+        )
+        .fetch_all(conn)
+        .await
+    }
+}
+
+/// another name for an artist, e.g. a misspelling or a merged artist
+#[derive(Clone, Debug)]
+pub struct ArtistAlias {
+    pub id: RowId,
+    pub artist_id: RowId,
+    pub name: String,
+    pub created: String, // TODO parse date
+}
+
+impl ArtistAlias {
+    // the artist's alias names, logged as its "aliases"
+    async fn get_names(
+        tx: &mut SqliteTx<'_>,
+        artist_id: RowId,
+    ) -> Result<Vec<String>, sqlx::Error> {
+        Ok(sqlx::query!(
+            "SELECT name FROM artist_aliases WHERE artist_id = ? ORDER BY name",
+            artist_id
+        )
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .map(|row| row.name)
+        .collect())
+    }
+
+    /// None if the name is already an alias. logged for undo
+    pub async fn create(
+        conn: &mut SqlitePoolConn,
+        artist_id: RowId,
+        name: &str,
+    ) -> Result<Option<RowId>, sqlx::Error> {
+        let mut tx = conn.begin().await?;
+        let before = Self::get_names(&mut tx, artist_id).await?;
+        let done = sqlx::query(
+            "INSERT OR IGNORE INTO artist_aliases (artist_id, name, match_name)
+            VALUES (?, ?, ?)",
+        )
+        .bind(artist_id)
+        .bind(name)
+        .bind(fold_artist_name(name))
+        .execute(&mut tx)
+        .await?;
+        if done.rows_affected() == 0 {
+            return Ok(None);
+        }
+
+        let group_id = EditGroup::create(&mut tx, "add alias").await?;
+        let after = Self::get_names(&mut tx, artist_id).await?;
+        EditLogEntry::record(
+            &mut tx,
+            group_id,
+            "artist",
+            artist_id,
+            "aliases",
+            log_list(&before),
+            log_list(&after),
+        )
+        .await?;
+        tx.commit().await?;
+
+        Ok(Some(done.last_insert_rowid()))
+    }
+
+    /// logged for undo
+    pub async fn delete(
+        conn: &mut SqlitePoolConn,
+        id: RowId,
+    ) -> Result<(), sqlx::Error> {
+        let alias = sqlx::query!(
+            "SELECT artist_id FROM artist_aliases WHERE id = ?",
+            id
+        )
+        .fetch_optional(conn.borrow_mut())
+        .await?;
+        let artist_id = match alias {
+            Some(alias) => alias.artist_id,
+            None => return Ok(()),
+        };
+
+        let mut tx = conn.begin().await?;
+        let group_id = EditGroup::create(&mut tx, "remove alias").await?;
+        let before = Self::get_names(&mut tx, artist_id).await?;
+        sqlx::query("DELETE FROM artist_aliases WHERE id = ?")
+            .bind(id)
+            .execute(&mut tx)
+            .await?;
+        let after = Self::get_names(&mut tx, artist_id).await?;
+        EditLogEntry::record(
+            &mut tx,
+            group_id,
+            "artist",
+            artist_id,
+            "aliases",
+            log_list(&before),
+            log_list(&after),
+        )
+        .await?;
+        tx.commit().await
+    }
+
+    pub async fn get_artist_aliases(
+        conn: &mut SqlitePoolConn,
+        artist_id: RowId,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as!(
+            Self,
+            "SELECT id, artist_id, name, created FROM artist_aliases
+            WHERE artist_id = ?
+            ORDER BY name",
+            artist_id
+        )
+        .fetch_all(conn)
+        .await
+    }
+}
+
+// does this need to be pub? only used internally
 #[derive(Clone, Debug)]
 pub struct ArtistRelease {
     pub artist_id: RowId,
@@ -159,165 +1395,2539 @@ impl ArtistRelease {
         .bind(release_id)
         .execute(conn)
         .await
-        .map(|_done| ())
+        .map(|_done| ())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Tag {
+    pub id: RowId,
+    pub name: String,
+    /// nested under this tag, see `Tag::set_parent`
+    pub parent_id: Option<RowId>,
+    pub color: Option<String>,
+}
+
+/// a tag with how many tracks have it, not counting nested tags
+#[derive(Clone, Debug)]
+pub struct TagUsage {
+    pub tag: Tag,
+    pub track_count: i64,
+}
+
+impl Tag {
+    pub async fn create(
+        conn: &mut SqlitePoolConn,
+        tag_name: &str,
+    ) -> Result<RowId, sqlx::Error> {
+        let id = sqlx::query("INSERT INTO tags (name) VALUES (?)")
+            .bind(tag_name)
+            .execute(conn)
+            .await?
+            .last_insert_rowid();
+
+        Ok(id)
+    }
+
+    pub async fn get(
+        conn: &mut SqlitePoolConn,
+        id: RowId,
+    ) -> Result<Self, sqlx::Error> {
+        sqlx::query_as!(Self, "SELECT * FROM tags WHERE id = ?", id)
+            .fetch_one(conn)
+            .await
+    }
+
+    /// case insensitive
+    pub async fn get_by_name(
+        conn: &mut SqlitePoolConn,
+        tag_name: &str,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as!(Self, "SELECT * FROM tags WHERE name = ?", tag_name)
+            .fetch_optional(conn)
+            .await
+    }
+
+    // tags will usually be reused instead of created -> optimize for the read
+    pub async fn find_or_create(
+        conn: &mut SqlitePoolConn,
+        tag_name: &str,
+    ) -> Result<RowId, sqlx::Error> {
+        match Self::get_by_name(conn, tag_name).await? {
+            Some(tag) => Ok(tag.id),
+            None => Self::create(conn, tag_name).await,
+        }
+    }
+
+    /// every tag with its track count, by name
+    pub async fn get_all_usage(
+        conn: &mut SqlitePoolConn,
+    ) -> Result<Vec<TagUsage>, sqlx::Error> {
+        Ok(sqlx::query!(
+            "SELECT
+                tags.id,
+                tags.name,
+                tags.parent_id,
+                tags.color,
+                COUNT(track_tags.track_id) AS track_count
+            FROM tags
+            LEFT JOIN track_tags ON track_tags.tag_id = tags.id
+            GROUP BY tags.id
+            ORDER BY tags.name"
+        )
+        .fetch_all(conn)
+        .await?
+        .into_iter()
+        .map(|row| TagUsage {
+            tag: Tag {
+                id: row.id,
+                name: row.name,
+                parent_id: row.parent_id,
+                color: row.color,
+            },
+            track_count: row.track_count as i64,
+        })
+        .collect())
+    }
+
+    /// fails with a unique violation if another tag has the name. logged
+    /// for undo
+    pub async fn rename(
+        conn: &mut SqlitePoolConn,
+        id: RowId,
+        tag_name: &str,
+    ) -> Result<Self, sqlx::Error> {
+        let tag = Self::get(conn, id).await?;
+
+        let mut tx = conn.begin().await?;
+        let group_id = EditGroup::create(&mut tx, "rename tag").await?;
+        sqlx::query("UPDATE tags SET name = ? WHERE id = ?")
+            .bind(tag_name)
+            .bind(id)
+            .execute(&mut tx)
+            .await?;
+        EditLogEntry::record(
+            &mut tx,
+            group_id,
+            "tag",
+            id,
+            "name",
+            Some(tag.name),
+            Some(tag_name.to_owned()),
+        )
+        .await?;
+        tx.commit().await?;
+
+        Self::get(conn, id).await
+    }
+
+    /// logged for undo
+    pub async fn set_color(
+        conn: &mut SqlitePoolConn,
+        id: RowId,
+        color: Option<&str>,
+    ) -> Result<(), sqlx::Error> {
+        let tag = Self::get(conn, id).await?;
+
+        let mut tx = conn.begin().await?;
+        let group_id = EditGroup::create(&mut tx, "set tag color").await?;
+        sqlx::query("UPDATE tags SET color = ? WHERE id = ?")
+            .bind(color)
+            .bind(id)
+            .execute(&mut tx)
+            .await?;
+        EditLogEntry::record(
+            &mut tx,
+            group_id,
+            "tag",
+            id,
+            "color",
+            tag.color,
+            log_text(color),
+        )
+        .await?;
+        tx.commit().await
+    }
+
+    /// nests the tag under `parent_id`, or moves it to the top level. false
+    /// (and nothing changes) if the parent is the tag or nested under it.
+    /// logged for undo
+    pub async fn set_parent(
+        conn: &mut SqlitePoolConn,
+        id: RowId,
+        parent_id: Option<RowId>,
+    ) -> Result<bool, sqlx::Error> {
+        if let Some(parent_id) = parent_id {
+            let nested = Self::get_nested_ids(conn, id).await?;
+            if nested.contains(&parent_id) {
+                return Ok(false);
+            }
+        }
+        let tag = Self::get(conn, id).await?;
+
+        let mut tx = conn.begin().await?;
+        let group_id = EditGroup::create(&mut tx, "nest tag").await?;
+        sqlx::query("UPDATE tags SET parent_id = ? WHERE id = ?")
+            .bind(parent_id)
+            .bind(id)
+            .execute(&mut tx)
+            .await?;
+        EditLogEntry::record(
+            &mut tx,
+            group_id,
+            "tag",
+            id,
+            "parent_id",
+            log_text(tag.parent_id),
+            log_text(parent_id),
+        )
+        .await?;
+        tx.commit().await?;
+
+        Ok(true)
+    }
+
+    /// the tag + every tag nested under it, at any depth
+    pub async fn get_nested_ids(
+        conn: &mut SqlitePoolConn,
+        id: RowId,
+    ) -> Result<Vec<RowId>, sqlx::Error> {
+        Ok(sqlx::query!(
+            "WITH RECURSIVE nested(id) AS (
+                SELECT ?
+                UNION
+                SELECT tags.id FROM tags
+                JOIN nested ON tags.parent_id = nested.id
+            )
+            SELECT id AS \"id!: RowId\" FROM nested",
+            id
+        )
+        .fetch_all(conn)
+        .await?
+        .into_iter()
+        .map(|row| row.id)
+        .collect())
+    }
+
+    /// tracks with the tag itself, not nested tags
+    pub async fn get_track_ids(
+        conn: &mut SqlitePoolConn,
+        id: RowId,
+    ) -> Result<Vec<RowId>, sqlx::Error> {
+        Ok(
+            sqlx::query!(
+                "SELECT track_id FROM track_tags WHERE tag_id = ?",
+                id
+            )
+            .fetch_all(conn)
+            .await?
+            .into_iter()
+            .map(|row| row.track_id)
+            .collect(),
+        )
+    }
+
+    /// moves the tracks + nested tags of `merge_id` to `keep_id`, then
+    /// deletes it. logged for undo. None if they're the same tag
+    pub async fn merge(
+        conn: &mut SqlitePoolConn,
+        keep_id: RowId,
+        merge_id: RowId,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        if keep_id == merge_id {
+            return Ok(None);
+        }
+
+        // the kept tag comes out from under the merged one first, or it'd end
+        // up nested under itself
+        let keep = Self::get(conn, keep_id).await?;
+        let merge = Self::get(conn, merge_id).await?;
+        let lift_keep = Self::get_nested_ids(conn, merge_id)
+            .await?
+            .contains(&keep_id);
+        let keep_tracks = Self::get_track_ids(conn, keep_id).await?;
+        let mut merged_tracks = Self::get_track_ids(conn, merge_id).await?;
+        merged_tracks.extend(keep_tracks.iter());
+        merged_tracks.sort();
+        merged_tracks.dedup();
+
+        let mut tx = conn.begin().await?;
+        let group_id = EditGroup::create(&mut tx, "merge tags").await?;
+        let children =
+            sqlx::query!("SELECT id FROM tags WHERE parent_id = ?", merge_id)
+                .fetch_all(&mut tx)
+                .await?;
+        let mut changes = Vec::new();
+        if lift_keep {
+            sqlx::query("UPDATE tags SET parent_id = ? WHERE id = ?")
+                .bind(merge.parent_id)
+                .bind(keep_id)
+                .execute(&mut tx)
+                .await?;
+            changes.push((
+                keep_id,
+                "parent_id",
+                log_text(keep.parent_id),
+                log_text(merge.parent_id),
+            ));
+        }
+        for child in children.into_iter().filter(|c| c.id != keep_id) {
+            changes.push((
+                child.id,
+                "parent_id",
+                log_text(Some(merge_id)),
+                log_text(Some(keep_id)),
+            ));
+        }
+        changes.push((
+            keep_id,
+            "tracks",
+            log_list(&keep_tracks),
+            log_list(&merged_tracks),
+        ));
+        for (id, field, before, after) in changes {
+            EditLogEntry::record(
+                &mut tx, group_id, "tag", id, field, before, after,
+            )
+            .await?;
+        }
+
+        for merge_query in &[
+            "UPDATE tags SET parent_id = ? WHERE parent_id = ?",
+            "INSERT OR IGNORE INTO track_tags (track_id, tag_id)
+            SELECT track_id, ? FROM track_tags WHERE tag_id = ?",
+        ] {
+            sqlx::query(merge_query)
+                .bind(keep_id)
+                .bind(merge_id)
+                .execute(&mut tx)
+                .await?;
+        }
+        Self::delete_in(&mut tx, group_id, merge_id).await?;
+        tx.commit().await?;
+
+        Self::get(conn, keep_id).await.map(Some)
+    }
+
+    /// untags every track, and moves nested tags up a level. returns how
+    /// many tracks had the tag. logged for undo
+    pub async fn delete(
+        conn: &mut SqlitePoolConn,
+        id: RowId,
+    ) -> Result<i64, sqlx::Error> {
+        let mut tx = conn.begin().await?;
+        let group_id = EditGroup::create(&mut tx, "delete tag").await?;
+        let untagged = Self::delete_in(&mut tx, group_id, id).await?;
+        tx.commit().await?;
+        Ok(untagged)
+    }
+
+    async fn delete_in(
+        tx: &mut SqliteTx<'_>,
+        group_id: RowId,
+        id: RowId,
+    ) -> Result<i64, sqlx::Error> {
+        let tag = sqlx::query_as!(Self, "SELECT * FROM tags WHERE id = ?", id)
+            .fetch_one(&mut *tx)
+            .await?;
+        let children: Vec<_> =
+            sqlx::query!("SELECT id FROM tags WHERE parent_id = ?", id)
+                .fetch_all(&mut *tx)
+                .await?
+                .into_iter()
+                .map(|row| row.id)
+                .collect();
+        let track_ids: Vec<_> = sqlx::query!(
+            "SELECT track_id FROM track_tags WHERE tag_id = ?",
+            id
+        )
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .map(|row| row.track_id)
+        .collect();
+
+        // the row itself goes last, so undo brings it back first
+        let mut deleted: Vec<_> = children
+            .into_iter()
+            .map(|child_id| {
+                (
+                    child_id,
+                    "parent_id",
+                    log_text(Some(id)),
+                    log_text(tag.parent_id),
+                )
+            })
+            .collect();
+        deleted.push((id, "tracks", log_list(&track_ids), None));
+        deleted.push((id, "color", tag.color.clone(), None));
+        deleted.push((id, "parent_id", log_text(tag.parent_id), None));
+        deleted.push((id, "row", Some(tag.name.clone()), None));
+        for (entity_id, field, before, after) in deleted {
+            EditLogEntry::record(
+                tx, group_id, "tag", entity_id, field, before, after,
+            )
+            .await?;
+        }
+
+        sqlx::query(
+            "UPDATE tags
+            SET parent_id = (SELECT parent_id FROM tags WHERE id = ?)
+            WHERE parent_id = ?",
+        )
+        .bind(id)
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+        let untagged = sqlx::query("DELETE FROM track_tags WHERE tag_id = ?")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        sqlx::query("DELETE FROM tags WHERE id = ?")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        Ok(untagged as i64)
+    }
+}
+
+#[derive(Debug)]
+pub struct TrackTag {
+    track_id: RowId,
+    tag_id: RowId,
+}
+
+impl TrackTag {
+    pub async fn create(
+        conn: &mut SqlitePoolConn,
+        track_id: RowId,
+        tag_id: RowId,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT OR IGNORE INTO track_tags (track_id, tag_id) VALUES (?, ?)",
+        )
+        .bind(track_id)
+        .bind(tag_id)
+        .execute(conn)
+        .await
+        .map(|_done| ())
+    }
+
+    pub async fn delete(
+        conn: &mut SqlitePoolConn,
+        track_id: RowId,
+        tag_id: RowId,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM track_tags WHERE track_id = ? AND tag_id = ?")
+            .bind(track_id)
+            .bind(tag_id)
+            .execute(conn)
+            .await
+            .map(|_done| ())
+    }
+
+    /// creates the tag if needed, matching names case insensitively
+    pub async fn create_by_name(
+        tx: &mut SqliteTx<'_>,
+        track_id: RowId,
+        name: &str,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT INTO tags (name) SELECT ?
+            WHERE NOT EXISTS (SELECT 1 FROM tags WHERE name = ?)",
+        )
+        .bind(name)
+        .bind(name)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query(
+            "INSERT OR IGNORE INTO track_tags (track_id, tag_id)
+            SELECT ?, id FROM tags WHERE name = ? LIMIT 1",
+        )
+        .bind(track_id)
+        .bind(name)
+        .execute(&mut *tx)
+        .await
+        .map(|_done| ())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ArtistRole {
+    Primary,
+    Featured,
+    Remixer,
+    Producer,
+}
+
+impl ArtistRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ArtistRole::Primary => "primary",
+            ArtistRole::Featured => "featured",
+            ArtistRole::Remixer => "remixer",
+            ArtistRole::Producer => "producer",
+        }
+    }
+
+    pub fn from_str(role: &str) -> Option<Self> {
+        match role {
+            "primary" => Some(ArtistRole::Primary),
+            "featured" => Some(ArtistRole::Featured),
+            "remixer" => Some(ArtistRole::Remixer),
+            "producer" => Some(ArtistRole::Producer),
+            _ => None,
+        }
+    }
+}
+
+/// where an analysable value (BPM, key) came from
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ValueSource {
+    Tagged,
+    Detected,
+}
+
+impl ValueSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ValueSource::Tagged => "tagged",
+            ValueSource::Detected => "detected",
+        }
+    }
+
+    pub fn from_str(source: &str) -> Option<Self> {
+        match source {
+            "tagged" => Some(ValueSource::Tagged),
+            "detected" => Some(ValueSource::Detected),
+            _ => None,
+        }
+    }
+}
+
+/// an artist credited on a specific track, as opposed to the release
+#[derive(Clone, Debug)]
+pub struct TrackArtist {
+    pub artist: Artist,
+    pub role: ArtistRole,
+}
+
+impl TrackArtist {
+    pub async fn create(
+        conn: &mut SqlitePoolConn,
+        track_id: RowId,
+        artist_id: RowId,
+        role: ArtistRole,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT OR IGNORE INTO track_artists (track_id, artist_id, role)
+            VALUES (?, ?, ?)",
+        )
+        .bind(track_id)
+        .bind(artist_id)
+        .bind(role.as_str())
+        .execute(conn)
+        .await
+        .map(|_done| ())
+    }
+
+    pub async fn get_track_artists(
+        conn: &mut SqlitePoolConn,
+        track_id: RowId,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let rows = sqlx::query!(
+            "SELECT
+                track_artists.role,
+                artists.id,
+                artists.name,
+                artists.sort_name,
+                artists.created
+            FROM track_artists
+            JOIN artists ON track_artists.artist_id = artists.id
+            WHERE track_artists.track_id = ?",
+            track_id
+        )
+        .fetch_all(conn)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| TrackArtist {
+                // the column has a CHECK constraint
+                role: ArtistRole::from_str(&row.role).unwrap(),
+                artist: Artist {
+                    id: row.id,
+                    name: row.name,
+                    sort_name: row.sort_name,
+                    created: row.created,
+                },
+            })
+            .collect())
+    }
+}
+
+/// one listen of a track. it's a skip if it stopped before the play threshold
+#[derive(Clone, Debug)]
+pub struct Play {
+    pub id: RowId,
+    pub track_id: RowId,
+    /// UTC, "YYYY-MM-DD HH:MM:SS"
+    pub played_at: String,
+    /// seconds actually played
+    pub listened: f64,
+    pub skipped: bool,
+    /// the track's length in seconds, if it was known
+    pub length: Option<f64>,
+}
+
+impl Play {
+    pub async fn create(
+        conn: &mut SqlitePoolConn,
+        track_id: RowId,
+        played_at: &str,
+        listened: f64,
+        skipped: bool,
+        length: Option<f64>,
+    ) -> Result<RowId, sqlx::Error> {
+        let id = sqlx::query(
+            "INSERT INTO plays (track_id, played_at, listened, skipped, length)
+            VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(track_id)
+        .bind(played_at)
+        .bind(listened)
+        .bind(skipped)
+        .bind(length)
+        .execute(conn)
+        .await?
+        .last_insert_rowid();
+
+        Ok(id)
+    }
+
+    /// most recent first, skips included
+    pub async fn get_history(
+        conn: &mut SqlitePoolConn,
+        limit: i64,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as!(
+            Self,
+            "SELECT * FROM plays ORDER BY played_at DESC, id DESC LIMIT ?",
+            limit
+        )
+        .fetch_all(conn)
+        .await
+    }
+
+    /// a listen of the track at the same time was already recorded
+    pub async fn exists(
+        conn: &mut SqlitePoolConn,
+        track_id: RowId,
+        played_at: &str,
+    ) -> Result<bool, sqlx::Error> {
+        sqlx::query!(
+            "SELECT id FROM plays WHERE track_id = ? AND played_at = ?",
+            track_id,
+            played_at
+        )
+        .fetch_optional(conn)
+        .await
+        .map(|row| row.is_some())
+    }
+
+    /// oldest first, skips included
+    pub async fn get_all_credited(
+        conn: &mut SqlitePoolConn,
+    ) -> Result<Vec<CreditedPlay>, sqlx::Error> {
+        Ok(sqlx::query!(
+            "SELECT
+                plays.id,
+                plays.track_id,
+                plays.played_at,
+                plays.listened,
+                plays.skipped,
+                plays.length,
+                tracks.name AS title,
+                tracks.track_num,
+                releases.name AS album,
+                COALESCE(
+                    (SELECT group_concat(artists.name, ', ')
+                    FROM track_artists
+                    JOIN artists ON track_artists.artist_id = artists.id
+                    WHERE track_artists.track_id = tracks.id
+                    AND track_artists.role = 'primary'),
+                    (SELECT name FROM artists
+                    WHERE id = releases.album_artist_id),
+                    ''
+                ) AS \"artist!: String\"
+            FROM plays
+            JOIN tracks ON plays.track_id = tracks.id
+            JOIN releases ON tracks.release_id = releases.id
+            ORDER BY plays.played_at, plays.id"
+        )
+        .fetch_all(conn)
+        .await?
+        .into_iter()
+        .map(|row| CreditedPlay {
+            play: Play {
+                id: row.id,
+                track_id: row.track_id,
+                played_at: row.played_at,
+                listened: row.listened,
+                skipped: row.skipped,
+                length: row.length,
+            },
+            artist: row.artist,
+            album: row.album,
+            title: row.title,
+            track_num: row.track_num,
+        })
+        .collect())
+    }
+
+    /// track ids + play counts, most played first. only counts plays at or
+    /// after `since` if given
+    pub async fn get_most_played(
+        conn: &mut SqlitePoolConn,
+        since: Option<&str>,
+        limit: i64,
+    ) -> Result<Vec<(RowId, i64)>, sqlx::Error> {
+        Ok(sqlx::query!(
+            "SELECT track_id, COUNT(*) AS \"play_count!: i64\"
+            FROM plays
+            WHERE NOT skipped AND (? IS NULL OR played_at >= ?)
+            GROUP BY track_id
+            ORDER BY COUNT(*) DESC, MAX(played_at) DESC
+            LIMIT ?",
+            since,
+            since,
+            limit
+        )
+        .fetch_all(conn)
+        .await?
+        .into_iter()
+        .map(|row| (row.track_id, row.play_count))
+        .collect())
+    }
+}
+
+/// a play with what scrobblers need to know about the track
+#[derive(Clone, Debug)]
+pub struct CreditedPlay {
+    pub play: Play,
+    /// primary track artists, or the album artist if there aren't any
+    pub artist: String,
+    pub album: String,
+    pub title: String,
+    pub track_num: Option<RowId>,
+}
+
+// TODO store track duration
+#[derive(Clone, Debug)]
+pub struct Track {
+    pub id: RowId,
+    pub name: String,
+    pub release_id: RowId,
+    pub file_path: String,
+    pub channels: RowId,
+    pub sample_rate: RowId,
+    pub bit_depth: RowId,
+    pub track_num: Option<RowId>,
+    pub track_total: Option<RowId>,
+    pub disc_num: Option<RowId>,
+    pub disc_total: Option<RowId>,
+    pub composer: Option<String>,
+    pub bpm: Option<f64>,
+    pub bpm_source: Option<String>,
+    pub bpm_confidence: Option<f64>,
+    /// Camelot notation, see `key::MusicalKey`
+    pub key: Option<String>,
+    pub key_source: Option<String>,
+    pub key_confidence: Option<f64>,
+    pub fingerprint: Option<Vec<u8>>,
+    /// true if the last analysis couldn't estimate everything it was asked to
+    pub analysis_failed: bool,
+    pub audio_hash: Option<String>,
+    /// false if the file was missing last time it was looked for
+    pub available: bool,
+    pub file_size: Option<RowId>,
+    pub file_mtime: Option<RowId>,
+    /// comma separated, see `Track::is_locked`
+    pub locked_fields: String,
+    /// 0 - 5
+    pub rating: Option<f64>,
+    pub created: String,  // TODO parse date
+    pub modified: String, // TODO parse date
+}
+
+/// filters for `Track::query_detailed`. unset fields match everything, text
+/// fields are case insensitive
+#[derive(Clone, Debug, Default)]
+pub struct TrackQuery {
+    /// exact genre name
+    pub genre: Option<String>,
+    /// tag name, including tags nested under it
+    pub tag: Option<String>,
+    /// exact label name
+    pub label: Option<String>,
+    /// catalog number, ignoring spacing and punctuation
+    pub catalog_num: Option<String>,
+    /// substring of the composer credit
+    pub composer: Option<String>,
+    /// inclusive. tracks without a BPM never match a BPM range
+    pub bpm_min: Option<f64>,
+    pub bpm_max: Option<f64>,
+    /// keys that mix harmonically with this one, see `MusicalKey::compatible`
+    pub compatible_with: Option<MusicalKey>,
+    /// whether the file was found last time it was looked for
+    pub available: Option<bool>,
+    pub ids: Option<Vec<RowId>>,
+    /// inclusive. unrated tracks never match
+    pub rating_min: Option<f64>,
+    /// false for tracks that have never been played, skips don't count
+    pub played: Option<bool>,
+    /// last played at or after this time, e.g. "2021-03-01"
+    pub played_since: Option<String>,
+}
+
+/// every editable part of a track, see `Track::update`. start from
+/// `TrackEdit::from` a `DetailedTrack` to only change a few fields
+#[derive(Clone, Debug)]
+pub struct TrackEdit {
+    pub name: String,
+    pub release_id: RowId,
+    pub track_num: Option<RowId>,
+    pub track_total: Option<RowId>,
+    pub disc_num: Option<RowId>,
+    pub disc_total: Option<RowId>,
+    pub composer: Option<String>,
+    pub bpm: Option<f64>,
+    pub key: Option<MusicalKey>,
+    pub artists: Vec<(RowId, ArtistRole)>,
+    pub genres: Vec<String>,
+    pub tags: Vec<String>,
+}
+
+impl From<&DetailedTrack> for TrackEdit {
+    fn from(t: &DetailedTrack) -> Self {
+        let mut genres: Vec<_> =
+            t.genres.iter().map(|g| g.name.clone()).collect();
+        genres.sort();
+        let mut tags: Vec<_> = t.tags.iter().map(|t| t.name.clone()).collect();
+        tags.sort();
+        TrackEdit {
+            name: t.name.clone(),
+            release_id: t.release.id,
+            track_num: t.track_num,
+            track_total: t.track_total,
+            disc_num: t.disc_num,
+            disc_total: t.disc_total,
+            composer: t.composer.clone(),
+            bpm: t.bpm,
+            key: t.key,
+            artists: t
+                .track_artists
+                .iter()
+                .map(|ta| (ta.artist.id, ta.role))
+                .collect(),
+            genres,
+            tags,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct WatchedDir {
+    pub id: RowId,
+    pub path: String,
+    pub last_scanned: Option<String>, // TODO parse date
+    pub created: String,              // TODO parse date
+}
+
+impl WatchedDir {
+    /// returns the existing entry if `path` is already watched
+    pub async fn find_or_create(
+        conn: &mut SqlitePoolConn,
+        path: &str,
+    ) -> Result<Self, sqlx::Error> {
+        sqlx::query("INSERT OR IGNORE INTO watched_dirs (path) VALUES (?)")
+            .bind(path)
+            .execute(conn.borrow_mut())
+            .await?;
+
+        sqlx::query_as!(Self, "SELECT * FROM watched_dirs WHERE path = ?", path)
+            .fetch_one(conn.borrow_mut())
+            .await
+    }
+
+    pub async fn get_all(
+        conn: &mut SqlitePoolConn,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as!(Self, "SELECT * FROM watched_dirs ORDER BY path")
+            .fetch_all(conn)
+            .await
+    }
+
+    /// tracks already imported from the dir stay in the library
+    pub async fn delete(
+        conn: &mut SqlitePoolConn,
+        id: RowId,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM watched_dirs WHERE id = ?")
+            .bind(id)
+            .execute(conn)
+            .await
+            .map(|_done| ())
+    }
+
+    pub async fn set_scanned(
+        conn: &mut SqlitePoolConn,
+        id: RowId,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "UPDATE watched_dirs SET last_scanned = CURRENT_TIMESTAMP
+            WHERE id = ?",
+        )
+        .bind(id)
+        .execute(conn)
+        .await
+        .map(|_done| ())
+    }
+}
+
+// edit log values are text, see migrations/*_edit_log.sql
+fn log_text<T: ToString>(value: Option<T>) -> Option<String> {
+    value.map(|v| v.to_string())
+}
+
+fn log_list<T: ToString>(items: &[T]) -> Option<String> {
+    Some(
+        items
+            .iter()
+            .map(|i| i.to_string())
+            .collect::<Vec<_>>()
+            .join("\n"),
+    )
+}
+
+fn log_lines(value: Option<&str>) -> impl Iterator<Item = &str> {
+    value.unwrap_or("").lines().filter(|l| !l.is_empty())
+}
+
+// columns that can be restored from the edit log as is
+const TRACK_LOGGED: &[&str] = &[
+    "name",
+    "release_id",
+    "track_num",
+    "track_total",
+    "disc_num",
+    "disc_total",
+    "composer",
+    "bpm",
+    "bpm_source",
+    "bpm_confidence",
+    "key",
+    "key_source",
+    "key_confidence",
+    "locked_fields",
+    "file_path",
+    "available",
+    "rating",
+];
+const RELEASE_LOGGED: &[&str] = &[
+    "name",
+    "date",
+    "album_artist_id",
+    "label_id",
+    "catalog_num",
+    "barcode",
+    "release_type",
+    "edition",
+];
+
+/// where an action is in the undo history
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EditState {
+    Done,
+    Undone,
+    /// undone and then followed by a new action, so it can't be redone
+    Discarded,
+}
+
+impl EditState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EditState::Done => "done",
+            EditState::Undone => "undone",
+            EditState::Discarded => "discarded",
+        }
+    }
+
+    pub fn from_str(state: &str) -> Option<Self> {
+        match state {
+            "done" => Some(EditState::Done),
+            "undone" => Some(EditState::Undone),
+            "discarded" => Some(EditState::Discarded),
+            _ => None,
+        }
+    }
+}
+
+/// one user action in the edit history, made up of `EditLogEntry`s. every
+/// edit is logged except `Track::merge`. analysis results and changes found
+/// on disk aren't edits
+#[derive(Clone, Debug)]
+pub struct EditGroup {
+    pub id: RowId,
+    pub description: String,
+    pub state: String,
+    pub created: String, // TODO parse date
+}
+
+/// a single changed field. entity is "track", "release", "artist" or "tag"
+#[derive(Clone, Debug)]
+pub struct EditLogEntry {
+    pub id: RowId,
+    pub group_id: RowId,
+    pub entity: String,
+    pub entity_id: RowId,
+    pub field: String,
+    pub before: Option<String>,
+    pub after: Option<String>,
+    pub created: String, // TODO parse date
+}
+
+/// an `EditGroup` with its changes, for showing the history
+#[derive(Debug)]
+pub struct EditAction {
+    pub id: RowId,
+    pub description: String,
+    pub state: EditState,
+    pub created: String, // TODO parse date
+    pub changes: Vec<EditLogEntry>,
+}
+
+impl EditGroup {
+    /// starts logging a new action. anything left to redo can't be anymore
+    pub async fn create(
+        tx: &mut SqliteTx<'_>,
+        description: &str,
+    ) -> Result<RowId, sqlx::Error> {
+        sqlx::query(
+            "UPDATE edit_groups SET state = 'discarded' WHERE state = 'undone'",
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        let id =
+            sqlx::query("INSERT INTO edit_groups (description) VALUES (?)")
+                .bind(description)
+                .execute(&mut *tx)
+                .await?
+                .last_insert_rowid();
+
+        Ok(id)
+    }
+
+    /// the latest action that hasn't been undone
+    pub async fn get_undoable(
+        conn: &mut SqlitePoolConn,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as!(
+            Self,
+            "SELECT * FROM edit_groups WHERE state = 'done'
+            ORDER BY id DESC LIMIT 1"
+        )
+        .fetch_optional(conn)
+        .await
+    }
+
+    /// the earliest undone action, redone in the order they happened
+    pub async fn get_redoable(
+        conn: &mut SqlitePoolConn,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as!(
+            Self,
+            "SELECT * FROM edit_groups WHERE state = 'undone'
+            ORDER BY id ASC LIMIT 1"
+        )
+        .fetch_optional(conn)
+        .await
+    }
+
+    /// newest first
+    pub async fn get_history(
+        conn: &mut SqlitePoolConn,
+        limit: i64,
+    ) -> Result<Vec<EditAction>, sqlx::Error> {
+        let groups = sqlx::query_as!(
+            Self,
+            "SELECT * FROM edit_groups ORDER BY id DESC LIMIT ?",
+            limit
+        )
+        .fetch_all(conn.borrow_mut())
+        .await?;
+
+        let mut history = Vec::with_capacity(groups.len());
+        for group in groups {
+            history.push(EditAction {
+                id: group.id,
+                state: EditState::from_str(&group.state)
+                    .unwrap_or(EditState::Done),
+                changes: EditLogEntry::get_group(conn, group.id).await?,
+                description: group.description,
+                created: group.created,
+            });
+        }
+        Ok(history)
+    }
+
+    /// restores the before values of the group's changes in reverse order
+    /// (undo), or the after values in order (redo), in one transaction.
+    /// returns the changes
+    pub async fn replay(
+        conn: &mut SqlitePoolConn,
+        id: RowId,
+        undo: bool,
+    ) -> Result<Vec<EditLogEntry>, sqlx::Error> {
+        let entries = EditLogEntry::get_group(conn, id).await?;
+        let state = match undo {
+            true => EditState::Undone,
+            false => EditState::Done,
+        };
+
+        let mut tx = conn.begin().await?;
+        match undo {
+            true => {
+                for entry in entries.iter().rev() {
+                    entry.apply(&mut tx, entry.before.as_deref()).await?;
+                }
+            }
+            false => {
+                for entry in entries.iter() {
+                    entry.apply(&mut tx, entry.after.as_deref()).await?;
+                }
+            }
+        }
+        sqlx::query("UPDATE edit_groups SET state = ? WHERE id = ?")
+            .bind(state.as_str())
+            .bind(id)
+            .execute(&mut tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(entries)
+    }
+}
+
+impl EditLogEntry {
+    /// does nothing if the value didn't change
+    pub async fn record(
+        tx: &mut SqliteTx<'_>,
+        group_id: RowId,
+        entity: &str,
+        entity_id: RowId,
+        field: &str,
+        before: Option<String>,
+        after: Option<String>,
+    ) -> Result<(), sqlx::Error> {
+        if before == after {
+            return Ok(());
+        }
+
+        sqlx::query(
+            "INSERT INTO edit_log
+            (group_id, entity, entity_id, field, before, after)
+            VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(group_id)
+        .bind(entity)
+        .bind(entity_id)
+        .bind(field)
+        .bind(before)
+        .bind(after)
+        .execute(&mut *tx)
+        .await
+        .map(|_done| ())
+    }
+
+    pub async fn get_group(
+        conn: &mut SqlitePoolConn,
+        group_id: RowId,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as!(
+            Self,
+            "SELECT * FROM edit_log WHERE group_id = ? ORDER BY id",
+            group_id
+        )
+        .fetch_all(conn)
+        .await
+    }
+
+    // sets the field to `value`, which came from this or an earlier entry
+    async fn apply(
+        &self,
+        tx: &mut SqliteTx<'_>,
+        value: Option<&str>,
+    ) -> Result<(), sqlx::Error> {
+        Self::apply_value(tx, &self.entity, self.entity_id, &self.field, value)
+            .await
+    }
+
+    async fn apply_value(
+        tx: &mut SqliteTx<'_>,
+        entity: &str,
+        id: RowId,
+        field: &str,
+        value: Option<&str>,
+    ) -> Result<(), sqlx::Error> {
+        match (entity, field) {
+            // sqlite converts the text to the column's type
+            ("track", field) if TRACK_LOGGED.contains(&field) => {
+                let update =
+                    format!("UPDATE tracks SET {} = ? WHERE id = ?", field);
+                sqlx::query(&update)
+                    .bind(value)
+                    .bind(id)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            ("track", "genres") => {
+                sqlx::query("DELETE FROM track_genres WHERE track_id = ?")
+                    .bind(id)
+                    .execute(&mut *tx)
+                    .await?;
+                for name in log_lines(value) {
+                    TrackGenre::create_by_name(tx, id, name).await?;
+                }
+            }
+            ("track", "tags") => {
+                sqlx::query("DELETE FROM track_tags WHERE track_id = ?")
+                    .bind(id)
+                    .execute(&mut *tx)
+                    .await?;
+                for name in log_lines(value) {
+                    TrackTag::create_by_name(tx, id, name).await?;
+                }
+            }
+            ("track", "artists") => {
+                sqlx::query("DELETE FROM track_artists WHERE track_id = ?")
+                    .bind(id)
+                    .execute(&mut *tx)
+                    .await?;
+                // "artist_id:role"
+                for credit in log_lines(value) {
+                    let mut parts = credit.splitn(2, ':');
+                    let artist_id =
+                        parts.next().and_then(|a| a.parse::<RowId>().ok());
+                    let role = parts.next().and_then(ArtistRole::from_str);
+                    if let (Some(artist_id), Some(role)) = (artist_id, role) {
+                        sqlx::query(
+                            "INSERT OR IGNORE INTO track_artists
+                            (track_id, artist_id, role)
+                            VALUES (?, ?, ?)",
+                        )
+                        .bind(id)
+                        .bind(artist_id)
+                        .bind(role.as_str())
+                        .execute(&mut *tx)
+                        .await?;
+                    }
+                }
+            }
+            // the release's existence, with its name as the value. other
+            // columns are logged before it's deleted, and restored after
+            ("release", "row") => match value {
+                Some(name) => {
+                    sqlx::query(
+                        "INSERT OR IGNORE INTO releases (id, name)
+                        VALUES (?, ?)",
+                    )
+                    .bind(id)
+                    .bind(name)
+                    .execute(&mut *tx)
+                    .await?;
+                }
+                None => {
+                    sqlx::query("DELETE FROM releases WHERE id = ?")
+                        .bind(id)
+                        .execute(&mut *tx)
+                        .await?;
+                }
+            },
+            ("release", field) if RELEASE_LOGGED.contains(&field) => {
+                let update =
+                    format!("UPDATE releases SET {} = ? WHERE id = ?", field);
+                sqlx::query(&update)
+                    .bind(value)
+                    .bind(id)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            ("release", "artists") => {
+                sqlx::query("DELETE FROM artist_releases WHERE release_id = ?")
+                    .bind(id)
+                    .execute(&mut *tx)
+                    .await?;
+                for artist_id in log_lines(value) {
+                    sqlx::query(
+                        "INSERT OR IGNORE INTO artist_releases
+                        (artist_id, release_id)
+                        VALUES (?, ?)",
+                    )
+                    .bind(artist_id)
+                    .bind(id)
+                    .execute(&mut *tx)
+                    .await?;
+                }
+            }
+            ("artist", "name") => {
+                let name = value.unwrap_or_default();
+                Artist::set_name(&mut *tx, id, name).await?;
+            }
+            ("artist", "sort_name") => {
+                sqlx::query("UPDATE artists SET sort_name = ? WHERE id = ?")
+                    .bind(value)
+                    .bind(id)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            // the artist's existence, with its name as the value. it's kept
+            // if it's been credited since
+            ("artist", "row") => match value {
+                Some(name) => {
+                    sqlx::query(
+                        "INSERT OR IGNORE INTO artists (id, name, match_name)
+                        VALUES (?, ?, ?)",
+                    )
+                    .bind(id)
+                    .bind(name)
+                    .bind(fold_artist_name(name))
+                    .execute(&mut *tx)
+                    .await?;
+                }
+                None => {
+                    sqlx::query(
+                        "DELETE FROM artists WHERE id = ?
+                        AND id NOT IN (SELECT artist_id FROM track_artists)
+                        AND id NOT IN (SELECT artist_id FROM artist_releases)
+                        AND id NOT IN (SELECT artist_id FROM artist_aliases)
+                        AND id NOT IN (
+                            SELECT album_artist_id FROM releases
+                            WHERE album_artist_id IS NOT NULL
+                        )",
+                    )
+                    .bind(id)
+                    .execute(&mut *tx)
+                    .await?;
+                }
+            },
+            // alias names, one per line. they're moved from other artists
+            ("artist", "aliases") => {
+                let names: Vec<&str> = log_lines(value).collect();
+                let current = sqlx::query!(
+                    "SELECT name FROM artist_aliases WHERE artist_id = ?",
+                    id
+                )
+                .fetch_all(&mut *tx)
+                .await?;
+                for row in current {
+                    if !names.contains(&row.name.as_str()) {
+                        sqlx::query(
+                            "DELETE FROM artist_aliases WHERE name = ?",
+                        )
+                        .bind(&row.name)
+                        .execute(&mut *tx)
+                        .await?;
+                    }
+                }
+                for name in names {
+                    sqlx::query(
+                        "INSERT INTO artist_aliases
+                        (artist_id, name, match_name)
+                        SELECT id, ?, ? FROM artists WHERE id = ?
+                        ON CONFLICT(name) DO UPDATE
+                        SET artist_id = excluded.artist_id",
+                    )
+                    .bind(name)
+                    .bind(fold_artist_name(name))
+                    .bind(id)
+                    .execute(&mut *tx)
+                    .await?;
+                }
+            }
+            // like releases. anything added to the tag since is let go of
+            // before it's deleted again
+            ("tag", "row") => match value {
+                Some(name) => {
+                    sqlx::query(
+                        "INSERT OR IGNORE INTO tags (id, name) VALUES (?, ?)",
+                    )
+                    .bind(id)
+                    .bind(name)
+                    .execute(&mut *tx)
+                    .await?;
+                }
+                None => {
+                    for delete_query in &[
+                        "UPDATE tags SET parent_id = NULL WHERE parent_id = ?",
+                        "DELETE FROM track_tags WHERE tag_id = ?",
+                        "DELETE FROM tags WHERE id = ?",
+                    ] {
+                        sqlx::query(delete_query)
+                            .bind(id)
+                            .execute(&mut *tx)
+                            .await?;
+                    }
+                }
+            },
+            // another tag may have taken the name since
+            ("tag", "name") => {
+                sqlx::query("UPDATE OR IGNORE tags SET name = ? WHERE id = ?")
+                    .bind(value)
+                    .bind(id)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            ("tag", "color") => {
+                sqlx::query("UPDATE tags SET color = ? WHERE id = ?")
+                    .bind(value)
+                    .bind(id)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            // the parent may have been deleted since
+            ("tag", "parent_id") => {
+                sqlx::query(
+                    "UPDATE tags
+                    SET parent_id = (SELECT id FROM tags WHERE id = ?)
+                    WHERE id = ?",
+                )
+                .bind(value)
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+            }
+            // track ids, one per line. tracks deleted since are skipped
+            ("tag", "tracks") => {
+                sqlx::query("DELETE FROM track_tags WHERE tag_id = ?")
+                    .bind(id)
+                    .execute(&mut *tx)
+                    .await?;
+                for track_id in log_lines(value) {
+                    sqlx::query(
+                        "INSERT OR IGNORE INTO track_tags (track_id, tag_id)
+                        SELECT tracks.id, tags.id FROM tracks, tags
+                        WHERE tracks.id = ? AND tags.id = ?",
+                    )
+                    .bind(track_id)
+                    .bind(id)
+                    .execute(&mut *tx)
+                    .await?;
+                }
+            }
+            // only fields handled above are ever recorded
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+// higher is better. lossy formats are parsed with a bit depth of 0, missing
+// files rank last
+fn quality_rank(track: &Track) -> (bool, u8, RowId, RowId) {
+    let format_rank =
+        match parse::detect_format(std::path::Path::new(&track.file_path)) {
+            Some(parse::AudioFormat::Flac) => 3,
+            Some(parse::AudioFormat::Mp4) => 2,
+            Some(parse::AudioFormat::Aiff) | Some(parse::AudioFormat::Wav) => 1,
+            Some(_) => 0,
+            None => return (false, 0, 0, 0),
+        };
+
+    (
+        track.bit_depth > 0,
+        format_rank,
+        track.bit_depth,
+        track.sample_rate,
+    )
+}
+
+// TODO how significant of an impact on memory does duplicating Artist, Release,
+// and Tags per track have? performance?
+// - use [A]RC to reference count and share memory
+// - for Vec<DetailedTrack> etc,  wrap + store Artists/Releases/Tags and use
+// refs in this struct
+// - stack allocated str (e.g inlinable_string) for performance
+#[derive(Debug)]
+pub struct DetailedTrack {
+    pub id: RowId,
+    pub name: String,
+    pub release: Release,
+    pub artists: Vec<Artist>,
+    pub track_artists: Vec<TrackArtist>,
+    pub tags: Vec<Tag>,
+    pub genres: Vec<Genre>,
+    pub label: Option<Label>,
+    pub composer: Option<String>,
+    pub bpm: Option<f64>,
+    pub bpm_source: Option<ValueSource>,
+    pub bpm_confidence: Option<f64>,
+    pub key: Option<MusicalKey>,
+    pub key_source: Option<ValueSource>,
+    pub key_confidence: Option<f64>,
+    pub file_path: String,
+    pub available: bool,
+    pub channels: RowId,
+    pub sample_rate: RowId,
+    pub bit_depth: RowId,
+    pub track_num: Option<RowId>,
+    pub track_total: Option<RowId>,
+    pub disc_num: Option<RowId>,
+    pub disc_total: Option<RowId>,
+    pub rating: Option<f64>,
+    /// skips don't count, see `Play`
+    pub play_count: i64,
+    pub skip_count: i64,
+    pub last_played: Option<String>,
+    pub created: String,  // TODO parse date
+    pub modified: String, // TODO parse date
+}
+
+impl Track {
+    pub async fn create(
+        conn: &mut SqlitePoolConn,
+        name: &str,
+        release_id: RowId,
+        file_path: &str,
+        channels: RowId,
+        sample_rate: RowId,
+        bit_depth: RowId,
+        track_num: Option<RowId>,
+        track_total: Option<RowId>,
+        disc_num: Option<RowId>,
+        disc_total: Option<RowId>,
+        composer: Option<&str>,
+        bpm: Option<f64>,
+        key: Option<MusicalKey>,
+        audio_hash: Option<&str>,
+        file_size: Option<RowId>,
+        file_mtime: Option<RowId>,
+    ) -> Result<RowId, sqlx::Error> {
+        let track_id = sqlx::query(
+            "INSERT INTO tracks
+            (name, release_id, file_path, channels, sample_rate, bit_depth,
+            track_num, track_total, disc_num, disc_total, composer, bpm,
+            bpm_source, key, key_source, audio_hash, file_size, file_mtime)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(name)
+        .bind(release_id)
+        .bind(file_path)
+        .bind(channels)
+        .bind(sample_rate)
+        .bind(bit_depth)
+        .bind(track_num)
+        .bind(track_total)
+        .bind(disc_num)
+        .bind(disc_total)
+        .bind(composer)
+        .bind(bpm)
+        .bind(bpm.map(|_| ValueSource::Tagged.as_str()))
+        .bind(key.map(|k| k.to_camelot()))
+        .bind(key.map(|_| ValueSource::Tagged.as_str()))
+        .bind(audio_hash)
+        .bind(file_size)
+        .bind(file_mtime)
+        .execute(conn.borrow_mut())
+        .await?
+        .last_insert_rowid();
+
+        Ok(track_id)
+    }
+
+    pub async fn get(
+        conn: &mut SqlitePoolConn,
+        id: RowId,
+    ) -> Result<Self, sqlx::Error> {
+        sqlx::query_as!(Self, "SELECT * FROM tracks WHERE id = ?", id)
+            .fetch_one(conn)
+            .await
+    }
+
+    pub async fn get_all(
+        conn: &mut SqlitePoolConn,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as!(Self, "SELECT * FROM tracks;")
+            .fetch_all(conn)
+            .await
+    }
+
+    /// the track at `path`, or every track under it if it's a dir
+    pub async fn get_under_path(
+        conn: &mut SqlitePoolConn,
+        path: &str,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        // paths can contain LIKE's wildcards
+        let escaped = path
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_");
+        sqlx::query_as!(
+            Self,
+            "SELECT * FROM tracks
+            WHERE file_path = ? OR file_path LIKE ? || '/%' ESCAPE '\\'",
+            path,
+            escaped
+        )
+        .fetch_all(conn)
+        .await
+    }
+
+    pub async fn get_by_path(
+        conn: &mut SqlitePoolConn,
+        file_path: &str,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as!(
+            Self,
+            "SELECT * FROM tracks WHERE file_path = ?",
+            file_path
+        )
+        .fetch_optional(conn)
+        .await
+    }
+
+    /// applies a user's edit. changed fields are locked, see `is_locked`, so
+    /// rescans don't undo them. BPM and key set by hand count as tagged.
+    /// logged for undo, returns the `EditGroup` id
+    pub async fn update(
+        conn: &mut SqlitePoolConn,
+        id: RowId,
+        edit: &TrackEdit,
+    ) -> Result<RowId, sqlx::Error> {
+        Self::update_many(conn, &[(id, edit.clone())], "edit track").await
+    }
+
+    /// `update` for many tracks at once, in a single transaction and
+    /// `EditGroup`
+    pub async fn update_many(
+        conn: &mut SqlitePoolConn,
+        edits: &[(RowId, TrackEdit)],
+        description: &str,
+    ) -> Result<RowId, sqlx::Error> {
+        // current values are all read before anything is written
+        let mut current = Vec::with_capacity(edits.len());
+        for (id, _) in edits.iter() {
+            let t = Self::get(conn, *id).await?;
+            let mut genres: Vec<_> = Genre::get_track_genres(conn, *id)
+                .await?
+                .into_iter()
+                .map(|g| g.name)
+                .collect();
+            genres.sort();
+            let mut tags: Vec<_> = sqlx::query!(
+                "SELECT tags.name FROM track_tags
+                JOIN tags ON track_tags.tag_id = tags.id
+                WHERE track_tags.track_id = ?",
+                id
+            )
+            .fetch_all(conn.borrow_mut())
+            .await?
+            .into_iter()
+            .map(|row| row.name)
+            .collect();
+            tags.sort();
+            let artists: Vec<_> = TrackArtist::get_track_artists(conn, *id)
+                .await?
+                .into_iter()
+                .map(|ta| format!("{}:{}", ta.artist.id, ta.role.as_str()))
+                .collect();
+            current.push((t, genres, tags, artists));
+        }
+
+        let tagged = Some(ValueSource::Tagged.as_str().to_owned());
+        let mut emptied = Vec::new();
+        let mut tx = conn.begin().await?;
+        let group_id = EditGroup::create(&mut tx, description).await?;
+
+        for ((id, edit), (t, genres, tags, artists)) in
+            edits.iter().zip(current.iter())
+        {
+            let mut edit_genres = edit.genres.clone();
+            edit_genres.sort();
+            let mut edit_tags = edit.tags.clone();
+            edit_tags.sort();
+            let edit_artists: Vec<_> = edit
+                .artists
+                .iter()
+                .map(|(artist_id, role)| {
+                    format!("{}:{}", artist_id, role.as_str())
+                })
+                .collect();
+            let key = t.key.as_deref().and_then(MusicalKey::from_camelot);
+
+            let mut locked: Vec<_> = t
+                .locked_fields
+                .split(',')
+                .filter(|f| !f.is_empty())
+                .map(|f| f.to_owned())
+                .collect();
+            let changes = [
+                ("name", t.name != edit.name),
+                ("track_num", t.track_num != edit.track_num),
+                ("track_total", t.track_total != edit.track_total),
+                ("disc_num", t.disc_num != edit.disc_num),
+                ("disc_total", t.disc_total != edit.disc_total),
+                ("composer", t.composer != edit.composer),
+                ("bpm", t.bpm != edit.bpm),
+                ("key", key != edit.key),
+                ("genres", *genres != edit_genres),
+            ];
+            for (field, changed) in changes.iter() {
+                if *changed && !locked.iter().any(|l| l == field) {
+                    locked.push(field.to_string());
+                }
+            }
+
+            let mut updated = t.clone();
+            updated.name = edit.name.clone();
+            updated.release_id = edit.release_id;
+            updated.track_num = edit.track_num;
+            updated.track_total = edit.track_total;
+            updated.disc_num = edit.disc_num;
+            updated.disc_total = edit.disc_total;
+            updated.composer = edit.composer.clone();
+            if t.bpm != edit.bpm {
+                updated.bpm = edit.bpm;
+                updated.bpm_source = edit.bpm.and(tagged.clone());
+                updated.bpm_confidence = None;
+            }
+            if key != edit.key {
+                updated.key = edit.key.map(|k| k.to_camelot());
+                updated.key_source = edit.key.and(tagged.clone());
+                updated.key_confidence = None;
+            }
+            updated.locked_fields = locked.join(",");
+
+            sqlx::query(
+                "UPDATE tracks SET
+                    name = ?, release_id = ?, track_num = ?, track_total = ?,
+                    disc_num = ?, disc_total = ?, composer = ?,
+                    bpm = ?, bpm_source = ?, bpm_confidence = ?,
+                    key = ?, key_source = ?, key_confidence = ?,
+                    locked_fields = ?
+                WHERE id = ?",
+            )
+            .bind(&updated.name)
+            .bind(updated.release_id)
+            .bind(updated.track_num)
+            .bind(updated.track_total)
+            .bind(updated.disc_num)
+            .bind(updated.disc_total)
+            .bind(&updated.composer)
+            .bind(updated.bpm)
+            .bind(&updated.bpm_source)
+            .bind(updated.bpm_confidence)
+            .bind(&updated.key)
+            .bind(&updated.key_source)
+            .bind(updated.key_confidence)
+            .bind(&updated.locked_fields)
+            .bind(id)
+            .execute(&mut tx)
+            .await?;
+            t.log_changes(&mut tx, group_id, &updated).await?;
+
+            if *artists != edit_artists {
+                sqlx::query("DELETE FROM track_artists WHERE track_id = ?")
+                    .bind(id)
+                    .execute(&mut tx)
+                    .await?;
+                for (artist_id, role) in edit.artists.iter() {
+                    sqlx::query(
+                        "INSERT OR IGNORE INTO track_artists
+                        (track_id, artist_id, role)
+                        VALUES (?, ?, ?)",
+                    )
+                    .bind(id)
+                    .bind(artist_id)
+                    .bind(role.as_str())
+                    .execute(&mut tx)
+                    .await?;
+                }
+            }
+
+            if *genres != edit_genres {
+                sqlx::query("DELETE FROM track_genres WHERE track_id = ?")
+                    .bind(id)
+                    .execute(&mut tx)
+                    .await?;
+                for name in edit.genres.iter() {
+                    TrackGenre::create_by_name(&mut tx, *id, name).await?;
+                }
+            }
+
+            if *tags != edit_tags {
+                sqlx::query("DELETE FROM track_tags WHERE track_id = ?")
+                    .bind(id)
+                    .execute(&mut tx)
+                    .await?;
+                for name in edit.tags.iter() {
+                    TrackTag::create_by_name(&mut tx, *id, name).await?;
+                }
+            }
+
+            let lists = [
+                ("artists", artists, &edit_artists),
+                ("genres", genres, &edit_genres),
+                ("tags", tags, &edit_tags),
+            ];
+            for (field, before, after) in lists.iter() {
+                EditLogEntry::record(
+                    &mut tx,
+                    group_id,
+                    "track",
+                    *id,
+                    field,
+                    log_list(before),
+                    log_list(after),
+                )
+                .await?;
+            }
+
+            if t.release_id != edit.release_id {
+                emptied.push(t.release_id);
+            }
+        }
+
+        // releases the tracks were moved out of may be empty now
+        emptied.sort();
+        emptied.dedup();
+        for release_id in emptied {
+            Release::delete_if_empty(&mut tx, group_id, release_id).await?;
+        }
+
+        tx.commit().await?;
+        Ok(group_id)
+    }
+
+    // logs the columns that differ in `updated`, see `TRACK_LOGGED`
+    async fn log_changes(
+        &self,
+        tx: &mut SqliteTx<'_>,
+        group_id: RowId,
+        updated: &Self,
+    ) -> Result<(), sqlx::Error> {
+        let columns = [
+            ("name", Some(&self.name), Some(&updated.name)),
+            (
+                "composer",
+                self.composer.as_ref(),
+                updated.composer.as_ref(),
+            ),
+            (
+                "bpm_source",
+                self.bpm_source.as_ref(),
+                updated.bpm_source.as_ref(),
+            ),
+            ("key", self.key.as_ref(), updated.key.as_ref()),
+            (
+                "key_source",
+                self.key_source.as_ref(),
+                updated.key_source.as_ref(),
+            ),
+            (
+                "locked_fields",
+                Some(&self.locked_fields),
+                Some(&updated.locked_fields),
+            ),
+            ("file_path", Some(&self.file_path), Some(&updated.file_path)),
+        ];
+        let numbers = [
+            (
+                "release_id",
+                Some(self.release_id),
+                Some(updated.release_id),
+            ),
+            ("track_num", self.track_num, updated.track_num),
+            ("track_total", self.track_total, updated.track_total),
+            ("disc_num", self.disc_num, updated.disc_num),
+            ("disc_total", self.disc_total, updated.disc_total),
+            (
+                "available",
+                Some(self.available as RowId),
+                Some(updated.available as RowId),
+            ),
+        ];
+        let reals = [
+            ("bpm", self.bpm, updated.bpm),
+            (
+                "bpm_confidence",
+                self.bpm_confidence,
+                updated.bpm_confidence,
+            ),
+            (
+                "key_confidence",
+                self.key_confidence,
+                updated.key_confidence,
+            ),
+            ("rating", self.rating, updated.rating),
+        ];
+
+        let changes = columns
+            .iter()
+            .map(|(f, before, after)| (f, log_text(*before), log_text(*after)))
+            .chain(
+                numbers
+                    .iter()
+                    .map(|(f, b, a)| (f, log_text(*b), log_text(*a))),
+            )
+            .chain(
+                reals
+                    .iter()
+                    .map(|(f, b, a)| (f, log_text(*b), log_text(*a))),
+            );
+        for (field, before, after) in changes {
+            EditLogEntry::record(
+                tx, group_id, "track", self.id, field, before, after,
+            )
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// after the file was rewritten, so it isn't seen as changed on disk
+    pub async fn set_file_stat(
+        conn: &mut SqlitePoolConn,
+        id: RowId,
+        file_size: RowId,
+        file_mtime: RowId,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "UPDATE tracks SET file_size = ?, file_mtime = ? WHERE id = ?",
+        )
+        .bind(file_size)
+        .bind(file_mtime)
+        .bind(id)
+        .execute(conn)
+        .await
+        .map(|_done| ())
+    }
+
+    /// whether the user edited `field` by hand, in which case it shouldn't be
+    /// replaced by what's in the file
+    pub fn is_locked(&self, field: &str) -> bool {
+        self.locked_fields.split(',').any(|f| f == field)
+    }
+
+    /// refreshes a track from its changed file. fields the user edited are
+    /// kept, see `is_locked`. the release and artists are left alone, moving
+    /// a track between releases is up to the user. if the audio itself
+    /// changed, detected values + the fingerprint are cleared to be
+    /// analyzed again
+    pub async fn update_from_parse(
+        conn: &mut SqlitePoolConn,
+        id: RowId,
+        metadata: &parse::ParseResult,
+    ) -> Result<(), sqlx::Error> {
+        let mut t = Self::get(conn, id).await?;
+        let tagged = Some(ValueSource::Tagged.as_str().to_owned());
+        let detected = Some(ValueSource::Detected.as_str().to_owned());
+
+        if !t.is_locked("name") {
+            t.name = metadata.track.clone();
+        }
+        if !t.is_locked("track_num") {
+            t.track_num = metadata.track_pos.map(|n| n as RowId);
+        }
+        if !t.is_locked("track_total") {
+            t.track_total = metadata.track_total.map(|n| n as RowId);
+        }
+        if !t.is_locked("disc_num") {
+            t.disc_num = metadata.disc_pos.map(|n| n as RowId);
+        }
+        if !t.is_locked("disc_total") {
+            t.disc_total = metadata.disc_total.map(|n| n as RowId);
+        }
+        if !t.is_locked("composer") {
+            t.composer = metadata.composer.clone();
+        }
+
+        let audio_changed = t.audio_hash != metadata.audio_hash;
+        if audio_changed {
+            t.fingerprint = None;
+            t.analysis_failed = false;
+            if t.bpm_source == detected {
+                t.bpm = None;
+                t.bpm_source = None;
+                t.bpm_confidence = None;
+            }
+            if t.key_source == detected {
+                t.key = None;
+                t.key_source = None;
+                t.key_confidence = None;
+            }
+        }
+
+        // a removed tag leaves the value to be detected
+        if !t.is_locked("bpm") {
+            match metadata.bpm {
+                Some(bpm) => {
+                    t.bpm = Some(bpm);
+                    t.bpm_source = tagged.clone();
+                    t.bpm_confidence = None;
+                }
+                None if t.bpm_source == tagged => {
+                    t.bpm = None;
+                    t.bpm_source = None;
+                }
+                None => (),
+            }
+        }
+        if !t.is_locked("key") {
+            match metadata.key.as_deref().and_then(MusicalKey::parse) {
+                Some(key) => {
+                    t.key = Some(key.to_camelot());
+                    t.key_source = tagged.clone();
+                    t.key_confidence = None;
+                }
+                None if t.key_source == tagged => {
+                    t.key = None;
+                    t.key_source = None;
+                }
+                None => (),
+            }
+        }
+
+        sqlx::query(
+            "UPDATE tracks SET
+                name = ?, track_num = ?, track_total = ?, disc_num = ?,
+                disc_total = ?, composer = ?, bpm = ?, bpm_source = ?,
+                bpm_confidence = ?, key = ?, key_source = ?,
+                key_confidence = ?, fingerprint = ?, channels = ?,
+                sample_rate = ?, bit_depth = ?, audio_hash = ?,
+                file_size = ?, file_mtime = ?, analysis_failed = ?,
+                available = 1
+            WHERE id = ?",
+        )
+        .bind(&t.name)
+        .bind(t.track_num)
+        .bind(t.track_total)
+        .bind(t.disc_num)
+        .bind(t.disc_total)
+        .bind(&t.composer)
+        .bind(t.bpm)
+        .bind(&t.bpm_source)
+        .bind(t.bpm_confidence)
+        .bind(&t.key)
+        .bind(&t.key_source)
+        .bind(t.key_confidence)
+        .bind(&t.fingerprint)
+        .bind(metadata.channels as RowId)
+        .bind(metadata.sample_rate as RowId)
+        .bind(metadata.bit_depth as RowId)
+        .bind(&metadata.audio_hash)
+        .bind(metadata.file_size.map(|s| s as RowId))
+        .bind(metadata.file_mtime)
+        .bind(t.analysis_failed)
+        .bind(id)
+        .execute(conn.borrow_mut())
+        .await?;
+
+        if !t.is_locked("genres") {
+            sqlx::query("DELETE FROM track_genres WHERE track_id = ?")
+                .bind(id)
+                .execute(conn.borrow_mut())
+                .await?;
+            for name in metadata.genres.iter() {
+                let genre = Genre::find_or_create(conn, name).await?;
+                TrackGenre::create(conn, id, genre.id).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// tracklist for a release in (disc, track) order. untagged discs are
+    /// treated as the first disc, untagged tracks go last
+    pub async fn get_release_tracks(
+        conn: &mut SqlitePoolConn,
+        release_id: RowId,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as!(
+            Self,
+            "SELECT * FROM tracks
+            WHERE release_id = ?
+            ORDER BY
+                COALESCE(disc_num, 1),
+                track_num IS NULL,
+                track_num,
+                name",
+            release_id
+        )
+        .fetch_all(conn)
+        .await
+    }
+
+    // TODO should this be outside the Track impl?
+    pub async fn get_all_detailed(
+        conn: &mut SqlitePoolConn,
+    ) -> Result<Vec<DetailedTrack>, sqlx::Error> {
+        Self::query_detailed(conn, &TrackQuery::default()).await
+    }
+
+    /// tracks with the same audio data, regardless of tags or path
+    pub async fn get_by_audio_hash(
+        conn: &mut SqlitePoolConn,
+        audio_hash: &str,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as!(
+            Self,
+            "SELECT * FROM tracks WHERE audio_hash = ?",
+            audio_hash
+        )
+        .fetch_all(conn)
+        .await
+    }
+
+    /// tracks imported before hashing, or whose file couldn't be hashed
+    pub async fn get_unhashed(
+        conn: &mut SqlitePoolConn,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as!(Self, "SELECT * FROM tracks WHERE audio_hash IS NULL")
+            .fetch_all(conn)
+            .await
+    }
+
+    pub async fn set_audio_hash(
+        conn: &mut SqlitePoolConn,
+        id: RowId,
+        audio_hash: &str,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE tracks SET audio_hash = ? WHERE id = ?")
+            .bind(audio_hash)
+            .bind(id)
+            .execute(conn)
+            .await
+            .map(|_done| ())
+    }
+
+    pub async fn set_available(
+        conn: &mut SqlitePoolConn,
+        id: RowId,
+        available: bool,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE tracks SET available = ? WHERE id = ?")
+            .bind(available)
+            .bind(id)
+            .execute(conn)
+            .await
+            .map(|_done| ())
+    }
+
+    /// a track with the title by the artist, as credited on the track or its
+    /// release or by alias. the one on `album` if there are a few. case
+    /// insensitive
+    pub async fn find_by_credit(
+        conn: &mut SqlitePoolConn,
+        artist: &str,
+        title: &str,
+        album: Option<&str>,
+    ) -> Result<Option<RowId>, sqlx::Error> {
+        sqlx::query!(
+            "WITH credited(artist_id) AS (
+                SELECT id FROM artists WHERE name = ? COLLATE NOCASE
+                UNION
+                SELECT artist_id FROM artist_aliases
+                WHERE name = ? COLLATE NOCASE
+            )
+            SELECT tracks.id FROM tracks
+            JOIN releases ON tracks.release_id = releases.id
+            WHERE tracks.name = ? COLLATE NOCASE
+            AND (
+                tracks.id IN (
+                    SELECT track_id FROM track_artists
+                    WHERE artist_id IN credited
+                )
+                OR releases.id IN (
+                    SELECT release_id FROM artist_releases
+                    WHERE artist_id IN credited
+                )
+            )
+            ORDER BY
+                releases.name = ? COLLATE NOCASE DESC,
+                tracks.available DESC,
+                tracks.id
+            LIMIT 1",
+            artist,
+            artist,
+            title,
+            album
+        )
+        .fetch_optional(conn)
+        .await
+        .map(|row| row.map(|r| r.id))
     }
-}
 
-#[derive(Debug)]
-pub struct Tag {
-    pub id: RowId,
-    pub name: String,
-}
+    /// 0 - 5, None to unrate. false, leaving the rating as it was, outside
+    /// that range. logged for undo
+    pub async fn set_rating(
+        conn: &mut SqlitePoolConn,
+        id: RowId,
+        rating: Option<f64>,
+    ) -> Result<bool, sqlx::Error> {
+        // NaN fails the range check too
+        if rating.map_or(false, |r| !(0.0..=5.0).contains(&r)) {
+            return Ok(false);
+        }
+        let track = Self::get(conn, id).await?;
 
-impl Tag {
-    pub async fn create(
+        let mut tx = conn.begin().await?;
+        let group_id = EditGroup::create(&mut tx, "rate track").await?;
+        sqlx::query("UPDATE tracks SET rating = ? WHERE id = ?")
+            .bind(rating)
+            .bind(id)
+            .execute(&mut tx)
+            .await?;
+        EditLogEntry::record(
+            &mut tx,
+            group_id,
+            "track",
+            id,
+            "rating",
+            log_text(track.rating),
+            log_text(rating),
+        )
+        .await?;
+        tx.commit().await?;
+
+        Ok(true)
+    }
+
+    pub async fn get_unavailable(
         conn: &mut SqlitePoolConn,
-        tag_name: &str,
-    ) -> Result<RowId, sqlx::Error> {
-        let id = sqlx::query("INSERT INTO tags (name) VALUES (?)")
-            .bind(tag_name)
-            .execute(conn)
-            .await?
-            .last_insert_rowid();
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as!(Self, "SELECT * FROM tracks WHERE available = 0")
+            .fetch_all(conn)
+            .await
+    }
 
-        Ok(id)
+    /// points tracks at their files' new locations and marks them available,
+    /// all or nothing. logged for undo as `description` if given, e.g. when
+    /// the user asked for it
+    pub async fn relink(
+        conn: &mut SqlitePoolConn,
+        moves: &[(RowId, String)],
+        description: Option<&str>,
+    ) -> Result<(), sqlx::Error> {
+        let mut before = Vec::new();
+        if description.is_some() {
+            for (id, _) in moves {
+                before.push(Self::get(conn, *id).await?);
+            }
+        }
+
+        let mut tx = conn.begin().await?;
+        for (id, file_path) in moves {
+            sqlx::query(
+                "UPDATE tracks SET file_path = ?, available = 1 WHERE id = ?",
+            )
+            .bind(file_path)
+            .bind(id)
+            .execute(&mut tx)
+            .await?;
+        }
+
+        if let Some(description) = description {
+            let group_id = EditGroup::create(&mut tx, description).await?;
+            for (t, (_, file_path)) in before.iter().zip(moves.iter()) {
+                let mut moved = t.clone();
+                moved.file_path = file_path.clone();
+                moved.available = true;
+                t.log_changes(&mut tx, group_id, &moved).await?;
+            }
+        }
+
+        tx.commit().await
     }
 
-    // tags will usually be reused instead of created -> optimize for the read
-    pub async fn find_or_create(
+    /// tracks without a BPM, key or fingerprint
+    pub async fn get_unanalyzed(
         conn: &mut SqlitePoolConn,
-        tag_name: &str,
-    ) -> Result<RowId, sqlx::Error> {
-        match sqlx::query_as!(
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as!(
             Self,
-            "SELECT id, name from tags WHERE name = ?",
-            tag_name
+            "SELECT * FROM tracks
+            WHERE NOT analysis_failed
+                AND (bpm IS NULL OR key IS NULL OR fingerprint IS NULL);"
         )
-        .fetch_one(conn.borrow_mut())
+        .fetch_all(conn)
         .await
-        {
-            Ok(tag) => Ok(tag.id),
-            Err(sqlx::Error::Database(e)) => match e.code() {
-                Some(code) if code == SQLITE_UNIQUE_VIOLATION => {
-                    Self::create(conn, tag_name).await
-                }
-                Some(code) => panic!("tag insert db failure {:?}", code),
-                None => panic!("tag insert db fail no code"),
-            },
-            Err(e) => panic!("tag insert failed {:?}", e),
-        }
     }
-}
 
-#[derive(Debug)]
-pub struct TrackTag {
-    track_id: RowId,
-    tag_id: RowId,
-}
+    /// skips the track in `get_unanalyzed` until its audio changes
+    pub async fn set_analysis_failed(
+        conn: &mut SqlitePoolConn,
+        id: RowId,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE tracks SET analysis_failed = 1 WHERE id = ?")
+            .bind(id)
+            .execute(conn)
+            .await
+            .map(|_done| ())
+    }
 
-impl TrackTag {
-    pub async fn create(
+    pub async fn set_fingerprint(
         conn: &mut SqlitePoolConn,
-        track_id: RowId,
-        tag_id: RowId,
+        id: RowId,
+        fingerprint: &[u8],
     ) -> Result<(), sqlx::Error> {
-        sqlx::query("INSERT INTO track_tags (track_id, tag_id) VALUES (?, ?)")
-            .bind(track_id)
-            .bind(tag_id)
+        sqlx::query("UPDATE tracks SET fingerprint = ? WHERE id = ?")
+            .bind(fingerprint)
+            .bind(id)
             .execute(conn)
             .await
             .map(|_done| ())
     }
-}
 
-// TODO store track duration
-#[derive(Clone, Debug)]
-pub struct Track {
-    pub id: RowId,
-    pub name: String,
-    pub release_id: RowId,
-    pub file_path: String,
-    pub channels: RowId,
-    pub sample_rate: RowId,
-    pub bit_depth: RowId,
-    pub track_num: Option<RowId>,
-    pub created: String,  // TODO parse date
-    pub modified: String, // TODO parse date
-}
+    pub async fn get_fingerprints(
+        conn: &mut SqlitePoolConn,
+    ) -> Result<Vec<(RowId, Vec<u8>)>, sqlx::Error> {
+        Ok(sqlx::query!(
+            "SELECT id, fingerprint as \"fingerprint!\" FROM tracks
+            WHERE fingerprint IS NOT NULL"
+        )
+        .fetch_all(conn)
+        .await?
+        .into_iter()
+        .map(|row| (row.id, row.fingerprint))
+        .collect())
+    }
 
-// TODO how significant of an impact on memory does duplicating Artist, Release,
-// and Tags per track have? performance?
-// - use [A]RC to reference count and share memory
-// - for Vec<DetailedTrack> etc,  wrap + store Artists/Releases/Tags and use
-// refs in this struct
-// - stack allocated str (e.g inlinable_string) for performance
-#[derive(Debug)]
-pub struct DetailedTrack {
-    pub id: RowId,
-    pub name: String,
-    pub release: Release,
-    pub artists: Vec<Artist>,
-    pub tags: Vec<Tag>,
-    pub file_path: String,
-    pub channels: RowId,
-    pub sample_rate: RowId,
-    pub bit_depth: RowId,
-    pub track_num: Option<RowId>,
-    pub created: String,  // TODO parse date
-    pub modified: String, // TODO parse date
-}
+    /// merges duplicates of the same recording into the track with the best
+    /// quality file: lossless first, then by format, bit depth and sample
+    /// rate. values missing on the kept track are filled from the others,
+    /// tagged BPM / key win over detected ones. tags, genres and credits of
+    /// all tracks are kept. returns the kept track's id, None for fewer than
+    /// two distinct tracks. not logged, so it can't be undone: the merged
+    /// tracks' rows, file stats and waveforms are gone
+    pub async fn merge(
+        conn: &mut SqlitePoolConn,
+        track_ids: &[RowId],
+    ) -> Result<Option<RowId>, sqlx::Error> {
+        // the kept track mustn't be merged into itself and deleted
+        let mut track_ids = track_ids.to_vec();
+        track_ids.sort();
+        track_ids.dedup();
+        if track_ids.len() < 2 {
+            return Ok(None);
+        }
 
-impl Track {
-    pub async fn create(
+        let mut tracks = Vec::new();
+        for id in track_ids {
+            tracks.push(Self::get(conn, id).await?);
+        }
+        tracks.sort_by_key(|t| std::cmp::Reverse(quality_rank(t)));
+        let mut kept = tracks.remove(0);
+
+        let tagged = Some(ValueSource::Tagged.as_str());
+        for other in tracks.iter() {
+            kept.track_num = kept.track_num.or(other.track_num);
+            kept.track_total = kept.track_total.or(other.track_total);
+            kept.disc_num = kept.disc_num.or(other.disc_num);
+            kept.disc_total = kept.disc_total.or(other.disc_total);
+            kept.composer = kept.composer.or_else(|| other.composer.clone());
+            kept.fingerprint =
+                kept.fingerprint.or_else(|| other.fingerprint.clone());
+            kept.rating = kept.rating.or(other.rating);
+
+            let replace_bpm = other.bpm.is_some()
+                && (kept.bpm.is_none()
+                    || (kept.bpm_source.as_deref() != tagged
+                        && other.bpm_source.as_deref() == tagged));
+            if replace_bpm {
+                kept.bpm = other.bpm;
+                kept.bpm_source = other.bpm_source.clone();
+                kept.bpm_confidence = other.bpm_confidence;
+            }
+
+            let replace_key = other.key.is_some()
+                && (kept.key.is_none()
+                    || (kept.key_source.as_deref() != tagged
+                        && other.key_source.as_deref() == tagged));
+            if replace_key {
+                kept.key = other.key.clone();
+                kept.key_source = other.key_source.clone();
+                kept.key_confidence = other.key_confidence;
+            }
+        }
+
+        let mut tx = conn.begin().await?;
+
+        sqlx::query(
+            "UPDATE tracks SET
+                track_num = ?, track_total = ?, disc_num = ?, disc_total = ?,
+                composer = ?, bpm = ?, bpm_source = ?, bpm_confidence = ?,
+                key = ?, key_source = ?, key_confidence = ?, fingerprint = ?,
+                rating = ?
+            WHERE id = ?",
+        )
+        .bind(kept.track_num)
+        .bind(kept.track_total)
+        .bind(kept.disc_num)
+        .bind(kept.disc_total)
+        .bind(&kept.composer)
+        .bind(kept.bpm)
+        .bind(&kept.bpm_source)
+        .bind(kept.bpm_confidence)
+        .bind(&kept.key)
+        .bind(&kept.key_source)
+        .bind(kept.key_confidence)
+        .bind(&kept.fingerprint)
+        .bind(kept.rating)
+        .bind(kept.id)
+        .execute(&mut tx)
+        .await?;
+
+        for other in tracks.iter() {
+            for table_query in &[
+                "INSERT OR IGNORE INTO track_tags (track_id, tag_id)
+                SELECT ?, tag_id FROM track_tags WHERE track_id = ?",
+                "INSERT OR IGNORE INTO track_genres (track_id, genre_id)
+                SELECT ?, genre_id FROM track_genres WHERE track_id = ?",
+                "INSERT OR IGNORE INTO track_artists (track_id, artist_id, role)
+                SELECT ?, artist_id, role FROM track_artists
+                WHERE track_id = ?",
+                "UPDATE plays SET track_id = ? WHERE track_id = ?",
+            ] {
+                sqlx::query(table_query)
+                    .bind(kept.id)
+                    .bind(other.id)
+                    .execute(&mut tx)
+                    .await?;
+            }
+
+            for delete_query in &[
+                "DELETE FROM track_tags WHERE track_id = ?",
+                "DELETE FROM track_genres WHERE track_id = ?",
+                "DELETE FROM track_artists WHERE track_id = ?",
+                "DELETE FROM tracks WHERE id = ?",
+            ] {
+                sqlx::query(delete_query)
+                    .bind(other.id)
+                    .execute(&mut tx)
+                    .await?;
+            }
+
+            // the duplicate may have been the only track on its release
+            for delete_query in &[
+                "DELETE FROM artist_releases WHERE release_id = ?
+                AND NOT EXISTS (SELECT 1 FROM tracks WHERE release_id = ?)",
+                "DELETE FROM releases WHERE id = ?
+                AND NOT EXISTS (SELECT 1 FROM tracks WHERE release_id = ?)",
+            ] {
+                sqlx::query(delete_query)
+                    .bind(other.release_id)
+                    .bind(other.release_id)
+                    .execute(&mut tx)
+                    .await?;
+            }
+        }
+
+        tx.commit().await?;
+
+        Ok(Some(kept.id))
+    }
+
+    /// stores an estimated BPM unless the track has a tagged one. returns
+    /// whether the track was updated
+    pub async fn set_detected_bpm(
         conn: &mut SqlitePoolConn,
-        name: &str,
-        release_id: RowId,
-        file_path: &str,
-        channels: RowId,
-        sample_rate: RowId,
-        bit_depth: RowId,
-        track_num: Option<RowId>,
-    ) -> Result<RowId, sqlx::Error> {
-        let track_id = sqlx::query(
-            "INSERT INTO tracks
-            (name, release_id, file_path, channels, sample_rate, bit_depth, track_num)
-            VALUES (?, ?, ?, ?, ?, ?, ?)",
+        id: RowId,
+        bpm: f64,
+        confidence: f64,
+    ) -> Result<bool, sqlx::Error> {
+        let done = sqlx::query(
+            "UPDATE tracks
+            SET bpm = ?, bpm_source = ?, bpm_confidence = ?
+            WHERE id = ? AND (bpm_source IS NULL OR bpm_source = ?)",
         )
-        .bind(name)
-        .bind(release_id)
-        .bind(file_path)
-        .bind(channels)
-        .bind(sample_rate)
-        .bind(bit_depth)
-        .bind(track_num)
-        .execute(conn.borrow_mut())
-        .await?
-        .last_insert_rowid();
+        .bind(bpm)
+        .bind(ValueSource::Detected.as_str())
+        .bind(confidence)
+        .bind(id)
+        .bind(ValueSource::Detected.as_str())
+        .execute(conn)
+        .await?;
 
-        Ok(track_id)
+        Ok(done.rows_affected() > 0)
     }
 
-    pub async fn get(
+    /// stores an estimated key unless the track has a tagged one. returns
+    /// whether the track was updated
+    pub async fn set_detected_key(
         conn: &mut SqlitePoolConn,
         id: RowId,
-    ) -> Result<Self, sqlx::Error> {
-        sqlx::query_as!(Self, "SELECT * FROM tracks WHERE id = ?", id)
-            .fetch_one(conn)
-            .await
+        key: MusicalKey,
+        confidence: f64,
+    ) -> Result<bool, sqlx::Error> {
+        let done = sqlx::query(
+            "UPDATE tracks
+            SET key = ?, key_source = ?, key_confidence = ?
+            WHERE id = ? AND (key_source IS NULL OR key_source = ?)",
+        )
+        .bind(key.to_camelot())
+        .bind(ValueSource::Detected.as_str())
+        .bind(confidence)
+        .bind(id)
+        .bind(ValueSource::Detected.as_str())
+        .execute(conn)
+        .await?;
+
+        Ok(done.rows_affected() > 0)
     }
 
-    pub async fn get_all(
+    /// tracks in a key compatible with `key` (see `MusicalKey::compatible`)
+    /// with a BPM in `bpm_min..=bpm_max`
+    pub async fn get_harmonic_matches(
         conn: &mut SqlitePoolConn,
-    ) -> Result<Vec<Self>, sqlx::Error> {
-        sqlx::query_as!(Self, "SELECT * FROM tracks;")
-            .fetch_all(conn)
-            .await
+        key: MusicalKey,
+        bpm_min: f64,
+        bpm_max: f64,
+    ) -> Result<Vec<DetailedTrack>, sqlx::Error> {
+        let query = TrackQuery {
+            bpm_min: Some(bpm_min),
+            bpm_max: Some(bpm_max),
+            compatible_with: Some(key),
+            ..TrackQuery::default()
+        };
+        Self::query_detailed(conn, &query).await
     }
 
-    // TODO should this be outside the Track impl?
-    pub async fn get_all_detailed(
+    pub async fn query_detailed(
         conn: &mut SqlitePoolConn,
+        query: &TrackQuery,
     ) -> Result<Vec<DetailedTrack>, sqlx::Error> {
+        // every filter is bound twice, once for the IS NULL check
+        let genre = query.genre.as_deref();
+        let tag = query.tag.as_deref();
+        let label = query.label.as_deref();
+        // matched after the query, sqlite can't strip all punctuation
+        let catalog_num =
+            query.catalog_num.as_deref().map(normalize_identifier);
+        let composer = query.composer.as_deref();
+        let played_since = query.played_since.as_deref();
+        // matched with instr, e.g. ",8A,9A,7A,8B,"
+        let keys = query.compatible_with.map(|k| {
+            k.compatible()
+                .iter()
+                .fold(String::from(","), |s, k| s + &k.to_camelot() + ",")
+        });
+        // matched with instr like keys, e.g. ",1,2,3,"
+        let ids = query.ids.as_ref().map(|ids| {
+            ids.iter()
+                .fold(String::from(","), |s, id| s + &id.to_string() + ",")
+        });
         let tracks_with_releases = sqlx::query!(
             "SELECT
                 tracks.id,
@@ -327,14 +3937,106 @@ impl Track {
                 tracks.sample_rate,
                 tracks.bit_depth,
                 tracks.track_num,
+                tracks.track_total,
+                tracks.disc_num,
+                tracks.disc_total,
+                tracks.composer,
+                tracks.bpm,
+                tracks.bpm_source,
+                tracks.bpm_confidence,
+                tracks.key,
+                tracks.key_source,
+                tracks.key_confidence,
+                tracks.available,
+                tracks.rating,
                 tracks.created,
                 tracks.modified,
+                COALESCE(plays.play_count, 0) AS \"play_count!: i64\",
+                COALESCE(plays.skip_count, 0) AS \"skip_count!: i64\",
+                plays.last_played AS \"last_played?: String\",
                 releases.id as release_id,
                 releases.name as release_name,
                 releases.date as release_date,
-                releases.created as release_created
+                releases.album_artist_id as release_album_artist_id,
+                releases.label_id as release_label_id,
+                releases.catalog_num as release_catalog_num,
+                releases.barcode as release_barcode,
+                releases.release_type as release_type,
+                releases.edition as release_edition,
+                releases.created as release_created,
+                labels.name as \"label_name?\",
+                labels.created as \"label_created?\"
             FROM tracks
-            JOIN releases ON tracks.release_id = releases.id;",
+            JOIN releases ON tracks.release_id = releases.id
+            LEFT JOIN labels ON releases.label_id = labels.id
+            LEFT JOIN artists album_artists
+                ON releases.album_artist_id = album_artists.id
+            LEFT JOIN (
+                SELECT
+                    track_id,
+                    SUM(NOT skipped) AS play_count,
+                    SUM(skipped) AS skip_count,
+                    MAX(CASE WHEN NOT skipped THEN played_at END)
+                        AS last_played
+                FROM plays
+                GROUP BY track_id
+            ) plays ON plays.track_id = tracks.id
+            WHERE
+                (? IS NULL OR tracks.id IN (
+                    SELECT track_genres.track_id FROM track_genres
+                    JOIN genres ON track_genres.genre_id = genres.id
+                    WHERE genres.name = ?))
+                AND (? IS NULL OR tracks.id IN (
+                    WITH RECURSIVE nested(id) AS (
+                        SELECT id FROM tags WHERE name = ?
+                        UNION
+                        SELECT tags.id FROM tags
+                        JOIN nested ON tags.parent_id = nested.id
+                    )
+                    SELECT track_id FROM track_tags
+                    WHERE tag_id IN (SELECT id FROM nested)))
+                AND (? IS NULL OR labels.name = ? COLLATE NOCASE)
+                AND (? IS NULL OR tracks.composer LIKE '%' || ? || '%')
+                AND (? IS NULL OR tracks.bpm >= ?)
+                AND (? IS NULL OR tracks.bpm <= ?)
+                AND (? IS NULL OR instr(?, ',' || tracks.key || ',') > 0)
+                AND (? IS NULL OR tracks.available = ?)
+                AND (? IS NULL OR instr(?, ',' || tracks.id || ',') > 0)
+                AND (? IS NULL OR tracks.rating >= ?)
+                AND (? IS NULL OR (COALESCE(plays.play_count, 0) > 0) = ?)
+                AND (? IS NULL OR plays.last_played >= ?)
+            ORDER BY
+                album_artists.id IS NULL,
+                COALESCE(album_artists.sort_name, album_artists.name)
+                    COLLATE NOCASE,
+                tracks.release_id,
+                COALESCE(tracks.disc_num, 1),
+                tracks.track_num IS NULL,
+                tracks.track_num;",
+            genre,
+            genre,
+            tag,
+            tag,
+            label,
+            label,
+            composer,
+            composer,
+            query.bpm_min,
+            query.bpm_min,
+            query.bpm_max,
+            query.bpm_max,
+            keys,
+            keys,
+            query.available,
+            query.available,
+            ids,
+            ids,
+            query.rating_min,
+            query.rating_min,
+            query.played,
+            query.played,
+            played_since,
+            played_since
         )
         .fetch_all(conn.borrow_mut())
         .await?;
@@ -344,7 +4046,9 @@ impl Track {
             "SELECT
                 track_tags.track_id,
                 tags.id,
-                tags.name
+                tags.name,
+                tags.parent_id,
+                tags.color
             FROM track_tags
             JOIN tags ON track_tags.tag_id = tags.id"
         )
@@ -356,6 +4060,30 @@ impl Track {
             ra.push(Tag {
                 id: row.id,
                 name: row.name,
+                parent_id: row.parent_id,
+                color: row.color,
+            })
+        });
+
+        let mut track_genres: HashMap<RowId, Vec<Genre>> = HashMap::new();
+        sqlx::query!(
+            "SELECT
+                track_genres.track_id,
+                genres.id,
+                genres.name,
+                genres.created
+            FROM track_genres
+            JOIN genres ON track_genres.genre_id = genres.id"
+        )
+        .fetch_all(conn.borrow_mut())
+        .await?
+        .into_iter()
+        .for_each(|row| {
+            let tg = track_genres.entry(row.track_id).or_insert(Vec::new());
+            tg.push(Genre {
+                id: row.id,
+                name: row.name,
+                created: row.created,
             })
         });
 
@@ -365,6 +4093,7 @@ impl Track {
                 artist_releases.release_id,
                 artists.id,
                 artists.name,
+                artists.sort_name,
                 artists.created
             FROM artist_releases
             JOIN artists ON artist_releases.artist_id = artists.id;"
@@ -378,13 +4107,62 @@ impl Track {
             ra.push(Artist {
                 id: row.id,
                 name: row.name,
+                sort_name: row.sort_name,
                 created: row.created,
             })
         });
 
+        let mut track_artists: HashMap<RowId, Vec<TrackArtist>> =
+            HashMap::new();
+        sqlx::query!(
+            "SELECT
+                track_artists.track_id,
+                track_artists.role,
+                artists.id,
+                artists.name,
+                artists.sort_name,
+                artists.created
+            FROM track_artists
+            JOIN artists ON track_artists.artist_id = artists.id;"
+        )
+        .fetch_all(conn.borrow_mut())
+        .await?
+        .into_iter()
+        .for_each(|row| {
+            let ta = track_artists.entry(row.track_id).or_insert(Vec::new());
+            ta.push(TrackArtist {
+                role: ArtistRole::from_str(&row.role).unwrap(),
+                artist: Artist {
+                    id: row.id,
+                    name: row.name,
+                    sort_name: row.sort_name,
+                    created: row.created,
+                },
+            })
+        });
+
         let mut detailed_tracks = Vec::new();
 
         for track in tracks_with_releases {
+            if catalog_num.is_some()
+                && track
+                    .release_catalog_num
+                    .as_deref()
+                    .map(normalize_identifier)
+                    != catalog_num
+            {
+                continue;
+            }
+            let label = match (
+                track.release_label_id,
+                track.label_name,
+                track.label_created,
+            ) {
+                (Some(id), Some(name), Some(created)) => {
+                    Some(Label { id, name, created })
+                }
+                _ => None,
+            };
             let dt = DetailedTrack {
                 id: track.id,
                 name: track.name,
@@ -392,19 +4170,51 @@ impl Track {
                     id: track.release_id,
                     name: track.release_name,
                     date: track.release_date,
+                    album_artist_id: track.release_album_artist_id,
+                    label_id: track.release_label_id,
+                    catalog_num: track.release_catalog_num,
+                    barcode: track.release_barcode,
+                    release_type: track.release_type,
+                    edition: track.release_edition,
                     created: track.release_created,
                 },
                 artists: release_artists
                     .get(&track.release_id)
                     .unwrap()
                     .to_owned(),
+                track_artists: track_artists
+                    .remove(&track.id)
+                    .unwrap_or_default(),
                 // TODO is removing slower than cloning & dropping?
-                tags: track_tags.remove(&track.id).unwrap(),
+                tags: track_tags.remove(&track.id).unwrap_or_default(),
+                genres: track_genres.remove(&track.id).unwrap_or_default(),
+                label,
+                composer: track.composer,
+                bpm: track.bpm,
+                bpm_source: track
+                    .bpm_source
+                    .as_deref()
+                    .and_then(ValueSource::from_str),
+                bpm_confidence: track.bpm_confidence,
+                key: track.key.as_deref().and_then(MusicalKey::from_camelot),
+                key_source: track
+                    .key_source
+                    .as_deref()
+                    .and_then(ValueSource::from_str),
+                key_confidence: track.key_confidence,
                 file_path: track.file_path,
+                available: track.available,
                 channels: track.channels,
                 sample_rate: track.sample_rate,
                 bit_depth: track.bit_depth,
                 track_num: track.track_num,
+                track_total: track.track_total,
+                disc_num: track.disc_num,
+                disc_total: track.disc_total,
+                rating: track.rating,
+                play_count: track.play_count,
+                skip_count: track.skip_count,
+                last_played: track.last_played,
                 created: track.created,
                 modified: track.modified,
             };
@@ -418,41 +4228,89 @@ impl Track {
 pub async fn import_from_parse_result(
     conn: SqlitePoolConn,
     metadata: parse::ParseResult,
+    release_match: ReleaseMatch,
+    artist_match: ArtistMatch,
 ) -> DetailedTrack {
     let mut conn = conn;
     let mut artists = vec![];
-    for curr_artist in metadata.artists {
-        let new_artist = match Artist::create(&mut conn, &curr_artist).await {
+    for curr_artist in metadata.artists.iter() {
+        let new_artist = match Artist::find_matching_or_create(
+            &mut conn,
+            curr_artist,
+            artist_match,
+        )
+        .await
+        {
             Ok(a) => a,
-            Err(sqlx::Error::Database(d)) => match d.code() {
-                Some(code) if code == SQLITE_UNIQUE_VIOLATION => {
-                    Artist::get_by_name(&mut conn, &curr_artist).await.unwrap()
-                }
-                _ => panic!("new artist failed db {:?}", d),
-            },
             Err(e) => panic!("new artist failed {:?}", e),
         };
         artists.push(new_artist);
     }
 
-    let primary_artist = &artists[0];
+    // the album artist is what groups tracks into a release. without one,
+    // fall back to the primary track artist unless it's a compilation,
+    // otherwise every artist on a compilation gets their own release
+    let album_artist_name = match (&metadata.album_artist, metadata.compilation)
+    {
+        (Some(a), _) => Some(a.as_str()),
+        (None, true) => Some(parse::VARIOUS_ARTISTS),
+        (None, false) => None,
+    };
+    let album_artist = match album_artist_name {
+        Some(name) => Some(
+            Artist::find_matching_or_create(&mut conn, name, artist_match)
+                .await
+                .expect("album artist failed"),
+        ),
+        None => None,
+    };
+
+    let primary_artist = album_artist.as_ref().unwrap_or(&artists[0]);
 
     // TODO should this be wrapped around all release creation?
     let releases = Release::get_artist_releases(&mut conn, primary_artist.id)
         .await
         .unwrap();
 
+    let label = match &metadata.label {
+        Some(l) => Some(
+            Label::find_or_create(&mut conn, l)
+                .await
+                .expect("label failed"),
+        ),
+        None => None,
+    };
+
+    let details = ReleaseDetails {
+        date: metadata.date.clone(),
+        label_id: label.as_ref().map(|l| l.id),
+        catalog_num: metadata.catalog_num.clone(),
+        barcode: metadata.barcode.clone(),
+        release_type: metadata.release_type.clone(),
+        edition: metadata.edition.clone(),
+    };
+
     let album = metadata.album.as_str();
-    let release = match releases.iter().find(|r| r.name == album) {
+    let release = match releases
+        .iter()
+        .find(|r| r.matches(album, &details, release_match))
+    {
         Some(r) => r.clone(),
-        None => Release::create(
-            &mut conn,
-            album,
-            metadata.date.as_deref(),
-            artists.iter().map(|a| a.id).collect(),
-        )
-        .await
-        .unwrap(),
+        None => {
+            let release_artist_ids = match &album_artist {
+                Some(a) => vec![a.id],
+                None => artists.iter().map(|a| a.id).collect(),
+            };
+            Release::create(
+                &mut conn,
+                album,
+                &details,
+                album_artist.as_ref().map(|a| a.id),
+                release_artist_ids,
+            )
+            .await
+            .unwrap()
+        }
     };
 
     let track_id = match Track::create(
@@ -464,6 +4322,15 @@ pub async fn import_from_parse_result(
         metadata.sample_rate as RowId,
         metadata.bit_depth as RowId,
         metadata.track_pos.map(|pos| pos as RowId),
+        metadata.track_total.map(|t| t as RowId),
+        metadata.disc_pos.map(|d| d as RowId),
+        metadata.disc_total.map(|d| d as RowId),
+        metadata.composer.as_deref(),
+        metadata.bpm,
+        metadata.key.as_deref().and_then(MusicalKey::parse),
+        metadata.audio_hash.as_deref(),
+        metadata.file_size.map(|s| s as RowId),
+        metadata.file_mtime,
     )
     .await
     {
@@ -478,6 +4345,58 @@ pub async fn import_from_parse_result(
         Err(e) => panic!("track insert failed {:?}", e),
     };
 
+    let credits = metadata
+        .featured
+        .iter()
+        .map(|a| (a, ArtistRole::Featured))
+        .chain(metadata.remixers.iter().map(|a| (a, ArtistRole::Remixer)))
+        .chain(metadata.producers.iter().map(|a| (a, ArtistRole::Producer)));
+
+    let mut track_artists = Vec::new();
+    for a in artists.iter() {
+        track_artists.push(TrackArtist {
+            artist: a.clone(),
+            role: ArtistRole::Primary,
+        });
+    }
+    for (name, role) in credits {
+        let artist =
+            Artist::find_matching_or_create(&mut conn, name, artist_match)
+                .await
+                .expect("credited artist failed");
+        track_artists.push(TrackArtist { artist, role });
+    }
+
+    for ta in track_artists.iter() {
+        TrackArtist::create(&mut conn, track_id, ta.artist.id, ta.role)
+            .await
+            .expect("track artist insert failed");
+    }
+
+    // an existing release may have a label the track wasn't tagged with
+    let label = match release.label_id {
+        Some(id) => {
+            Some(Label::get(&mut conn, id).await.expect("label get failed"))
+        }
+        None => None,
+    };
+
+    let mut genres = Vec::new();
+    for name in metadata.genres.iter() {
+        let genre = Genre::find_or_create(&mut conn, name)
+            .await
+            .expect("genre failed");
+        TrackGenre::create(&mut conn, track_id, genre.id)
+            .await
+            .expect("track genre insert failed");
+        genres.push(genre);
+    }
+
+    let release_artists = match &album_artist {
+        Some(a) => vec![a.clone()],
+        None => artists,
+    };
+
     // FIXME don't need to return this
     let t = Track::get(&mut conn, track_id)
         .await
@@ -487,13 +4406,31 @@ pub async fn import_from_parse_result(
         id: t.id,
         name: t.name,
         release,
-        artists,
+        artists: release_artists,
+        track_artists,
         tags: Vec::new(),
+        genres,
+        label,
+        composer: t.composer,
+        bpm: t.bpm,
+        bpm_source: t.bpm_source.as_deref().and_then(ValueSource::from_str),
+        bpm_confidence: t.bpm_confidence,
+        key: t.key.as_deref().and_then(MusicalKey::from_camelot),
+        key_source: t.key_source.as_deref().and_then(ValueSource::from_str),
+        key_confidence: t.key_confidence,
         file_path: t.file_path,
+        available: t.available,
         channels: t.channels,
         sample_rate: t.sample_rate,
         bit_depth: t.bit_depth,
         track_num: t.track_num,
+        track_total: t.track_total,
+        disc_num: t.disc_num,
+        disc_total: t.disc_total,
+        rating: t.rating,
+        play_count: 0,
+        skip_count: 0,
+        last_played: None,
         created: t.created,
         modified: t.modified,
     }
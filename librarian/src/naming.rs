@@ -0,0 +1,58 @@
+use crate::parse::{self, ParseResult};
+
+// TODO dir naming should go through here too (artist / album layout)
+
+/// characters that can't (or shouldn't) be in a file name on any of the
+/// platforms we target
+const RESERVED_CHARS: &[char] =
+    &['/', '\\', ':', '*', '?', '"', '<', '>', '|', '\0'];
+
+fn sanitize(s: &str) -> String {
+    s.chars()
+        .map(|c| match RESERVED_CHARS.contains(&c) {
+            true => '_',
+            false => c,
+        })
+        .collect::<String>()
+        .trim()
+        .to_owned()
+}
+
+/// renders a track file name (without extension) from a template.
+///
+/// supported placeholders:
+/// - `{title}`, `{artist}`, `{album_artist}`, `{album}`, `{date}`
+/// - `{track}` track number, zero padded to 2 digits
+/// - `{disc}` disc number
+/// - `{disc_total}` number of discs in the release
+///
+/// missing values render as an empty string, except `{album_artist}` which
+/// falls back to the first artist
+pub fn render_track_name(template: &str, meta: &ParseResult) -> String {
+    let album_artist = meta
+        .album_artist
+        .as_deref()
+        .or_else(|| match meta.compilation {
+            true => Some(parse::VARIOUS_ARTISTS),
+            false => None,
+        })
+        .unwrap_or(meta.artists[0].as_str());
+    let track = meta
+        .track_pos
+        .map(|t| format!("{:02}", t))
+        .unwrap_or_default();
+    let disc = meta.disc_pos.map(|d| d.to_string()).unwrap_or_default();
+    let disc_total = meta.disc_total.map(|d| d.to_string()).unwrap_or_default();
+
+    let rendered = template
+        .replace("{title}", &sanitize(&meta.track))
+        .replace("{artist}", &sanitize(&meta.artists.join(", ")))
+        .replace("{album_artist}", &sanitize(album_artist))
+        .replace("{album}", &sanitize(&meta.album))
+        .replace("{date}", &sanitize(meta.date.as_deref().unwrap_or("")))
+        .replace("{track}", &track)
+        .replace("{disc_total}", &disc_total)
+        .replace("{disc}", &disc);
+
+    rendered.trim().to_owned()
+}
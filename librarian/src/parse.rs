@@ -1,15 +1,26 @@
+use crate::hash;
 use claxon::{FlacReader, FlacReaderOptions};
-use hound::WavReader;
+use id3::TagLike;
 use log::{debug, error, trace, warn};
-use rtag::{
-    frame::FrameBody,
-    metadata::{MetadataReader, Unit},
+use std::{
+    fs,
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
 };
-use std::{fs, path::PathBuf};
 
 pub const UNKNOWN_ENTRY: &'static str = "";
 pub const UNKNOWN_ARTIST_DIR: &'static str = "Unknown Artist";
 pub const UNKNOWN_ALBUM_DIR: &'static str = "Unknown Album";
+/// album artist used for compilations that don't name one
+pub const VARIOUS_ARTISTS: &'static str = "Various Artists";
+
+/// how samples are encoded in the file (not after decoding)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SampleFormat {
+    SignedInt,
+    UnsignedInt,
+    Float,
+}
 
 // TODO better name than ParseResult
 // parsing may not be the right word for this module. more focused on metadata
@@ -23,9 +34,29 @@ pub struct ParseResultBuilder {
     date: Option<String>,
     track: Option<String>,
     track_pos: Option<i32>,
+    track_total: Option<i32>,
+    album_artist: Option<String>,
+    featured: Vec<String>,
+    remixers: Vec<String>,
+    producers: Vec<String>,
+    compilation: bool,
+    disc_pos: Option<i32>,
+    disc_total: Option<i32>,
+    cover: Option<Vec<u8>>,
+    comment: Option<String>,
+    genres: Vec<String>,
+    composer: Option<String>,
+    label: Option<String>,
+    catalog_num: Option<String>,
+    barcode: Option<String>,
+    release_type: Option<String>,
+    edition: Option<String>,
+    bpm: Option<f64>,
+    key: Option<String>,
     channels: Option<u16>,
     bit_depth: Option<u16>,
     sample_rate: Option<u32>,
+    sample_format: SampleFormat,
 }
 
 // would be ideal to tie the builder to the path a bit more strongly, such that
@@ -39,9 +70,29 @@ impl ParseResultBuilder {
             date: None,
             track: None,
             track_pos: None,
+            track_total: None,
+            album_artist: None,
+            featured: vec![],
+            remixers: vec![],
+            producers: vec![],
+            compilation: false,
+            disc_pos: None,
+            disc_total: None,
+            cover: None,
+            comment: None,
+            genres: vec![],
+            composer: None,
+            label: None,
+            catalog_num: None,
+            barcode: None,
+            release_type: None,
+            edition: None,
+            bpm: None,
+            key: None,
             channels: None,
             bit_depth: None,
             sample_rate: None,
+            sample_format: SampleFormat::SignedInt,
         }
     }
 
@@ -69,6 +120,82 @@ impl ParseResultBuilder {
         self.track_pos = Some(t)
     }
 
+    pub fn track_total(&mut self, t: i32) {
+        self.track_total = Some(t)
+    }
+
+    pub fn album_artist(&mut self, a: String) {
+        self.album_artist = Some(a)
+    }
+
+    pub fn featured(&mut self, a: String) {
+        self.featured.push(a)
+    }
+
+    pub fn remixer(&mut self, a: String) {
+        self.remixers.push(a)
+    }
+
+    pub fn producer(&mut self, a: String) {
+        self.producers.push(a)
+    }
+
+    pub fn compilation(&mut self, c: bool) {
+        self.compilation = c
+    }
+
+    pub fn disc_pos(&mut self, d: i32) {
+        self.disc_pos = Some(d)
+    }
+
+    pub fn disc_total(&mut self, d: i32) {
+        self.disc_total = Some(d)
+    }
+
+    pub fn cover(&mut self, c: Vec<u8>) {
+        self.cover = Some(c)
+    }
+
+    pub fn comment(&mut self, c: String) {
+        self.comment = Some(c)
+    }
+
+    pub fn genre(&mut self, g: String) {
+        self.genres.push(g)
+    }
+
+    pub fn composer(&mut self, c: String) {
+        self.composer = Some(c)
+    }
+
+    pub fn label(&mut self, l: String) {
+        self.label = Some(l)
+    }
+
+    pub fn catalog_num(&mut self, c: String) {
+        self.catalog_num = Some(c)
+    }
+
+    pub fn barcode(&mut self, b: String) {
+        self.barcode = Some(b)
+    }
+
+    pub fn release_type(&mut self, t: String) {
+        self.release_type = normalize_release_type(&t)
+    }
+
+    pub fn edition(&mut self, e: String) {
+        self.edition = Some(e)
+    }
+
+    pub fn bpm(&mut self, b: f64) {
+        self.bpm = Some(b)
+    }
+
+    pub fn key(&mut self, k: String) {
+        self.key = Some(k)
+    }
+
     pub fn channels(&mut self, c: u16) {
         self.channels = Some(c)
     }
@@ -81,6 +208,10 @@ impl ParseResultBuilder {
         self.sample_rate = Some(s)
     }
 
+    pub fn sample_format(&mut self, f: SampleFormat) {
+        self.sample_format = f
+    }
+
     pub fn has_bare_minimum(&self) -> bool {
         match (self.channels, self.bit_depth, self.sample_rate) {
             (Some(_), Some(_), Some(_)) => true,
@@ -127,6 +258,59 @@ impl ParseResultBuilder {
         }
     }
 
+    // handles the common "Artist - Title", "01 - Artist - Title" and
+    // "01 - Title" layouts. only for wavs, which are often left untagged
+    fn infer_from_file_name(&mut self) {
+        let stem = match self.path.file_stem() {
+            Some(s) => s.to_string_lossy().to_string(),
+            None => return,
+        };
+
+        let mut parts: Vec<&str> =
+            stem.split(" - ").map(|p| p.trim()).collect();
+        if parts.len() > 1 {
+            if let Ok(pos) = parts[0].parse() {
+                if self.track_pos == None {
+                    self.track_pos(pos);
+                }
+                parts.remove(0);
+            }
+        }
+
+        match parts.as_slice() {
+            [artist, title] if !artist.is_empty() && !title.is_empty() => {
+                trace!("inferred artist + title from file name {:?}", stem);
+                self.artist(artist.to_string());
+                self.track(title.to_string());
+            }
+            // "01 - Title"
+            [title] if !title.is_empty() => self.track(title.to_string()),
+            _ => (),
+        }
+    }
+
+    // artist tags often credit features inline e.g. "Artist feat. Other",
+    // and some only credit them in the title e.g. "Track (feat. Other)"
+    fn split_featured_artists(&mut self) {
+        let mut primary = Vec::with_capacity(self.artists.len());
+        for artist in self.artists.drain(..) {
+            match split_featured(&artist) {
+                Some((main, mut featured)) => {
+                    primary.push(main);
+                    self.featured.append(&mut featured);
+                }
+                None => primary.push(artist),
+            }
+        }
+        self.artists = primary;
+
+        if let Some((_, mut featured)) =
+            self.track.as_deref().and_then(split_featured)
+        {
+            self.featured.append(&mut featured);
+        }
+    }
+
     pub fn complete(
         mut self,
         populate_unknown_fields: bool,
@@ -139,7 +323,6 @@ impl ParseResultBuilder {
                 self.album(UNKNOWN_ENTRY.to_owned());
             }
             if self.track == None {
-                // TODO if no artist or track then attempt to parse file name
                 let file_name = self
                     .path
                     .file_stem()
@@ -151,10 +334,21 @@ impl ParseResultBuilder {
             }
         }
 
+        self.split_featured_artists();
+
         // TODO should this be optional?
         if self.artists.len() > 1 {
             self.artists.dedup();
         }
+        for credits in
+            &mut [&mut self.featured, &mut self.remixers, &mut self.producers]
+        {
+            credits.sort();
+            credits.dedup();
+        }
+        if self.genres.len() > 1 {
+            self.genres.dedup();
+        }
 
         match (
             self.artists.len() > 0,
@@ -178,9 +372,35 @@ impl ParseResultBuilder {
                 date: self.date,
                 track,
                 track_pos: self.track_pos,
+                track_total: self.track_total,
+                album_artist: self.album_artist,
+                featured: self.featured,
+                remixers: self.remixers,
+                producers: self.producers,
+                compilation: self.compilation,
+                disc_pos: self.disc_pos,
+                disc_total: self.disc_total,
+                cover: self.cover,
+                comment: self.comment,
+                genres: self.genres,
+                composer: self.composer,
+                label: self.label,
+                catalog_num: self.catalog_num,
+                barcode: self.barcode,
+                release_type: match (self.release_type, self.compilation) {
+                    (None, true) => Some(RELEASE_TYPE_COMPILATION.to_owned()),
+                    (t, _) => t,
+                },
+                edition: self.edition,
+                bpm: self.bpm,
+                key: self.key,
                 channels,
                 bit_depth,
                 sample_rate,
+                sample_format: self.sample_format,
+                audio_hash: None,
+                file_size: None,
+                file_mtime: None,
             }),
             _ => {
                 warn!("ParseResultBuilder unable to complete");
@@ -190,6 +410,63 @@ impl ParseResultBuilder {
     }
 }
 
+pub const RELEASE_TYPE_COMPILATION: &'static str = "compilation";
+
+/// release types are lowercased, and only the primary type is kept when
+/// there are several (musicbrainz style "album; compilation")
+pub fn normalize_release_type(t: &str) -> Option<String> {
+    let primary = t
+        .split(&[';', '/', '\0'][..])
+        .map(|t| t.trim().to_lowercase())
+        .find(|t| !t.is_empty())?;
+    match primary.as_str() {
+        "e.p." => Some("ep".to_owned()),
+        "lp" => Some("album".to_owned()),
+        _ => Some(primary),
+    }
+}
+
+// each must follow a space or an opening bracket, see split_featured
+const FEATURE_SEPARATORS: &[&str] =
+    &["feat. ", "feat ", "ft. ", "ft ", "featuring "];
+
+/// splits "Main (feat. A, B)" style credits into the main part and the
+/// featured artists. returns None if nothing is featured
+pub fn split_featured(credit: &str) -> Option<(String, Vec<String>)> {
+    // ascii lowercasing keeps byte offsets the same as the original
+    let lower = credit.to_ascii_lowercase();
+    let (idx, sep) = FEATURE_SEPARATORS
+        .iter()
+        .filter_map(|sep| {
+            lower
+                .match_indices(sep)
+                .map(|(idx, _)| idx)
+                .find(|&idx| {
+                    idx > 0
+                        && matches!(
+                            lower.as_bytes()[idx - 1],
+                            b' ' | b'(' | b'['
+                        )
+                })
+                .map(|idx| (idx, sep))
+        })
+        .min_by_key(|(idx, _)| *idx)?;
+
+    let main = credit[..idx].trim_end_matches(&['(', '['][..]).trim_end();
+    let featured = credit[idx + sep.len()..]
+        .trim_end_matches(&[')', ']'][..])
+        .split(", ")
+        .flat_map(|a| a.split(" & "))
+        .map(|a| a.trim().to_owned())
+        .filter(|a| !a.is_empty())
+        .collect::<Vec<_>>();
+
+    match (main.trim(), featured.len()) {
+        ("", _) | (_, 0) => None,
+        (m, _) => Some((m.to_owned(), featured)),
+    }
+}
+
 // should file path be stored in here?
 // TODO fields shouldn't be public
 #[derive(Debug)]
@@ -200,26 +477,54 @@ pub struct ParseResult {
     pub date: Option<String>,
     pub track: String,
     pub track_pos: Option<i32>,
+    pub track_total: Option<i32>,
+    pub album_artist: Option<String>,
+    pub featured: Vec<String>,
+    pub remixers: Vec<String>,
+    pub producers: Vec<String>,
+    // flagged as a compilation, without necessarily naming an album artist
+    pub compilation: bool,
+    pub disc_pos: Option<i32>,
+    pub disc_total: Option<i32>,
+    // raw image data from embedded artwork, format is not normalized
+    pub cover: Option<Vec<u8>>,
+    pub comment: Option<String>,
+    pub genres: Vec<String>,
+    pub composer: Option<String>,
+    pub label: Option<String>,
+    pub catalog_num: Option<String>,
+    pub barcode: Option<String>,
+    // lowercase, see `normalize_release_type`
+    pub release_type: Option<String>,
+    // edition / disambiguation comment e.g. "2015 remaster"
+    pub edition: Option<String>,
+    pub bpm: Option<f64>,
+    // as tagged, not normalized
+    pub key: Option<String>,
     pub channels: u16,
     pub bit_depth: u16,
     pub sample_rate: u32,
+    pub sample_format: SampleFormat,
+    // see `hash::audio_hash`, None if the file couldn't be hashed
+    pub audio_hash: Option<String>,
+    // see `file_stat`
+    pub file_size: Option<u64>,
+    pub file_mtime: Option<i64>,
 }
 
 // TODO split out import file types - MP3 etc. can have a trait or enum impl?
-// TODO handle invalid track numbers in tags. the main culprit are fraction
-// strings like "1/9". that should be a simple enough edge case to handle.
-// those tags should be updated too
 
 pub fn parse_flac(p: PathBuf) -> Option<ParseResult> {
     trace!("parsing flac {:?}", &p);
     let mut builder = ParseResultBuilder::new(p);
-    let reader = match FlacReader::open_ext(
-        builder.path(),
-        FlacReaderOptions {
-            metadata_only: true,
-            read_vorbis_comment: true,
-        },
-    ) {
+    let options = FlacReaderOptions {
+        metadata_only: true,
+        read_vorbis_comment: true,
+    };
+    let reader = match open_flac(builder.path())
+        .map_err(claxon::Error::from)
+        .and_then(|f| FlacReader::new_ext(f, options))
+    {
         Ok(r) => r,
         Err(e) => {
             error!("failed to read flac {:?} {:?}", builder, e);
@@ -249,46 +554,626 @@ pub fn parse_flac(p: PathBuf) -> Option<ParseResult> {
         builder.album(a.to_owned());
     }
 
+    // no single standard for this one
+    let album_artist = reader
+        .get_tag("albumartist")
+        .next()
+        .or_else(|| reader.get_tag("album artist").next());
+    if let Some(a) = album_artist {
+        builder.album_artist(a.to_owned());
+    }
+
+    for a in reader.get_tag("remixer") {
+        builder.remixer(a.to_owned());
+    }
+
+    for a in reader.get_tag("producer") {
+        builder.producer(a.to_owned());
+    }
+
+    if let Some(c) = reader.get_tag("compilation").next() {
+        builder.compilation(c == "1");
+    }
+
+    let label = reader
+        .get_tag("label")
+        .next()
+        .or_else(|| reader.get_tag("organization").next())
+        .or_else(|| reader.get_tag("publisher").next());
+    if let Some(l) = label {
+        builder.label(l.to_owned());
+    }
+
+    if let Some(c) = reader.get_tag("catalognumber").next() {
+        builder.catalog_num(c.to_owned());
+    }
+
+    let barcode = reader
+        .get_tag("barcode")
+        .next()
+        .or_else(|| reader.get_tag("upc").next())
+        .or_else(|| reader.get_tag("ean").next());
+    if let Some(b) = barcode {
+        builder.barcode(b.to_owned());
+    }
+
+    if let Some(t) = reader.get_tag("releasetype").next() {
+        builder.release_type(t.to_owned());
+    }
+
+    let edition = reader
+        .get_tag("edition")
+        .next()
+        .or_else(|| reader.get_tag("musicbrainz_albumcomment").next());
+    if let Some(e) = edition {
+        builder.edition(e.to_owned());
+    }
+
+    for g in reader.get_tag("genre") {
+        builder.genre(g.to_owned());
+    }
+
+    let composers: Vec<_> = reader.get_tag("composer").collect();
+    if composers.len() > 0 {
+        builder.composer(composers.join("; "));
+    }
+
+    if let Some(b) = reader.get_tag("bpm").next() {
+        match b.trim().parse() {
+            Ok(bpm) => builder.bpm(bpm),
+            Err(e) => {
+                warn!("failed to parse bpm {:?} {:?} {:?}", builder, b, e)
+            }
+        }
+    }
+
+    let key = reader
+        .get_tag("initialkey")
+        .next()
+        .or_else(|| reader.get_tag("key").next());
+    if let Some(k) = key {
+        builder.key(k.to_owned());
+    }
+
     if let Some(d) = reader.get_tag("date").next() {
         builder.date(d.to_owned());
     }
 
     if let Some(tn) = reader.get_tag("tracknumber").next() {
-        match tn.parse() {
-            Ok(x) => builder.track_pos(x),
-            Err(e) => warn!(
-                "failed to parse track number from v1 framer {:?} {:?} {:?}",
-                builder, tn, e
+        match parse_num_pair(tn) {
+            (Some(pos), total) => {
+                builder.track_pos(pos);
+                if let Some(t) = total {
+                    builder.track_total(t);
+                }
+            }
+            _ => warn!(
+                "failed to parse track number from vorbis comment {:?} {:?}",
+                builder, tn
             ),
         };
     }
 
+    let track_total = reader
+        .get_tag("tracktotal")
+        .next()
+        .or_else(|| reader.get_tag("totaltracks").next());
+    if let Some(Ok(t)) = track_total.map(|t| t.trim().parse()) {
+        builder.track_total(t);
+    }
+
+    if let Some(dn) = reader.get_tag("discnumber").next() {
+        match parse_num_pair(dn) {
+            (Some(pos), total) => {
+                builder.disc_pos(pos);
+                if let Some(t) = total {
+                    builder.disc_total(t);
+                }
+            }
+            _ => warn!(
+                "failed to parse disc number from vorbis comment {:?} {:?}",
+                builder, dn
+            ),
+        };
+    }
+
+    let disc_total = reader
+        .get_tag("disctotal")
+        .next()
+        .or_else(|| reader.get_tag("totaldiscs").next());
+    if let Some(Ok(d)) = disc_total.map(|d| d.trim().parse()) {
+        builder.disc_total(d);
+    }
+
     builder.complete(true)
 }
-// TODO what other WAV tagging formats are there to keep an eye out for?
-// TODO check out crate wav_reader
+const WAVE_FORMAT_PCM: u16 = 0x0001;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 0x0003;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+fn le_u16(b: &[u8], at: usize) -> Option<u16> {
+    b.get(at..at + 2).map(|x| u16::from_le_bytes([x[0], x[1]]))
+}
+
+fn le_u32(b: &[u8], at: usize) -> Option<u32> {
+    b.get(at..at + 4)
+        .map(|x| u32::from_le_bytes([x[0], x[1], x[2], x[3]]))
+}
+
+// IFF strings are fixed width or null terminated, and frequently both
+fn riff_str(b: &[u8]) -> Option<String> {
+    let end = b.iter().position(|c| *c == 0).unwrap_or(b.len());
+    let s = String::from_utf8_lossy(&b[..end]).trim().to_owned();
+    match s.is_empty() {
+        true => None,
+        false => Some(s),
+    }
+}
+
+fn be_u16(b: &[u8], at: usize) -> Option<u16> {
+    b.get(at..at + 2).map(|x| u16::from_be_bytes([x[0], x[1]]))
+}
+
+fn be_u32(b: &[u8], at: usize) -> Option<u32> {
+    b.get(at..at + 4)
+        .map(|x| u32::from_be_bytes([x[0], x[1], x[2], x[3]]))
+}
+
+/// top level chunks of a RIFF/WAVE (little endian) or FORM/AIFF (big endian)
+/// file. the audio data chunk is too big to hold onto, so only its position
+/// (offset, len) is kept
+#[derive(Debug)]
+pub struct IffChunks {
+    pub form_type: [u8; 4],
+    pub chunks: Vec<([u8; 4], Vec<u8>)>,
+    pub audio_data: Option<(u64, u64)>,
+}
+
+impl IffChunks {
+    pub fn get(&self, chunk_id: &[u8; 4]) -> Option<&[u8]> {
+        self.chunks
+            .iter()
+            .find(|(id, _)| id == chunk_id)
+            .map(|(_, data)| data.as_slice())
+    }
+}
+
+pub fn read_iff_chunks(
+    path: &Path,
+    big_endian: bool,
+) -> std::io::Result<IffChunks> {
+    let (form, audio_id) = match big_endian {
+        true => (b"FORM", b"SSND"),
+        false => (b"RIFF", b"data"),
+    };
+    let form_types: &[&[u8; 4]] = match big_endian {
+        true => &[b"AIFF", b"AIFC"],
+        false => &[b"WAVE"],
+    };
+    let read_u32 = if big_endian { be_u32 } else { le_u32 };
+
+    let mut file = fs::File::open(path)?;
+    let file_len = file.metadata()?.len();
+    let mut header = [0u8; 12];
+    file.read_exact(&mut header)?;
+    let mut form_type = [0u8; 4];
+    form_type.copy_from_slice(&header[8..12]);
+    if &header[0..4] != form || !form_types.contains(&&form_type) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "unexpected iff form",
+        ));
+    }
+
+    let mut iff = IffChunks {
+        form_type,
+        chunks: Vec::new(),
+        audio_data: None,
+    };
+    let mut chunk_header = [0u8; 8];
+    while file.read_exact(&mut chunk_header).is_ok() {
+        let mut id = [0u8; 4];
+        id.copy_from_slice(&chunk_header[0..4]);
+        let size = read_u32(&chunk_header, 4).unwrap() as u64;
+        // chunks are padded to an even length
+        let padded = size + (size & 1);
+        let offset = file.seek(SeekFrom::Current(0))?;
+        let remaining = file_len.saturating_sub(offset);
+
+        // recorders that were cut off leave the audio size too big, the
+        // audio that did get written is still playable
+        if &id == audio_id {
+            iff.audio_data = Some((offset, size.min(remaining)));
+            if padded >= remaining {
+                break;
+            }
+            file.seek(SeekFrom::Current(padded as i64))?;
+            continue;
+        }
+
+        // a corrupt or truncated chunk, keep what was read before it
+        if size > remaining {
+            warn!("iff chunk {:?} runs past end of file {:?}", id, path);
+            break;
+        }
+        let mut data = vec![0u8; size as usize];
+        file.read_exact(&mut data)?;
+        if padded > size {
+            file.seek(SeekFrom::Current(1))?;
+        }
+        iff.chunks.push((id, data));
+    }
+
+    Ok(iff)
+}
+
+// pulls (id, text) pairs out of a LIST chunk of type INFO
+pub(crate) fn riff_info_entries(list: &[u8]) -> Vec<([u8; 4], String)> {
+    let mut entries = Vec::new();
+    if list.get(0..4) != Some(&b"INFO"[..]) {
+        return entries;
+    }
+
+    let mut pos = 4;
+    while let Some(size) = le_u32(list, pos + 4) {
+        let size = size as usize;
+        let start = pos + 8;
+        let text = match list.get(start..start + size) {
+            Some(t) => t,
+            None => break,
+        };
+        let mut id = [0u8; 4];
+        id.copy_from_slice(&list[pos..pos + 4]);
+        if let Some(t) = riff_str(text) {
+            entries.push((id, t));
+        }
+        pos = start + size + (size & 1);
+    }
+
+    entries
+}
+
+// populates the builder from the fmt chunk, returning false if it's unusable
+fn apply_wav_fmt(builder: &mut ParseResultBuilder, fmt: &[u8]) -> bool {
+    let (format_tag, channels, sample_rate, bits) = match (
+        le_u16(fmt, 0),
+        le_u16(fmt, 2),
+        le_u32(fmt, 4),
+        le_u16(fmt, 14),
+    ) {
+        (Some(f), Some(c), Some(s), Some(b)) => (f, c, s, b),
+        _ => return false,
+    };
+
+    // WAVE_FORMAT_EXTENSIBLE stores the real format in the sub format GUID,
+    // and may store a smaller valid bit depth than the container size
+    let (format_tag, bits) = match format_tag {
+        WAVE_FORMAT_EXTENSIBLE => match (le_u16(fmt, 18), le_u16(fmt, 24)) {
+            (Some(valid), Some(sub)) if valid > 0 => (sub, valid),
+            (_, Some(sub)) => (sub, bits),
+            _ => return false,
+        },
+        f => (f, bits),
+    };
+
+    let sample_format = match format_tag {
+        WAVE_FORMAT_IEEE_FLOAT => SampleFormat::Float,
+        WAVE_FORMAT_PCM if bits <= 8 => SampleFormat::UnsignedInt,
+        WAVE_FORMAT_PCM => SampleFormat::SignedInt,
+        f => {
+            warn!("unsupported wav format tag {:#x} {:?}", f, builder);
+            return false;
+        }
+    };
+
+    builder.channels(channels);
+    builder.sample_rate(sample_rate);
+    builder.bit_depth(bits);
+    builder.sample_format(sample_format);
+    true
+}
+
+// v2.4 allows multiple values in one text frame, separated by a null byte
+fn id3_text_values(tag: &id3::Tag, frame_id: &str) -> Vec<String> {
+    tag.get(frame_id)
+        .and_then(|f| f.content().text())
+        .map(|t| {
+            t.split('\0')
+                .map(|v| v.trim())
+                .filter(|v| !v.is_empty())
+                .map(|v| v.to_owned())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn id3_text(tag: &id3::Tag, frame_id: &str) -> Option<String> {
+    id3_text_values(tag, frame_id).into_iter().next()
+}
+
+// user defined TXXX frames, matched on their description
+fn id3_extended_text(tag: &id3::Tag, description: &str) -> Option<String> {
+    tag.extended_texts()
+        .find(|t| t.description.eq_ignore_ascii_case(description))
+        .map(|t| t.value.trim().to_owned())
+        .filter(|v| !v.is_empty())
+}
+
+/// populates the builder from an ID3v2 tag. v2.2 frame ids are converted to
+/// their v2.3/v2.4 equivalents when the tag is read, so only those are handled
+/// https://id3.org/id3v2.4.0-frames
+fn apply_id3v2(builder: &mut ParseResultBuilder, tag: &id3::Tag) {
+    trace!("id3 {:?} tag {:?}", tag.version(), builder);
+
+    if let Some(t) = id3_text(tag, "TIT2") {
+        builder.track(t);
+    }
+
+    for a in id3_text_values(tag, "TPE1") {
+        builder.artist(a);
+    }
+
+    if let Some(a) = id3_text(tag, "TPE2") {
+        builder.album_artist(a);
+    }
+
+    // "interpreted, remixed, or otherwise modified by"
+    for a in id3_text_values(tag, "TPE4") {
+        builder.remixer(a);
+    }
+
+    // TIPL (v2.4) / IPLS (v2.3)
+    let people = tag.involved_people_lists().flat_map(|l| l.items.iter());
+    for p in people {
+        match p.involvement.to_lowercase().as_str() {
+            "producer" => builder.producer(p.involvee.clone()),
+            "remixer" | "mix" => builder.remixer(p.involvee.clone()),
+            _ => trace!("ignoring involved person {:?}", p),
+        }
+    }
+
+    // itunes compilation flag, not in the spec but widely used
+    if let Some(c) = id3_text(tag, "TCMP") {
+        builder.compilation(c == "1");
+    }
+
+    if let Some(a) = id3_text(tag, "TALB") {
+        builder.album(a);
+    }
+
+    // v2.4 replaced TYER (+ TDAT) with TDRC, but plenty of v2.3 tags have
+    // both. release time is the last resort
+    let date = id3_text(tag, "TDRC")
+        .or_else(|| id3_text(tag, "TYER"))
+        .or_else(|| id3_text(tag, "TDRL"));
+    if let Some(d) = date {
+        builder.date(d);
+    }
+
+    if let Some(t) = id3_text(tag, "TRCK") {
+        match parse_num_pair(&t) {
+            (Some(pos), total) => {
+                builder.track_pos(pos);
+                if let Some(total) = total {
+                    builder.track_total(total);
+                }
+            }
+            _ => warn!("failed to parse TRCK frame {:?} {:?}", builder, t),
+        }
+    }
+
+    if let Some(d) = id3_text(tag, "TPOS") {
+        match parse_num_pair(&d) {
+            (Some(pos), total) => {
+                builder.disc_pos(pos);
+                if let Some(total) = total {
+                    builder.disc_total(total);
+                }
+            }
+            _ => warn!("failed to parse TPOS frame {:?} {:?}", builder, d),
+        }
+    }
+
+    // v2.3 and older may reference v1 genres by index e.g. "(17)", which
+    // the parsed genre resolves. it only works on the whole frame though
+    let genres = id3_text_values(tag, "TCON");
+    match (genres.len(), tag.genre_parsed()) {
+        (1, Some(g)) => builder.genre(g.trim().to_owned()),
+        _ => genres.into_iter().for_each(|g| builder.genre(g)),
+    };
+
+    let composers = id3_text_values(tag, "TCOM");
+    if composers.len() > 0 {
+        builder.composer(composers.join("; "));
+    }
+
+    if let Some(b) = id3_text(tag, "TBPM") {
+        match b.parse() {
+            Ok(bpm) => builder.bpm(bpm),
+            Err(e) => {
+                warn!("failed to parse TBPM {:?} {:?} {:?}", builder, b, e)
+            }
+        }
+    }
+
+    if let Some(k) = id3_text(tag, "TKEY") {
+        builder.key(k);
+    }
+
+    if let Some(l) = id3_text(tag, "TPUB") {
+        builder.label(l);
+    }
+
+    // no dedicated frames for these, picard style TXXX is the most common
+    if let Some(c) = id3_extended_text(tag, "CATALOGNUMBER") {
+        builder.catalog_num(c);
+    }
+    if let Some(b) = id3_extended_text(tag, "BARCODE") {
+        builder.barcode(b);
+    }
+    let release_type = id3_extended_text(tag, "RELEASETYPE")
+        .or_else(|| id3_extended_text(tag, "MusicBrainz Album Type"));
+    if let Some(t) = release_type {
+        builder.release_type(t);
+    }
+    let edition = id3_extended_text(tag, "EDITION")
+        .or_else(|| id3_extended_text(tag, "MusicBrainz Album Comment"));
+    if let Some(e) = edition {
+        builder.edition(e);
+    }
+
+    // players stash their own data in described comments (e.g. iTunNORM),
+    // so prefer the one without a description
+    let comment = tag
+        .comments()
+        .find(|c| c.description.is_empty())
+        .or_else(|| tag.comments().next());
+    if let Some(c) = comment {
+        if !c.text.trim().is_empty() {
+            builder.comment(c.text.trim().to_owned());
+        }
+    }
+
+    for p in tag.pictures() {
+        match p.picture_type {
+            id3::frame::PictureType::CoverFront => {
+                builder.cover(p.data.clone());
+            }
+            _ => trace!("ignoring pic {:?}", p.description),
+        }
+    }
+}
+
+// only fills in what the v2 tag didn't have
+fn apply_id3v1(builder: &mut ParseResultBuilder, tag: &id3::v1::Tag) {
+    let non_empty = |s: &str| match s.trim() {
+        "" => None,
+        t => Some(t.to_owned()),
+    };
+
+    match non_empty(&tag.title) {
+        Some(t) if builder.track == None => builder.track(t),
+        _ => (),
+    };
+    match non_empty(&tag.artist) {
+        Some(a) if builder.artists.is_empty() => builder.artist(a),
+        _ => (),
+    };
+    match non_empty(&tag.album) {
+        Some(a) if builder.album == None => builder.album(a),
+        _ => (),
+    };
+    match non_empty(&tag.year) {
+        Some(y) if builder.date == None => builder.date(y),
+        _ => (),
+    };
+    match non_empty(&tag.comment) {
+        Some(c) if builder.comment == None => builder.comment(c),
+        _ => (),
+    };
+    match tag.track {
+        Some(t) if builder.track_pos == None && t > 0 => {
+            builder.track_pos(t as i32)
+        }
+        _ => (),
+    };
+    match tag.genre() {
+        Some(g) if builder.genres.is_empty() => builder.genre(g.to_owned()),
+        _ => (),
+    };
+}
+
+// metadata precedence is id3 chunk > LIST/INFO > bext. filename inference
+// in `complete` is only used when all of them are missing
 pub fn parse_wav(path: PathBuf) -> Option<ParseResult> {
     trace!("parsing wav {:?}", &path);
-    let w = match WavReader::open(&path) {
-        Ok(reader) => reader,
+    let mut builder = ParseResultBuilder::new(path);
+
+    let iff = match read_iff_chunks(builder.path(), false) {
+        Ok(c) => c,
         Err(e) => {
-            error!("failed to read wav {:?} {:?}", &path, e);
+            error!("failed to read wav {:?} {:?}", builder, e);
             return None;
         }
     };
 
-    let encoding = w.spec();
-    Some(ParseResult {
-        path,
-        artists: vec![UNKNOWN_ENTRY.to_string()],
-        album: UNKNOWN_ENTRY.to_string(),
-        track: UNKNOWN_ENTRY.to_string(),
-        date: None,
-        track_pos: None,
-        channels: encoding.channels,
-        bit_depth: encoding.bits_per_sample,
-        sample_rate: encoding.sample_rate,
-    })
+    match iff.get(b"fmt ") {
+        Some(fmt) if apply_wav_fmt(&mut builder, fmt) => (),
+        _ => {
+            error!("failed to read wav fmt chunk {:?}", builder);
+            return None;
+        }
+    };
+
+    if let Some(data) = iff.get(b"id3 ").or(iff.get(b"ID3 ")) {
+        match id3::Tag::read_from2(std::io::Cursor::new(data)) {
+            Ok(tag) => apply_id3v2(&mut builder, &tag),
+            Err(e) => {
+                warn!("failed to read wav id3 chunk {:?} {:?}", builder, e)
+            }
+        }
+    }
+
+    let info = iff
+        .chunks
+        .iter()
+        .filter(|(id, _)| id == b"LIST")
+        .flat_map(|(_, data)| riff_info_entries(data));
+    for (id, text) in info {
+        match &id {
+            b"INAM" if builder.track == None => builder.track(text),
+            b"IART" if builder.artists.is_empty() => builder.artist(text),
+            b"IPRD" if builder.album == None => builder.album(text),
+            b"ICRD" if builder.date == None => builder.date(text),
+            b"ITRK" | b"IPRT" if builder.track_pos == None => {
+                match parse_num_pair(&text) {
+                    (Some(pos), total) => {
+                        builder.track_pos(pos);
+                        if let Some(t) = total {
+                            builder.track_total(t);
+                        }
+                    }
+                    _ => warn!("failed to parse wav track number {:?}", text),
+                }
+            }
+            b"ICMT" if builder.comment == None => builder.comment(text),
+            b"IGNR" if builder.genres.is_empty() => builder.genre(text),
+            _ => trace!("ignoring wav info entry {:?} {:?}", id, text),
+        }
+    }
+
+    // broadcast wav. description is free text, usually the take/cue name
+    if let Some(bext) = iff.get(b"bext") {
+        let description = bext.get(0..256).and_then(riff_str);
+        let origination_date = bext.get(320..330).and_then(riff_str);
+        if let Some(d) = description {
+            if builder.track == None {
+                builder.track(d.clone());
+            }
+            if builder.comment == None {
+                builder.comment(d);
+            }
+        }
+        match origination_date {
+            Some(d) if builder.date == None => builder.date(d),
+            _ => (),
+        };
+    }
+
+    // only guess from the file name when the tags gave us nothing
+    if builder.artists.is_empty() && builder.track == None {
+        builder.infer_from_file_name();
+    }
+
+    builder.complete(true)
+}
+
+/// parses track/disc style numbers, which are often "n/total"
+pub fn parse_num_pair(s: &str) -> (Option<i32>, Option<i32>) {
+    let mut parts = s.splitn(2, '/').map(|p| p.trim().parse().ok());
+    let pos = parts.next().flatten();
+    let total = parts.next().flatten();
+    (pos, total)
 }
 
 pub fn parse_mp3(path: PathBuf) -> Option<ParseResult> {
@@ -318,90 +1203,275 @@ pub fn parse_mp3(path: PathBuf) -> Option<ParseResult> {
     // TODO what to input? mp3 bit depth is lost in the process
     builder.bit_depth(0);
 
-    let path_str = match builder.path().to_str() {
-        Some(p) => p,
-        None => {
-            error!("failed to parse mp3 path {:?}", builder);
+    // v2 tags are more complete, so v1 is only used to fill in the gaps
+    match id3::Tag::read_from_path(builder.path()) {
+        Ok(tag) => apply_id3v2(&mut builder, &tag),
+        Err(e) => match e.kind {
+            id3::ErrorKind::NoTag => trace!("no id3v2 tag {:?}", builder),
+            _ => warn!("failed to read id3v2 tag {:?} {:?}", builder, e),
+        },
+    };
+
+    match id3::v1::Tag::read_from_path(builder.path()) {
+        Ok(tag) => apply_id3v1(&mut builder, &tag),
+        Err(e) => match e.kind {
+            id3::ErrorKind::NoTag => trace!("no id3v1 tag {:?}", builder),
+            _ => warn!("failed to read id3v1 tag {:?} {:?}", builder, e),
+        },
+    };
+
+    builder.complete(true)
+}
+
+fn mp4_freeform(tag: &mp4ameta::Tag, name: &str) -> Option<String> {
+    let ident = mp4ameta::FreeformIdent::new("com.apple.iTunes", name);
+    tag.strings_of(&ident)
+        .map(|v| v.trim())
+        .find(|v| !v.is_empty())
+        .map(|v| v.to_owned())
+}
+
+// iTunes style metadata lives in the moov.udta.meta.ilst atom. the container
+// can hold either AAC (lossy) or ALAC (lossless), which only matters here for
+// the bit depth
+pub fn parse_mp4(path: PathBuf) -> Option<ParseResult> {
+    trace!("parsing mp4 {:?}", &path);
+    let mut builder = ParseResultBuilder::new(path);
+
+    let tag = match mp4ameta::Tag::read_from_path(builder.path()) {
+        Ok(t) => t,
+        Err(e) => {
+            error!("failed to read mp4 meta {:?} {:?}", builder, e);
             return None;
         }
     };
 
-    let meta_read = match MetadataReader::new(path_str) {
-        Ok(r) => r,
+    match fs::File::open(builder.path()).map(|f| alac::Reader::new(f)) {
+        Ok(Ok(r)) => {
+            let info = r.stream_info();
+            builder.channels(info.channels() as u16);
+            builder.bit_depth(info.bit_depth() as u16);
+            builder.sample_rate(info.sample_rate());
+        }
+        Ok(Err(_)) => {
+            trace!("mp4 is not alac, assuming aac {:?}", builder);
+            match (tag.channel_config(), tag.sample_rate()) {
+                (Some(c), Some(s)) => {
+                    builder.channels(c.channel_count() as u16);
+                    builder.sample_rate(s.hz());
+                }
+                _ => warn!("mp4 missing audio info {:?}", builder),
+            };
+            // same as mp3, there is no bit depth for lossy data
+            builder.bit_depth(0);
+        }
         Err(e) => {
-            error!("failed to read mp3 meta {:?} {:?}", builder, e);
+            error!("failed to open mp4 {:?} {:?}", builder, e);
             return None;
         }
     };
 
-    // TODO prioritize v1 or v2 tags?
-    // https://id3.org/id3v2.3.0#Declared_ID3v2_frames
-    for m in meta_read {
-        match m {
-            Unit::FrameV1(f) => {
-                builder.album(f.album);
-                builder.track(f.title);
-                builder.date(f.year);
-                builder.artist(f.artist);
-                match f.track.parse() {
-                    Ok(x) => builder.track_pos(x),
-                    Err(e) => warn!(
-                        "failed to parse track number from v1 framer {:?} \
-                         {:?} {:?}",
-                        builder, f.track, e
-                    ),
-                };
-            }
-            Unit::FrameV2(_, FrameBody::PIC(p)) => {
-                trace!("ignoring pic {:?}", p.description)
-            }
-            Unit::FrameV2(_, FrameBody::APIC(p)) => {
-                trace!("ignoring pic {:?}", p.description)
-            }
-            Unit::FrameV2(_, FrameBody::PRIV(p)) => {
-                trace!("ignoring priv tag from owner: {:?}", p.owner_identifier)
-            }
-            Unit::FrameV2(_, FrameBody::TALB(a)) => {
-                builder.album(a.text);
-            }
-            Unit::FrameV2(_, FrameBody::TIT2(t)) => {
-                builder.track(t.text);
-            }
-            Unit::FrameV2(_, FrameBody::TPE1(a)) => {
-                builder.artist(a.text);
-            }
-            Unit::FrameV2(_, FrameBody::TYER(y)) => {
-                builder.date(y.text);
-            }
-            Unit::FrameV2(_, FrameBody::TDAT(y)) => {
-                // should this be a backup? is this used
-                // for year sometimes as well?
-                // TODO more research on usage
-                trace!("date (date?) {:?}", y);
-                // date = y.text;
+    // ©nam
+    if let Some(t) = tag.title() {
+        builder.track(t.to_owned());
+    }
+
+    // ©ART
+    for artist in tag.artists() {
+        builder.artist(artist.to_owned());
+    }
+
+    // aART
+    if let Some(a) = tag.album_artist() {
+        builder.album_artist(a.to_owned());
+    }
+
+    // cpil
+    builder.compilation(tag.compilation());
+
+    // freeform ----:com.apple.iTunes atoms, as written by picard
+    if let Some(l) = mp4_freeform(&tag, "LABEL") {
+        builder.label(l);
+    }
+    if let Some(c) = mp4_freeform(&tag, "CATALOGNUMBER") {
+        builder.catalog_num(c);
+    }
+    if let Some(b) = mp4_freeform(&tag, "BARCODE") {
+        builder.barcode(b);
+    }
+    if let Some(t) = mp4_freeform(&tag, "MusicBrainz Album Type") {
+        builder.release_type(t);
+    }
+    if let Some(e) = mp4_freeform(&tag, "MusicBrainz Album Comment") {
+        builder.edition(e);
+    }
+
+    // ©alb
+    if let Some(a) = tag.album() {
+        builder.album(a.to_owned());
+    }
+
+    // ©gen
+    for g in tag.genres() {
+        builder.genre(g.to_owned());
+    }
+
+    // ©wrt
+    let composers: Vec<_> = tag.composers().collect();
+    if composers.len() > 0 {
+        builder.composer(composers.join("; "));
+    }
+
+    // tmpo
+    if let Some(b) = tag.bpm() {
+        builder.bpm(b as f64);
+    }
+
+    if let Some(k) = mp4_freeform(&tag, "initialkey") {
+        builder.key(k);
+    }
+
+    // ©day
+    if let Some(d) = tag.year() {
+        builder.date(d.to_owned());
+    }
+
+    // trkn
+    if let Some(t) = tag.track_number() {
+        builder.track_pos(t as i32);
+    }
+    if let Some(t) = tag.total_tracks() {
+        builder.track_total(t as i32);
+    }
+
+    // disk
+    if let Some(d) = tag.disc_number() {
+        builder.disc_pos(d as i32);
+    }
+    if let Some(d) = tag.total_discs() {
+        builder.disc_total(d as i32);
+    }
+
+    // covr
+    if let Some(img) = tag.artwork() {
+        builder.cover(img.data.to_vec());
+    }
+
+    builder.complete(true)
+}
+
+/// the parts of an AIFF/AIFC COMM chunk needed for playback
+#[derive(Debug)]
+pub struct AiffFormat {
+    pub channels: u16,
+    pub bit_depth: u16,
+    pub sample_rate: u32,
+    pub sample_format: SampleFormat,
+    pub little_endian: bool,
+}
+
+// sample rate is stored as an 80 bit IEEE 754 extended float
+fn extended_to_u32(b: &[u8]) -> Option<u32> {
+    let exp = (be_u16(b, 0)? & 0x7FFF) as i32 - 16383;
+    let mantissa = u64::from_be_bytes([
+        *b.get(2)?,
+        *b.get(3)?,
+        *b.get(4)?,
+        *b.get(5)?,
+        *b.get(6)?,
+        *b.get(7)?,
+        *b.get(8)?,
+        *b.get(9)?,
+    ]);
+    match exp {
+        0..=31 => Some((mantissa >> (63 - exp)) as u32),
+        _ => None,
+    }
+}
+
+impl AiffFormat {
+    pub fn from_chunks(iff: &IffChunks) -> Option<Self> {
+        let comm = iff.get(b"COMM")?;
+        let channels = be_u16(comm, 0)?;
+        let bit_depth = be_u16(comm, 6)?;
+        let sample_rate = extended_to_u32(comm.get(8..18)?)?;
+
+        // plain AIFF is always big endian signed ints. AIFC adds a
+        // compression type, of which only the uncompressed ones are handled
+        let compression = match &iff.form_type {
+            b"AIFC" => comm.get(18..22)?,
+            _ => &b"NONE"[..],
+        };
+        let (sample_format, little_endian) = match compression {
+            b"NONE" | b"twos" => (SampleFormat::SignedInt, false),
+            b"sowt" => (SampleFormat::SignedInt, true),
+            b"raw " => (SampleFormat::UnsignedInt, false),
+            b"fl32" | b"FL32" => (SampleFormat::Float, false),
+            c => {
+                warn!("unsupported aifc compression {:?}", c);
+                return None;
             }
-            Unit::FrameV2(_, FrameBody::TRCK(t)) => match t.text.parse() {
-                Ok(pos) => builder.track_pos(pos),
-                Err(e) => warn!(
-                    "failed to parse track number from TRCK frame {:?} {:?} \
-                     {:?}",
-                    builder, t, e
-                ),
-            },
-            Unit::FrameV2(_, b) => {
-                trace!("v2 tag {:?}", b);
+        };
+
+        Some(AiffFormat {
+            channels,
+            bit_depth,
+            sample_rate,
+            sample_format,
+            little_endian,
+        })
+    }
+}
+
+pub fn parse_aiff(path: PathBuf) -> Option<ParseResult> {
+    trace!("parsing aiff {:?}", &path);
+    let mut builder = ParseResultBuilder::new(path);
+
+    let iff = match read_iff_chunks(builder.path(), true) {
+        Ok(c) => c,
+        Err(e) => {
+            error!("failed to read aiff {:?} {:?}", builder, e);
+            return None;
+        }
+    };
+
+    match AiffFormat::from_chunks(&iff) {
+        Some(f) => {
+            builder.channels(f.channels);
+            builder.bit_depth(f.bit_depth);
+            builder.sample_rate(f.sample_rate);
+            builder.sample_format(f.sample_format);
+        }
+        None => {
+            error!("failed to read aiff COMM chunk {:?}", builder);
+            return None;
+        }
+    };
+
+    if let Some(data) = iff.get(b"ID3 ").or(iff.get(b"id3 ")) {
+        match id3::Tag::read_from2(std::io::Cursor::new(data)) {
+            Ok(tag) => apply_id3v2(&mut builder, &tag),
+            Err(e) => {
+                warn!("failed to read aiff id3 chunk {:?} {:?}", builder, e)
             }
-            Unit::Header(h) => trace!("header {:?}", h),
-            Unit::ExtendedHeader(h) => trace!("ext header {:?}", h),
         }
     }
 
-    builder.complete(true)
-}
+    // fallback text chunks from the original AIFF spec
+    match iff.get(b"NAME").and_then(riff_str) {
+        Some(n) if builder.track == None => builder.track(n),
+        _ => (),
+    };
+    match iff.get(b"AUTH").and_then(riff_str) {
+        Some(a) if builder.artists.is_empty() => builder.artist(a),
+        _ => (),
+    };
+    match iff.get(b"ANNO").and_then(riff_str) {
+        Some(a) if builder.comment == None => builder.comment(a),
+        _ => (),
+    };
 
-pub fn parse_aiff(p: PathBuf) -> Option<ParseResult> {
-    trace!("skipping aif/f file {:?}", &p);
-    None
+    builder.complete(true)
 }
 
 pub const FLAC: &'static str = "flac";
@@ -409,17 +1479,271 @@ pub const WAV: &'static str = "wav";
 pub const MP3: &'static str = "mp3";
 pub const AIF: &'static str = "aif";
 pub const AIFF: &'static str = "aiff";
+pub const M4A: &'static str = "m4a";
+pub const MP4: &'static str = "mp4";
+pub const OGG: &'static str = "ogg";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AudioFormat {
+    Flac,
+    Wav,
+    Mp3,
+    Aiff,
+    Mp4,
+    Ogg,
+}
+
+impl AudioFormat {
+    pub fn from_ext(ext: &str) -> Option<Self> {
+        match ext.to_lowercase().as_str() {
+            FLAC => Some(AudioFormat::Flac),
+            WAV => Some(AudioFormat::Wav),
+            MP3 => Some(AudioFormat::Mp3),
+            AIF | AIFF => Some(AudioFormat::Aiff),
+            M4A | MP4 => Some(AudioFormat::Mp4),
+            OGG => Some(AudioFormat::Ogg),
+            _ => None,
+        }
+    }
+
+    /// only identifies formats with an unambiguous signature. a bare mpeg
+    /// frame sync is too easy to hit by accident, see `is_mpeg_sync`
+    pub fn from_magic(header: &[u8]) -> Option<Self> {
+        let magic = header.get(0..4);
+        let form_type = header.get(8..12);
+        match (magic, form_type) {
+            (Some(b"fLaC"), _) => Some(AudioFormat::Flac),
+            (Some(b"RIFF"), Some(b"WAVE")) => Some(AudioFormat::Wav),
+            (Some(b"FORM"), Some(t)) if t == b"AIFF" || t == b"AIFC" => {
+                Some(AudioFormat::Aiff)
+            }
+            (Some(b"OggS"), _) => Some(AudioFormat::Ogg),
+            _ if header.get(4..8) == Some(&b"ftyp"[..]) => {
+                Some(AudioFormat::Mp4)
+            }
+            _ => None,
+        }
+    }
+}
+
+fn is_mpeg_sync(header: &[u8]) -> bool {
+    match header {
+        [0xFF, b, ..] => b & 0xE0 == 0xE0,
+        _ => false,
+    }
+}
+
+// ID3v2 header is 10 bytes, followed by a syncsafe size, plus an optional
+// 10 byte footer
+pub(crate) fn id3v2_len(header: &[u8]) -> Option<u64> {
+    match header {
+        [b'I', b'D', b'3', _, _, flags, a, b, c, d, ..] => {
+            let size = ((*a as u64 & 0x7F) << 21)
+                | ((*b as u64 & 0x7F) << 14)
+                | ((*c as u64 & 0x7F) << 7)
+                | (*d as u64 & 0x7F);
+            let footer = if flags & 0x10 != 0 { 10 } else { 0 };
+            Some(10 + size + footer)
+        }
+        _ => None,
+    }
+}
+
+/// opens a flac file positioned at the "fLaC" marker. claxon rejects
+/// streams starting with the ID3v2 tags some taggers prepend, so they're
+/// skipped
+pub fn open_flac(path: &Path) -> std::io::Result<fs::File> {
+    let mut file = fs::File::open(path)?;
+    let mut start = 0;
+    let mut header = [0u8; 10];
+    loop {
+        file.seek(SeekFrom::Start(start))?;
+        let len = read_header(&mut file, &mut header)?;
+        match id3v2_len(&header[..len]) {
+            Some(tag_len) => start += tag_len,
+            None => break,
+        }
+    }
+    file.seek(SeekFrom::Start(start))?;
+    Ok(file)
+}
+
+fn read_header(file: &mut fs::File, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match file.read(&mut buf[read..])? {
+            0 => break,
+            n => read += n,
+        }
+    }
+    Ok(read)
+}
+
+/// sniffs the file contents to figure out the format, using the extension
+/// as a hint when the contents are ambiguous (or unreadable)
+pub fn detect_format(path: &Path) -> Option<AudioFormat> {
+    let ext_hint = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .and_then(AudioFormat::from_ext);
+
+    let mut file = match fs::File::open(path) {
+        Ok(f) => f,
+        Err(e) => {
+            error!("failed to open file for detection {:?} {:?}", path, e);
+            return None;
+        }
+    };
+
+    let mut header = [0u8; 12];
+    let len = match read_header(&mut file, &mut header) {
+        Ok(l) => l,
+        Err(e) => {
+            warn!("failed to read header {:?} {:?}", path, e);
+            return ext_hint;
+        }
+    };
+    let mut header = &header[..len];
+
+    // ID3v2 tags are mostly found on mp3s, but they get prepended to other
+    // formats (flac especially) by some taggers. skip over it and look again
+    let mut after_id3 = [0u8; 12];
+    let has_id3 = match id3v2_len(header) {
+        Some(tag_len) => {
+            let after_len = file
+                .seek(SeekFrom::Start(tag_len))
+                .and_then(|_| read_header(&mut file, &mut after_id3))
+                .unwrap_or(0);
+            header = &after_id3[..after_len];
+            true
+        }
+        None => false,
+    };
+
+    match AudioFormat::from_magic(header) {
+        Some(f) => {
+            if ext_hint.is_some() && ext_hint != Some(f) {
+                debug!("file ext doesn't match contents {:?} {:?}", path, f);
+            }
+            Some(f)
+        }
+        None if has_id3 || is_mpeg_sync(header) => Some(AudioFormat::Mp3),
+        None => ext_hint,
+    }
+}
+
+/// (size in bytes, modified time in seconds since the unix epoch)
+pub fn file_stat(path: &Path) -> Option<(u64, i64)> {
+    let meta = fs::metadata(path).ok()?;
+    let mtime = meta
+        .modified()
+        .ok()?
+        .duration_since(std::time::UNIX_EPOCH)
+        .ok()?;
+    Some((meta.len(), mtime.as_secs() as i64))
+}
 
-// TODO handle ext casing, normalize?
 pub fn parse_track(path: PathBuf) -> Option<ParseResult> {
-    match path.extension().and_then(|e| e.to_str()) {
-        Some(e) if e == FLAC => parse_flac(path),
-        Some(e) if e == WAV => parse_wav(path),
-        Some(e) if e == MP3 => parse_mp3(path),
-        Some(e) if (e == AIF || e == AIFF) => parse_aiff(path),
-        some_ext => {
-            debug!("skipping unsupported file type {:?}", some_ext);
+    let format = detect_format(&path);
+    let result = match format {
+        Some(AudioFormat::Flac) => parse_flac(path),
+        Some(AudioFormat::Wav) => parse_wav(path),
+        Some(AudioFormat::Mp3) => parse_mp3(path),
+        Some(AudioFormat::Aiff) => parse_aiff(path),
+        Some(AudioFormat::Mp4) => parse_mp4(path),
+        Some(AudioFormat::Ogg) => {
+            debug!("skipping unsupported ogg file {:?}", path);
+            None
+        }
+        None => {
+            debug!("skipping unsupported file type {:?}", path);
             None
         }
+    };
+
+    // parsing already read the file, hashing now keeps it in the page cache
+    result.map(|mut r| {
+        r.audio_hash = match hash::audio_hash(&r.path, format.unwrap()) {
+            Ok(h) => Some(h),
+            Err(e) => {
+                warn!("failed to hash audio {:?} {:?}", r.path, e);
+                None
+            }
+        };
+        if let Some((size, mtime)) = file_stat(&r.path) {
+            r.file_size = Some(size);
+            r.file_mtime = Some(mtime);
+        }
+        r
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{flac_stream, temp_file};
+
+    #[test]
+    fn flac_behind_id3() {
+        let mut tag = Vec::new();
+        let mut id3 = id3::Tag::new();
+        id3.set_title("From ID3");
+        id3.write_to(&mut tag, id3::Version::Id3v24).unwrap();
+        tag.extend_from_slice(&flac_stream("From FLAC"));
+        let path = temp_file("id3.flac", &tag);
+
+        assert_eq!(detect_format(&path), Some(AudioFormat::Flac));
+        let parsed = parse_flac(path.clone()).unwrap();
+        assert_eq!(parsed.track, "From FLAC");
+        // same reader playback decodes with
+        let reader = FlacReader::new(open_flac(&path).unwrap()).unwrap();
+        assert_eq!(reader.streaminfo().sample_rate, 44100);
+        fs::remove_file(&path).unwrap();
+    }
+
+    fn featured(main: &str, artists: &[&str]) -> Option<(String, Vec<String>)> {
+        let artists = artists.iter().map(|a| a.to_string()).collect();
+        Some((main.to_owned(), artists))
+    }
+
+    #[test]
+    fn featured_credits() {
+        let split = split_featured;
+        assert_eq!(split("Artist feat. Other"), featured("Artist", &["Other"]));
+        assert_eq!(split("Artist Ft Other"), featured("Artist", &["Other"]));
+        assert_eq!(split("Track (feat. Other)"), featured("Track", &["Other"]));
+        assert_eq!(split("Main (feat. A, B)"), featured("Main", &["A", "B"]));
+        assert_eq!(split("Main [ft. A & B]"), featured("Main", &["A", "B"]));
+        assert_eq!(
+            split("Main (featuring A, B & C)"),
+            featured("Main", &["A", "B", "C"])
+        );
+        assert_eq!(split("Aftermath"), None);
+        assert_eq!(split("Loft Party"), None);
+        assert_eq!(split("feat. Other"), None);
+        assert_eq!(split("Artist (feat. )"), None);
+    }
+
+    #[test]
+    fn num_pairs() {
+        assert_eq!(parse_num_pair("3/12"), (Some(3), Some(12)));
+        assert_eq!(parse_num_pair(" 3 / 12 "), (Some(3), Some(12)));
+        assert_eq!(parse_num_pair("7"), (Some(7), None));
+        assert_eq!(parse_num_pair("/9"), (None, Some(9)));
+        assert_eq!(parse_num_pair(""), (None, None));
+        assert_eq!(parse_num_pair("a/b"), (None, None));
+    }
+
+    #[test]
+    fn release_types() {
+        let norm = |t| normalize_release_type(t);
+        assert_eq!(norm("Album"), Some("album".to_owned()));
+        assert_eq!(norm("E.P."), Some("ep".to_owned()));
+        assert_eq!(norm("LP"), Some("album".to_owned()));
+        assert_eq!(norm("album; compilation"), Some("album".to_owned()));
+        assert_eq!(norm("single/remix"), Some("single".to_owned()));
+        assert_eq!(norm(" ;compilation"), Some("compilation".to_owned()));
+        assert_eq!(norm(""), None);
+        assert_eq!(norm(" ; "), None);
     }
 }
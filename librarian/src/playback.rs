@@ -1,27 +1,35 @@
+use crate::parse::{self, AudioFormat};
 use claxon::FlacReader;
 use cpal::{
     traits::{DeviceTrait, HostTrait, StreamTrait},
     Sample, SupportedStreamConfigRange,
 };
 use log::{debug, error, trace};
+use std::io::{self, Read, Seek, SeekFrom};
 use std::path::PathBuf;
-use std::sync::mpsc::{Receiver, SyncSender};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, SyncSender};
+use std::sync::Arc;
+use std::time::Duration;
 
 trait SampleConvertIter<S: cpal::Sample>: Iterator {
     fn to_sample(val: Self::Item) -> S;
 }
 
-// may need cpal::SampleFormat prop or way to determine format is signed / float
 #[derive(Debug)]
 pub struct AudioMetadata {
     pub channels: u16,
     pub bit_depth: u16,
     pub sample_rate: u32,
+    pub sample_format: parse::SampleFormat,
+    /// per channel. None if the format doesn't say without decoding it all
+    pub total_frames: Option<u64>,
 }
 
 pub enum SampleReceiver {
     I16(Receiver<i16>),
     I32(Receiver<i32>),
+    F32(Receiver<f32>),
 }
 
 impl From<Receiver<i16>> for SampleReceiver {
@@ -36,6 +44,12 @@ impl From<Receiver<i32>> for SampleReceiver {
     }
 }
 
+impl From<Receiver<f32>> for SampleReceiver {
+    fn from(rx: Receiver<f32>) -> SampleReceiver {
+        SampleReceiver::F32(rx)
+    }
+}
+
 macro_rules! sample_channel_generator {
     ($fn_name:ident, $Reader:ty, $SamplePrimitive:ty, $transform:expr) => {
         pub fn $fn_name(
@@ -83,6 +97,14 @@ sample_channel_generator!(
     |x: Result<i32, claxon::Error>| x.unwrap()
 );
 
+// hound converts unsigned 8 bit to signed, so only the scale is needed
+sample_channel_generator!(
+    wav_sample_chan_i8,
+    hound::WavReader<std::fs::File>,
+    i16,
+    |x: Result<i8, hound::Error>| (x.unwrap() as i16) << 8
+);
+
 sample_channel_generator!(
     wav_sample_chan_i16,
     hound::WavReader<std::fs::File>,
@@ -105,6 +127,44 @@ sample_channel_generator!(
     |x: Result<i32, hound::Error>| x.unwrap()
 );
 
+sample_channel_generator!(
+    wav_sample_chan_f32,
+    hound::WavReader<std::fs::File>,
+    f32,
+    |x: Result<f32, hound::Error>| x.unwrap()
+);
+
+sample_channel_generator!(
+    alac_sample_chan_i16,
+    alac::Reader<std::fs::File>,
+    i16,
+    |x: Result<i16, alac::InvalidData>| x.unwrap()
+);
+
+// alac 20 bit samples are shifted up so they can share the 24 bit path
+sample_channel_generator!(
+    alac_sample_chan_i20,
+    alac::Reader<std::fs::File>,
+    i32,
+    |x: Result<i32, alac::InvalidData>| cpal::Unpacked24::new(x.unwrap() << 4)
+        .to_i32()
+);
+
+sample_channel_generator!(
+    alac_sample_chan_i24,
+    alac::Reader<std::fs::File>,
+    i32,
+    |x: Result<i32, alac::InvalidData>| cpal::Unpacked24::new(x.unwrap())
+        .to_i32()
+);
+
+sample_channel_generator!(
+    alac_sample_chan_i32,
+    alac::Reader<std::fs::File>,
+    i32,
+    |x: Result<i32, alac::InvalidData>| x.unwrap()
+);
+
 // FIXME this can be replaced with a macro once there is common iter trait
 // hound + claxon both have the `samples` fn available to iter thru samples
 fn mp3_sample_chan_i16(
@@ -128,22 +188,185 @@ fn mp3_sample_chan_i16(
     (SampleReceiver::I16(rx), parse_thread)
 }
 
+// reads uncompressed pcm straight from the file, for formats without a
+// dedicated decoder
+fn pcm_sample_chan<S>(
+    mut file: std::fs::File,
+    data_len: u64,
+    sample_width: usize,
+    convert: fn(&[u8]) -> S,
+) -> (Receiver<S>, std::thread::JoinHandle<()>)
+where
+    S: Send + 'static,
+{
+    let (tx, rx) = std::sync::mpsc::channel();
+    let parse_thread = std::thread::spawn(move || {
+        let mut buf = vec![0u8; sample_width * 1024];
+        let mut remaining = data_len as usize;
+        while remaining >= sample_width {
+            let to_read = std::cmp::min(buf.len(), remaining);
+            let to_read = to_read - (to_read % sample_width);
+            if let Err(e) = file.read_exact(&mut buf[..to_read]) {
+                error!("failed to read pcm data {:?}", e);
+                break;
+            }
+            remaining -= to_read;
+
+            for s in buf[..to_read].chunks_exact(sample_width) {
+                if let Err(e) = tx.send(convert(s)) {
+                    trace!("sample tx chan closed {:?}", e);
+                    return;
+                }
+            }
+        }
+    });
+
+    (rx, parse_thread)
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn decode_error<E: std::fmt::Debug>(msg: &str, e: E) -> io::Error {
+    invalid(&format!("{} {:?}", msg, e))
+}
+
+fn unsupported(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, msg)
+}
+
+fn i24_from_be(b: &[u8]) -> i32 {
+    // shift into the top bytes and back down to sign extend
+    i32::from_be_bytes([b[0], b[1], b[2], 0]) >> 8
+}
+
+fn i24_from_le(b: &[u8]) -> i32 {
+    i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8
+}
+
+fn aiff_sample_chan(
+    path: &PathBuf,
+    format: &parse::AiffFormat,
+) -> io::Result<(SampleReceiver, std::thread::JoinHandle<()>)> {
+    let iff = parse::read_iff_chunks(path, true)?;
+    let (chunk_offset, chunk_len) = iff
+        .audio_data
+        .ok_or_else(|| invalid("aiff missing SSND chunk"))?;
+
+    // SSND starts with an offset + block size before the sample data
+    let mut file = std::fs::File::open(path)?;
+    let mut ssnd_header = [0u8; 8];
+    file.seek(SeekFrom::Start(chunk_offset))?;
+    file.read_exact(&mut ssnd_header)?;
+    let data_offset = u32::from_be_bytes([
+        ssnd_header[0],
+        ssnd_header[1],
+        ssnd_header[2],
+        ssnd_header[3],
+    ]) as u64;
+    file.seek(SeekFrom::Current(data_offset as i64))?;
+    let data_len = chunk_len
+        .checked_sub(8 + data_offset)
+        .ok_or_else(|| invalid("aiff sample offset past end of SSND"))?;
+
+    use parse::SampleFormat::*;
+    let le = format.little_endian;
+    Ok(match (format.sample_format, format.bit_depth) {
+        (SignedInt, 8) => {
+            let convert = |b: &[u8]| (b[0] as i8 as i16) << 8;
+            let (rx, t) = pcm_sample_chan(file, data_len, 1, convert);
+            (rx.into(), t)
+        }
+        (UnsignedInt, 8) => {
+            let convert = |b: &[u8]| (b[0] as i16 - 128) << 8;
+            let (rx, t) = pcm_sample_chan(file, data_len, 1, convert);
+            (rx.into(), t)
+        }
+        (SignedInt, 16) => {
+            let convert: fn(&[u8]) -> _ = match le {
+                true => |b: &[u8]| i16::from_le_bytes([b[0], b[1]]),
+                false => |b: &[u8]| i16::from_be_bytes([b[0], b[1]]),
+            };
+            let (rx, t) = pcm_sample_chan(file, data_len, 2, convert);
+            (rx.into(), t)
+        }
+        (SignedInt, 24) => {
+            let convert: fn(&[u8]) -> _ = match le {
+                true => {
+                    |b: &[u8]| cpal::Unpacked24::new(i24_from_le(b)).to_i32()
+                }
+                false => {
+                    |b: &[u8]| cpal::Unpacked24::new(i24_from_be(b)).to_i32()
+                }
+            };
+            let (rx, t) = pcm_sample_chan(file, data_len, 3, convert);
+            (rx.into(), t)
+        }
+        (SignedInt, 32) => {
+            let convert: fn(&[u8]) -> _ = match le {
+                true => |b: &[u8]| i32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+                false => {
+                    |b: &[u8]| i32::from_be_bytes([b[0], b[1], b[2], b[3]])
+                }
+            };
+            let (rx, t) = pcm_sample_chan(file, data_len, 4, convert);
+            (rx.into(), t)
+        }
+        (Float, 32) => {
+            let convert =
+                |b: &[u8]| f32::from_be_bytes([b[0], b[1], b[2], b[3]]);
+            let (rx, t) = pcm_sample_chan(file, data_len, 4, convert);
+            (rx.into(), t)
+        }
+        _ => {
+            return Err(unsupported(format!(
+                "unsupported aiff format {:?}",
+                format
+            )))
+        }
+    })
+}
+
+fn aac_sample_chan_i16(
+    reader: redlux::Decoder<std::io::BufReader<std::fs::File>>,
+) -> (SampleReceiver, std::thread::JoinHandle<()>) {
+    let (tx, rx) = std::sync::mpsc::channel();
+    let parse_thread = std::thread::spawn(move || {
+        for s in reader {
+            match tx.send(s) {
+                Ok(_) => (),
+                Err(e) => {
+                    trace!("sample tx chan closed {:?}", e);
+                    break;
+                }
+            }
+        }
+    });
+
+    (SampleReceiver::I16(rx), parse_thread)
+}
+
 pub fn create_sample_channel(
     path: PathBuf,
-) -> (SampleReceiver, std::thread::JoinHandle<()>, AudioMetadata) {
-    let track_file =
-        std::fs::File::open(&path).expect("Unable to open track file");
-    match path.extension() {
-        Some(e) if e == crate::parse::FLAC => {
+) -> io::Result<(SampleReceiver, std::thread::JoinHandle<()>, AudioMetadata)> {
+    let track_file = std::fs::File::open(&path)?;
+    Ok(match crate::parse::detect_format(&path) {
+        Some(AudioFormat::Flac) => {
             debug!("Got flac");
-            let r = FlacReader::new(track_file).unwrap();
+            let r = parse::open_flac(&path)
+                .map_err(claxon::Error::from)
+                .and_then(FlacReader::new)
+                .map_err(|e| decode_error("unable to read flac", e))?;
 
             let meta = r.streaminfo();
             let (rx, parse_thread) = match meta.bits_per_sample as u16 {
                 16 => flac_sample_chan_i16(r),
                 24 => flac_sample_chan_i24(r),
                 32 => flac_sample_chan_i32(r),
-                _ => unimplemented!("unsupported bitrate flac"),
+                b => {
+                    return Err(unsupported(format!("unsupported flac {}", b)))
+                }
             };
 
             (
@@ -153,21 +376,47 @@ pub fn create_sample_channel(
                     channels: meta.channels as u16,
                     bit_depth: meta.bits_per_sample as u16,
                     sample_rate: meta.sample_rate,
+                    sample_format: parse::SampleFormat::SignedInt,
+                    total_frames: meta.samples,
                 },
             )
         }
-        Some(e) if e == crate::parse::WAV => {
+        Some(AudioFormat::Wav) => {
             debug!("Got wav");
-            let r = hound::WavReader::new(track_file).unwrap();
+            let r = hound::WavReader::new(track_file)
+                .map_err(|e| decode_error("unable to read wav", e))?;
 
-            // TODO check for 32 bit floats? need to support in AudioMetadata
             let meta = r.spec();
-            let (rx, parse_thread) = match meta.bits_per_sample {
-                16 => wav_sample_chan_i16(r),
-                24 => wav_sample_chan_i24(r),
-                32 => wav_sample_chan_i32(r),
-                _ => unimplemented!("unsupported bitrate wav"),
-            };
+            let total_frames = r.duration() as u64;
+            let (rx, parse_thread, sample_format) =
+                match (meta.sample_format, meta.bits_per_sample) {
+                    (hound::SampleFormat::Int, 8) => {
+                        let (rx, pt) = wav_sample_chan_i8(r);
+                        (rx, pt, parse::SampleFormat::UnsignedInt)
+                    }
+                    (hound::SampleFormat::Int, 16) => {
+                        let (rx, pt) = wav_sample_chan_i16(r);
+                        (rx, pt, parse::SampleFormat::SignedInt)
+                    }
+                    (hound::SampleFormat::Int, 24) => {
+                        let (rx, pt) = wav_sample_chan_i24(r);
+                        (rx, pt, parse::SampleFormat::SignedInt)
+                    }
+                    (hound::SampleFormat::Int, 32) => {
+                        let (rx, pt) = wav_sample_chan_i32(r);
+                        (rx, pt, parse::SampleFormat::SignedInt)
+                    }
+                    (hound::SampleFormat::Float, 32) => {
+                        let (rx, pt) = wav_sample_chan_f32(r);
+                        (rx, pt, parse::SampleFormat::Float)
+                    }
+                    (f, b) => {
+                        return Err(unsupported(format!(
+                            "unsupported wav {:?} {}",
+                            f, b
+                        )))
+                    }
+                };
 
             (
                 rx,
@@ -176,13 +425,38 @@ pub fn create_sample_channel(
                     channels: meta.channels,
                     bit_depth: meta.bits_per_sample,
                     sample_rate: meta.sample_rate,
+                    sample_format,
+                    total_frames: Some(total_frames),
+                },
+            )
+        }
+        Some(AudioFormat::Aiff) => {
+            debug!("Got aiff");
+            let format = parse::read_iff_chunks(&path, true)
+                .ok()
+                .as_ref()
+                .and_then(parse::AiffFormat::from_chunks)
+                .ok_or_else(|| invalid("unable to read aiff format"))?;
+
+            let (rx, parse_thread) = aiff_sample_chan(&path, &format)?;
+            (
+                rx,
+                parse_thread,
+                AudioMetadata {
+                    channels: format.channels,
+                    bit_depth: format.bit_depth,
+                    sample_rate: format.sample_rate,
+                    sample_format: format.sample_format,
+                    total_frames: None,
                 },
             )
         }
-        Some(e) if e == crate::parse::MP3 => {
+        Some(AudioFormat::Mp3) => {
             let mut r = minimp3::Decoder::new(track_file);
             // FIXME losing first frame to get sample rate
-            let frame_meta = r.next_frame().unwrap();
+            let frame_meta = r
+                .next_frame()
+                .map_err(|e| decode_error("unable to read mp3", e))?;
 
             let (rx, parse_thread) = mp3_sample_chan_i16(r);
             (
@@ -193,13 +467,66 @@ pub fn create_sample_channel(
                     // convert kbits/sec to bits/sample(?)? or is it only i16?
                     bit_depth: 16,
                     sample_rate: frame_meta.sample_rate as u32,
+                    sample_format: parse::SampleFormat::SignedInt,
+                    total_frames: None,
                 },
             )
         }
-        x => {
-            unimplemented!("unsupported format {:?}", x);
+        Some(AudioFormat::Mp4) => {
+            // the container doesn't say which codec it holds up front, so try
+            // alac first and fall back to aac
+            match alac::Reader::new(track_file) {
+                Ok(r) => {
+                    debug!("Got alac");
+                    let meta = r.stream_info();
+                    let audio_meta = AudioMetadata {
+                        channels: meta.channels() as u16,
+                        bit_depth: meta.bit_depth() as u16,
+                        sample_rate: meta.sample_rate(),
+                        sample_format: parse::SampleFormat::SignedInt,
+                        total_frames: None,
+                    };
+                    let (rx, parse_thread) = match audio_meta.bit_depth {
+                        16 => alac_sample_chan_i16(r),
+                        20 => alac_sample_chan_i20(r),
+                        24 => alac_sample_chan_i24(r),
+                        32 => alac_sample_chan_i32(r),
+                        b => {
+                            return Err(unsupported(format!(
+                                "unsupported alac {}",
+                                b
+                            )))
+                        }
+                    };
+
+                    (rx, parse_thread, audio_meta)
+                }
+                Err(_) => {
+                    debug!("Got aac");
+                    let track_file = std::fs::File::open(&path)?;
+                    let file_size = track_file.metadata()?.len();
+                    let r = redlux::Decoder::new_mpeg4(
+                        std::io::BufReader::new(track_file),
+                        file_size,
+                    )
+                    .map_err(|e| decode_error("unable to read aac", e))?;
+
+                    let audio_meta = AudioMetadata {
+                        channels: r.channels() as u16,
+                        // aac decodes to i16 like mp3
+                        bit_depth: 16,
+                        sample_rate: r.sample_rate(),
+                        sample_format: parse::SampleFormat::SignedInt,
+                        total_frames: None,
+                    };
+                    let (rx, parse_thread) = aac_sample_chan_i16(r);
+
+                    (rx, parse_thread, audio_meta)
+                }
+            }
         }
-    }
+        x => return Err(unsupported(format!("unsupported format {:?}", x))),
+    })
 }
 
 fn get_output_stream<O, I>(
@@ -207,6 +534,7 @@ fn get_output_stream<O, I>(
     sample_chan: std::sync::mpsc::Receiver<I>,
     config: &cpal::StreamConfig,
     channels: usize,
+    progress: Arc<Progress>,
     // ) -> Result<cpal::Stream, cpal::BuildStreamError>
 ) -> Result<cpal::Stream, ()>
 where
@@ -219,6 +547,7 @@ where
             .build_output_stream(
                 config,
                 move |data: &mut [O], _conf: &cpal::OutputCallbackInfo| {
+                    let mut frames = 0;
                     'buffer_iter: for frame in
                         data.chunks_mut(stream_chans as usize)
                     {
@@ -227,13 +556,19 @@ where
                                 Ok(s) => {
                                     frame[point] = cpal::Sample::from::<I>(&s);
                                 }
+                                // the parse thread is done, end of the track
                                 Err(e) => {
                                     error!("sample rx channel closed {:?}", e);
+                                    progress
+                                        .finished
+                                        .store(true, Ordering::Relaxed);
                                     break 'buffer_iter;
                                 }
                             };
                         }
+                        frames += 1;
                     }
+                    progress.frames.fetch_add(frames, Ordering::Relaxed);
                 },
                 move |err| {
                     // TODO proper handling
@@ -245,7 +580,7 @@ where
     )
 }
 
-// FIXME account for U16/U32 i/o -- needs to be accomodated in AudioMetadata
+// FIXME account for U16/U32 output
 fn get_config_score(
     input_meta: &AudioMetadata,
     config: &SupportedStreamConfigRange,
@@ -262,16 +597,26 @@ fn get_config_score(
     // +2 input audio requires no conversion to output format
     // +1 input audio requires upcast (ideally lossless but prob lossy)
     // 0 audio requires downcast (lossy)
+    // 8 bit input is scaled up to i16 before it gets here
     let format_score = match (input_meta.bit_depth, config.sample_format()) {
+        // float only stays lossless as float. i32 keeps the full 24 bit
+        // mantissa, anything smaller truncates
+        (_, o) if input_meta.sample_format == parse::SampleFormat::Float => {
+            match o {
+                cpal::SampleFormat::F32 => 2,
+                cpal::SampleFormat::I32 => 1,
+                _ => 0,
+            }
+        }
         (i, o)
-            if (i == 16 && o == cpal::SampleFormat::I16)
+            if (i <= 16 && o == cpal::SampleFormat::I16)
                 || (i == 24 && o == cpal::SampleFormat::I24)
                 || (i == 32 && o == cpal::SampleFormat::I32) =>
         {
             2
         }
         (i, o)
-            if (i == 16
+            if (i <= 16
                 && (o == cpal::SampleFormat::I24
                     || o == cpal::SampleFormat::I32
                     || o == cpal::SampleFormat::F32))
@@ -285,10 +630,11 @@ fn get_config_score(
     channel_score + format_score
 }
 
-// TODO this should return an error if the track is not available. update db?
+// the file is expected to exist, see `Library::play_track`
 pub fn create_stream(
     source: PathBuf,
-) -> (cpal::Stream, std::thread::JoinHandle<()>) {
+    progress: Arc<Progress>,
+) -> io::Result<(cpal::Stream, std::thread::JoinHandle<()>)> {
     let host = cpal::default_host();
 
     let device = host
@@ -297,9 +643,16 @@ pub fn create_stream(
 
     debug!("selected device {:?}", device.name().unwrap());
 
-    let (sample_rx, parse_thread, input_meta) = create_sample_channel(source);
+    let (sample_rx, parse_thread, input_meta) = create_sample_channel(source)?;
 
     debug!("selected track meta {:?}", input_meta);
+    progress
+        .sample_rate
+        .store(input_meta.sample_rate, Ordering::Relaxed);
+    progress.total_frames.store(
+        input_meta.total_frames.unwrap_or_default(),
+        Ordering::Relaxed,
+    );
 
     let mut sorted_configs = device
         .supported_output_configs()
@@ -341,6 +694,7 @@ pub fn create_stream(
                 rx,
                 &config,
                 audio_chans as usize,
+                progress,
             )
         }
         (cpal::SampleFormat::U16, SampleReceiver::I32(rx)) => {
@@ -349,6 +703,7 @@ pub fn create_stream(
                 rx,
                 &config,
                 audio_chans as usize,
+                progress,
             )
         }
         (cpal::SampleFormat::I16, SampleReceiver::I16(rx)) => {
@@ -357,6 +712,7 @@ pub fn create_stream(
                 rx,
                 &config,
                 audio_chans as usize,
+                progress,
             )
         }
         (cpal::SampleFormat::I16, SampleReceiver::I32(rx)) => {
@@ -365,6 +721,7 @@ pub fn create_stream(
                 rx,
                 &config,
                 audio_chans as usize,
+                progress,
             )
         }
         (cpal::SampleFormat::I24, SampleReceiver::I32(rx))
@@ -375,6 +732,7 @@ pub fn create_stream(
                 rx,
                 &config,
                 audio_chans as usize,
+                progress,
             )
         }
         (cpal::SampleFormat::I24, SampleReceiver::I16(rx))
@@ -385,6 +743,7 @@ pub fn create_stream(
                 rx,
                 &config,
                 audio_chans as usize,
+                progress,
             )
         }
         // cpal::SampleFormat::I24 => unimplemented!("24 bit output unsupported"),
@@ -394,6 +753,7 @@ pub fn create_stream(
                 rx,
                 &config,
                 audio_chans as usize,
+                progress,
             )
         }
         (cpal::SampleFormat::F32, SampleReceiver::I32(rx)) => {
@@ -402,12 +762,71 @@ pub fn create_stream(
                 rx,
                 &config,
                 audio_chans as usize,
+                progress,
+            )
+        }
+        (cpal::SampleFormat::F32, SampleReceiver::F32(rx)) => {
+            get_output_stream::<f32, _>(
+                &device,
+                rx,
+                &config,
+                audio_chans as usize,
+                progress,
+            )
+        }
+        (cpal::SampleFormat::U16, SampleReceiver::F32(rx)) => {
+            get_output_stream::<u16, _>(
+                &device,
+                rx,
+                &config,
+                audio_chans as usize,
+                progress,
+            )
+        }
+        (cpal::SampleFormat::I16, SampleReceiver::F32(rx)) => {
+            get_output_stream::<i16, _>(
+                &device,
+                rx,
+                &config,
+                audio_chans as usize,
+                progress,
+            )
+        }
+        (cpal::SampleFormat::I24, SampleReceiver::F32(rx))
+        | (cpal::SampleFormat::I32, SampleReceiver::F32(rx)) => {
+            get_output_stream::<i32, _>(
+                &device,
+                rx,
+                &config,
+                audio_chans as usize,
+                progress,
             )
         }
     }
     .unwrap();
 
-    (stream, parse_thread)
+    Ok((stream, parse_thread))
+}
+
+/// how much of the track has been output, shared with the audio thread
+#[derive(Debug, Default)]
+pub struct Progress {
+    frames: AtomicU64,
+    sample_rate: AtomicU32,
+    // 0 if unknown
+    total_frames: AtomicU64,
+    finished: AtomicBool,
+}
+
+/// how much of a track was listened to, see `AudioStream::listened`
+#[derive(Clone, Copy, Debug)]
+pub struct Listened {
+    /// time actually played, not counting pauses
+    pub played: Duration,
+    /// the track's length, if the format says without decoding it all
+    pub length: Option<Duration>,
+    /// played through to the end
+    pub finished: bool,
 }
 
 #[derive(Debug)]
@@ -416,35 +835,63 @@ pub enum StreamCommand {
     Play,
     Stop,
 }
+// how often the stream thread checks whether the track has ended
+const FINISH_POLL: Duration = Duration::from_millis(250);
+
 pub struct AudioStream {
     tx_stream: SyncSender<StreamCommand>,
     _thread: std::thread::JoinHandle<()>,
+    progress: Arc<Progress>,
 }
 
 impl AudioStream {
-    pub fn from_path(source: PathBuf) -> Self {
+    /// `on_finish` is called from the stream's thread once the track has
+    /// played through to the end
+    pub fn from_path<F>(source: PathBuf, on_finish: F) -> Self
+    where
+        F: FnOnce() + Send + 'static,
+    {
         // This implementation is as it is because cpal::Stream is !Send
         // Maybe there is a way to avoid this, but it seems it would require
         // keeping the stream on the main thread, which I'm not sure a lib
         // can guarantee.
 
         let (tx, rx) = std::sync::mpsc::sync_channel(64);
+        let progress = Arc::new(Progress::default());
+        let stream_progress = progress.clone();
         let thread = std::thread::spawn(move || {
-            let (s, _pt) = create_stream(source);
+            let (s, _pt) = match create_stream(source, stream_progress.clone())
+            {
+                Ok(stream) => stream,
+                Err(e) => {
+                    error!("unable to play track {:?}", e);
+                    return;
+                }
+            };
             s.play().unwrap();
 
-            while let Ok(res) = rx.recv() {
-                match res {
-                    StreamCommand::Pause => {
+            let mut on_finish = Some(on_finish);
+            loop {
+                match rx.recv_timeout(FINISH_POLL) {
+                    Ok(StreamCommand::Pause) => {
                         s.pause().unwrap();
                     }
-                    StreamCommand::Play => {
+                    Ok(StreamCommand::Play) => {
                         s.play().unwrap();
                     }
-                    StreamCommand::Stop => {
+                    Ok(StreamCommand::Stop) => {
                         s.pause().unwrap();
                         break;
                     }
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+                // the output callback can't block, so the end of the track
+                // is noticed here
+                if stream_progress.finished.load(Ordering::Relaxed) {
+                    if let Some(on_finish) = on_finish.take() {
+                        on_finish();
+                    }
                 }
             }
         });
@@ -452,18 +899,45 @@ impl AudioStream {
         AudioStream {
             _thread: thread,
             tx_stream: tx,
+            progress,
+        }
+    }
+
+    pub fn listened(&self) -> Listened {
+        let sample_rate = self.progress.sample_rate.load(Ordering::Relaxed);
+        let to_duration = |frames: u64| match sample_rate {
+            // the stream hasn't started yet
+            0 => Duration::default(),
+            rate => Duration::from_secs_f64(frames as f64 / rate as f64),
+        };
+
+        let total_frames = self.progress.total_frames.load(Ordering::Relaxed);
+        Listened {
+            played: to_duration(self.progress.frames.load(Ordering::Relaxed)),
+            length: match total_frames {
+                0 => None,
+                frames => Some(to_duration(frames)),
+            },
+            finished: self.progress.finished.load(Ordering::Relaxed),
         }
     }
 
     pub fn play(&self) {
-        self.tx_stream.send(StreamCommand::Play).unwrap();
+        self.send(StreamCommand::Play)
     }
 
     pub fn pause(&self) {
-        self.tx_stream.send(StreamCommand::Pause).unwrap()
+        self.send(StreamCommand::Pause)
     }
 
     pub fn stop(&self) {
-        self.tx_stream.send(StreamCommand::Stop).unwrap()
+        self.send(StreamCommand::Stop)
+    }
+
+    // the stream thread is gone if the track couldn't be played
+    fn send(&self, command: StreamCommand) {
+        if let Err(e) = self.tx_stream.send(command) {
+            debug!("stream already stopped {:?}", e);
+        }
     }
 }
@@ -0,0 +1,131 @@
+use crate::{hash, models::Track, parse};
+use log::{debug, warn};
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::OsString,
+    fs,
+    path::{Path, PathBuf},
+};
+
+fn collect_files(dir: &Path, files: &mut Vec<(PathBuf, u64)>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(e) => e,
+        Err(e) => {
+            warn!("failed to read dir {:?} {:?}", dir, e);
+            return;
+        }
+    };
+
+    for entry in entries.filter_map(|e| e.ok()) {
+        let path = entry.path();
+        match entry.metadata() {
+            Ok(m) if m.is_dir() => collect_files(&path, files),
+            Ok(m) => files.push((path, m.len())),
+            Err(e) => warn!("failed to stat {:?} {:?}", path, e),
+        }
+    }
+}
+
+fn file_hash(path: &Path) -> Option<String> {
+    let format = parse::detect_format(path)?;
+    match hash::audio_hash(path, format) {
+        Ok(h) => Some(h),
+        Err(e) => {
+            debug!("failed to hash {:?} {:?}", path, e);
+            None
+        }
+    }
+}
+
+/// looks through `search_dirs` for the files of `missing` tracks. a file with
+/// the same name (and size, if known) is taken as long as the audio hash
+/// matches, or it's the only candidate for a track without a hash. anything
+/// still unmatched is looked for by hash alone, to catch renamed files.
+/// paths in `known` are already in the library and never matched
+pub fn find_moved(
+    missing: &[Track],
+    search_dirs: &[PathBuf],
+    known: &HashSet<PathBuf>,
+) -> Vec<(i64, PathBuf)> {
+    let mut files = Vec::new();
+    for dir in search_dirs {
+        collect_files(dir, &mut files);
+    }
+    files.retain(|(path, _)| !known.contains(path));
+
+    let mut by_name: HashMap<OsString, Vec<usize>> = HashMap::new();
+    for (idx, (path, _)) in files.iter().enumerate() {
+        if let Some(name) = path.file_name() {
+            by_name
+                .entry(name.to_owned())
+                .or_insert(Vec::new())
+                .push(idx);
+        }
+    }
+
+    let mut hashes: HashMap<usize, Option<String>> = HashMap::new();
+    let mut claimed = HashSet::new();
+    let mut moved = Vec::new();
+    let mut unmatched = Vec::new();
+    for track in missing {
+        let candidates: Vec<usize> = Path::new(&track.file_path)
+            .file_name()
+            .and_then(|name| by_name.get(name))
+            .map(|c| c.as_slice())
+            .unwrap_or(&[])
+            .iter()
+            .copied()
+            .filter(|idx| !claimed.contains(idx))
+            .filter(|idx| match track.file_size {
+                Some(size) => files[*idx].1 == size as u64,
+                None => true,
+            })
+            .collect();
+
+        let found = match &track.audio_hash {
+            Some(expected) => candidates.into_iter().find(|idx| {
+                let found = hashes
+                    .entry(*idx)
+                    .or_insert_with(|| file_hash(&files[*idx].0));
+                found.as_ref() == Some(expected)
+            }),
+            // ambiguous without a hash to tell them apart
+            None if candidates.len() == 1 => Some(candidates[0]),
+            None => None,
+        };
+
+        match found {
+            Some(idx) => {
+                claimed.insert(idx);
+                moved.push((track.id, files[idx].0.clone()));
+            }
+            None => unmatched.push(track),
+        }
+    }
+
+    // renamed and possibly retagged, only the audio is left to go on
+    if unmatched.iter().any(|t| t.audio_hash.is_some()) {
+        let mut by_hash: HashMap<String, usize> = HashMap::new();
+        for idx in 0..files.len() {
+            if claimed.contains(&idx) {
+                continue;
+            }
+            let found = hashes
+                .entry(idx)
+                .or_insert_with(|| file_hash(&files[idx].0));
+            if let Some(h) = found {
+                by_hash.entry(h.clone()).or_insert(idx);
+            }
+        }
+
+        for track in unmatched {
+            let found =
+                track.audio_hash.as_ref().and_then(|h| by_hash.remove(h));
+            if let Some(idx) = found {
+                moved.push((track.id, files[idx].0.clone()));
+            }
+        }
+    }
+
+    moved
+}
@@ -0,0 +1,248 @@
+use chrono::{Local, NaiveDateTime, TimeZone, Utc};
+use serde_derive::{Deserialize, Serialize};
+use std::io::{self, BufRead, Write};
+
+const LOG_VERSION: &str = "#AUDIOSCROBBLER/1.1";
+// stored plays are UTC, see `models::Play`
+const PLAYED_AT_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// one listen, as exported to and imported from listening history files
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Scrobble {
+    pub artist: String,
+    pub album: Option<String>,
+    pub title: String,
+    pub track_num: Option<i64>,
+    /// seconds listened. a .scrobbler.log doesn't say, so imported entries
+    /// use the track's length
+    pub listened: f64,
+    pub skipped: bool,
+    /// the track's length in seconds, if known
+    #[serde(default)]
+    pub length: Option<f64>,
+    /// unix time, UTC
+    pub timestamp: i64,
+}
+
+impl Scrobble {
+    /// `models::Play::played_at` format. None if the timestamp is out of
+    /// range
+    pub fn played_at(&self) -> Option<String> {
+        Utc.timestamp_opt(self.timestamp, 0)
+            .single()
+            .map(|t| t.format(PLAYED_AT_FORMAT).to_string())
+    }
+
+    /// None if it isn't in the `models::Play::played_at` format
+    pub fn timestamp_from(played_at: &str) -> Option<i64> {
+        NaiveDateTime::parse_from_str(played_at, PLAYED_AT_FORMAT)
+            .ok()
+            .map(|t| t.timestamp())
+    }
+}
+
+// tabs + newlines separate fields and entries, they can't be escaped
+fn log_field(value: &str) -> String {
+    value.replace(&['\t', '\n', '\r'][..], " ")
+}
+
+/// the Audioscrobbler portable player log format. fields are tab separated:
+/// artist, album, title, track number, track length in seconds, L(istened)
+/// or S(kipped), timestamp + an optional MusicBrainz id, which isn't
+/// written. the length is left empty if it isn't known
+pub fn write_scrobbler_log<W: Write>(
+    out: &mut W,
+    scrobbles: &[Scrobble],
+    client: &str,
+) -> io::Result<()> {
+    writeln!(out, "{}", LOG_VERSION)?;
+    writeln!(out, "#TZ/UTC")?;
+    writeln!(out, "#CLIENT/{}", log_field(client))?;
+    for s in scrobbles {
+        writeln!(
+            out,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t",
+            log_field(&s.artist),
+            log_field(s.album.as_deref().unwrap_or_default()),
+            log_field(&s.title),
+            s.track_num.map(|n| n.to_string()).unwrap_or_default(),
+            s.length
+                .map(|l| (l.round() as i64).to_string())
+                .unwrap_or_default(),
+            if s.skipped { "S" } else { "L" },
+            s.timestamp
+        )?;
+    }
+    Ok(())
+}
+
+/// entries missing an artist, title or timestamp are left out. players
+/// without a clock set log local time as "#TZ/UNKNOWN", which is converted
+/// to UTC with this machine's time zone
+pub fn read_scrobbler_log<R: BufRead>(input: R) -> io::Result<Vec<Scrobble>> {
+    let mut local_time = false;
+    let mut scrobbles = Vec::new();
+    for line in input.lines() {
+        let line = line?;
+        if let Some(header) = line.strip_prefix('#') {
+            if header.starts_with("TZ/") {
+                local_time = header != "TZ/UTC";
+            }
+            continue;
+        }
+
+        let fields: Vec<&str> =
+            line.trim_end_matches('\r').split('\t').collect();
+        let field = |i: usize| {
+            fields.get(i).map(|f| f.trim()).filter(|f| !f.is_empty())
+        };
+        let (artist, title, timestamp) = match (
+            field(0),
+            field(2),
+            field(6).and_then(|t| t.parse::<i64>().ok()),
+        ) {
+            (Some(a), Some(t), Some(ts)) => (a, t, ts),
+            _ => continue,
+        };
+        let timestamp = match local_time {
+            true => match NaiveDateTime::from_timestamp_opt(timestamp, 0)
+                .and_then(|t| Local.from_local_datetime(&t).earliest())
+            {
+                Some(t) => t.timestamp(),
+                None => continue,
+            },
+            false => timestamp,
+        };
+
+        let length = field(4).and_then(|l| l.parse().ok());
+        scrobbles.push(Scrobble {
+            artist: artist.to_owned(),
+            album: field(1).map(str::to_owned),
+            title: title.to_owned(),
+            // e.g. "3/12" from some players
+            track_num: field(3)
+                .and_then(|n| n.split('/').next())
+                .and_then(|n| n.parse().ok()),
+            listened: length.unwrap_or(0.0),
+            skipped: field(5) == Some("S"),
+            timestamp,
+            length,
+        });
+    }
+    Ok(scrobbles)
+}
+
+/// one JSON object per line, see `Scrobble`
+pub fn write_json_lines<W: Write>(
+    out: &mut W,
+    scrobbles: &[Scrobble],
+) -> io::Result<()> {
+    for s in scrobbles {
+        serde_json::to_writer(&mut *out, s)?;
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// blank lines are skipped, anything else that isn't a `Scrobble` fails
+pub fn read_json_lines<R: BufRead>(input: R) -> io::Result<Vec<Scrobble>> {
+    let mut scrobbles = Vec::new();
+    for line in input.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        scrobbles.push(serde_json::from_str(&line)?);
+    }
+    Ok(scrobbles)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // whole seconds listened to the end, which a .scrobbler.log keeps
+    fn scrobbles() -> Vec<Scrobble> {
+        vec![
+            Scrobble {
+                artist: "Artist".to_owned(),
+                album: Some("Album".to_owned()),
+                title: "Title".to_owned(),
+                track_num: Some(3),
+                listened: 241.0,
+                skipped: false,
+                timestamp: 1_615_000_000,
+                length: Some(241.0),
+            },
+            Scrobble {
+                artist: "Other Artist".to_owned(),
+                album: None,
+                title: "Other Title".to_owned(),
+                track_num: None,
+                listened: 0.0,
+                skipped: true,
+                timestamp: 1_615_000_300,
+                length: None,
+            },
+        ]
+    }
+
+    #[test]
+    fn scrobbler_log_round_trips() {
+        let mut out = Vec::new();
+        write_scrobbler_log(&mut out, &scrobbles(), "test").unwrap();
+        let text = String::from_utf8(out.clone()).unwrap();
+        assert!(text.contains("Artist\tAlbum\tTitle\t3\t241\tL\t1615000000"));
+
+        assert_eq!(read_scrobbler_log(&out[..]).unwrap(), scrobbles());
+    }
+
+    #[test]
+    fn scrobbler_log_writes_length_not_listened() {
+        let mut scrobble = scrobbles().remove(0);
+        scrobble.listened = 30.4;
+        scrobble.skipped = true;
+        let mut out = Vec::new();
+        write_scrobbler_log(&mut out, &[scrobble], "test").unwrap();
+
+        let read = read_scrobbler_log(&out[..]).unwrap();
+        assert_eq!(read[0].length, Some(241.0));
+        assert!(read[0].skipped);
+    }
+
+    #[test]
+    fn scrobbler_log_replaces_separators() {
+        let mut scrobble = scrobbles().remove(0);
+        scrobble.title = "Two\tLines\n".to_owned();
+        let mut out = Vec::new();
+        write_scrobbler_log(&mut out, &[scrobble], "test").unwrap();
+
+        let read = read_scrobbler_log(&out[..]).unwrap();
+        assert_eq!(read.len(), 1);
+        assert_eq!(read[0].title, "Two Lines");
+    }
+
+    #[test]
+    fn json_lines_round_trip() {
+        let mut scrobbles = scrobbles();
+        scrobbles[0].listened = 120.5;
+        let mut out = Vec::new();
+        write_json_lines(&mut out, &scrobbles).unwrap();
+
+        assert_eq!(read_json_lines(&out[..]).unwrap(), scrobbles);
+    }
+
+    #[test]
+    fn played_at_is_utc() {
+        let scrobble = scrobbles().remove(0);
+        let played_at = scrobble.played_at().unwrap();
+        assert_eq!(played_at, "2021-03-06 03:06:40");
+        assert_eq!(Scrobble::timestamp_from(&played_at), Some(1_615_000_000));
+
+        let out_of_range = Scrobble {
+            timestamp: i64::MAX,
+            ..scrobble
+        };
+        assert_eq!(out_of_range.played_at(), None);
+    }
+}
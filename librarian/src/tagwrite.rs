@@ -0,0 +1,731 @@
+use crate::{
+    key::MusicalKey,
+    models::{ArtistRole, DetailedTrack},
+    parse::{self, AudioFormat},
+};
+use id3::{
+    frame::{ExtendedText, InvolvedPeopleList, InvolvedPeopleListItem},
+    TagLike, Version,
+};
+use std::{
+    fs,
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+const FLAC_PADDING: u8 = 1;
+const FLAC_VORBIS_COMMENT: u8 = 4;
+const FLAC_LAST_BLOCK: u8 = 0x80;
+
+// replaced on write, everything else in the vorbis comment is kept. aliases
+// the parser also reads are dropped so they can't contradict the new values
+const VORBIS_MANAGED: &[&str] = &[
+    "TITLE",
+    "ARTIST",
+    "ALBUM",
+    "ALBUMARTIST",
+    "ALBUM ARTIST",
+    "REMIXER",
+    "PRODUCER",
+    "DATE",
+    "TRACKNUMBER",
+    "TRACKTOTAL",
+    "TOTALTRACKS",
+    "DISCNUMBER",
+    "DISCTOTAL",
+    "TOTALDISCS",
+    "GENRE",
+    "COMPOSER",
+    "BPM",
+    "INITIALKEY",
+    "KEY",
+    "LABEL",
+    "ORGANIZATION",
+    "PUBLISHER",
+    "CATALOGNUMBER",
+    "BARCODE",
+    "UPC",
+    "EAN",
+    "RELEASETYPE",
+    "EDITION",
+];
+
+const INFO_MANAGED: &[&[u8; 4]] = &[
+    b"INAM", b"IART", b"IPRD", b"ICRD", b"ITRK", b"IPRT", b"IGNR",
+];
+
+/// what gets written to a file's tags. empty values remove the tag
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TagValues {
+    pub title: String,
+    /// primary artists, the first crediting any featured ones inline
+    pub artists: Vec<String>,
+    pub remixers: Vec<String>,
+    pub producers: Vec<String>,
+    pub album: Option<String>,
+    pub album_artist: Option<String>,
+    pub date: Option<String>,
+    pub track_num: Option<i64>,
+    pub track_total: Option<i64>,
+    pub disc_num: Option<i64>,
+    pub disc_total: Option<i64>,
+    pub genres: Vec<String>,
+    pub composer: Option<String>,
+    pub bpm: Option<f64>,
+    pub key: Option<MusicalKey>,
+    pub label: Option<String>,
+    pub catalog_num: Option<String>,
+    pub barcode: Option<String>,
+    pub release_type: Option<String>,
+    pub edition: Option<String>,
+}
+
+impl From<&DetailedTrack> for TagValues {
+    fn from(t: &DetailedTrack) -> Self {
+        let credited = |role: ArtistRole| -> Vec<String> {
+            t.track_artists
+                .iter()
+                .filter(|ta| ta.role == role)
+                .map(|ta| ta.artist.name.clone())
+                .collect()
+        };
+
+        // written the way `parse::split_featured` reads them back
+        let mut artists = credited(ArtistRole::Primary);
+        let featured = credited(ArtistRole::Featured);
+        match artists.first_mut() {
+            Some(first) if !featured.is_empty() => {
+                *first = format!("{} feat. {}", first, featured.join(", "))
+            }
+            _ => (),
+        }
+
+        let album_artist = t.release.album_artist_id.and_then(|id| {
+            t.artists
+                .iter()
+                .find(|a| a.id == id)
+                .map(|a| a.name.clone())
+        });
+        let album = match t.release.name.as_str() {
+            parse::UNKNOWN_ENTRY => None,
+            name => Some(name.to_owned()),
+        };
+
+        TagValues {
+            title: t.name.clone(),
+            artists,
+            remixers: credited(ArtistRole::Remixer),
+            producers: credited(ArtistRole::Producer),
+            album,
+            album_artist,
+            date: t.release.date.clone(),
+            track_num: t.track_num,
+            track_total: t.track_total,
+            disc_num: t.disc_num,
+            disc_total: t.disc_total,
+            genres: t.genres.iter().map(|g| g.name.clone()).collect(),
+            composer: t.composer.clone(),
+            bpm: t.bpm,
+            key: t.key,
+            label: t.label.as_ref().map(|l| l.name.clone()),
+            catalog_num: t.release.catalog_num.clone(),
+            barcode: t.release.barcode.clone(),
+            release_type: t.release.release_type.clone(),
+            edition: t.release.edition.clone(),
+        }
+    }
+}
+
+impl TagValues {
+    // detected BPMs have more precision than anyone wants in a tag
+    fn bpm_text(&self) -> Option<String> {
+        self.bpm.map(|b| ((b * 100.0).round() / 100.0).to_string())
+    }
+
+    fn key_text(&self) -> Option<String> {
+        self.key.map(|k| k.to_standard())
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn id3_err(e: id3::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e.to_string())
+}
+
+// "3/12" style position, the total alone isn't worth writing
+fn num_pair(num: Option<i64>, total: Option<i64>) -> Option<String> {
+    match (num, total) {
+        (Some(n), Some(t)) => Some(format!("{}/{}", n, t)),
+        (Some(n), None) => Some(n.to_string()),
+        (None, _) => None,
+    }
+}
+
+// writes to a temp file next to `path` and renames it over the original once
+// it's complete, so a failed write never leaves a half written file behind
+fn write_atomic<F>(path: &Path, write: F) -> io::Result<()>
+where
+    F: FnOnce(&Path) -> io::Result<()>,
+{
+    let name = path
+        .file_name()
+        .ok_or_else(|| invalid("path has no file name"))?;
+    let mut tmp_name = std::ffi::OsString::from(".");
+    tmp_name.push(name);
+    tmp_name.push(".tmp");
+    let tmp: PathBuf = path.with_file_name(tmp_name);
+
+    let result = write(&tmp).and_then(|_| {
+        fs::set_permissions(&tmp, fs::metadata(path)?.permissions())?;
+        fs::File::open(&tmp)?.sync_all()
+    });
+    match result {
+        Ok(()) => fs::rename(&tmp, path),
+        Err(e) => {
+            let _ = fs::remove_file(&tmp);
+            Err(e)
+        }
+    }
+}
+
+fn read_at(file: &mut fs::File, at: u64, buf: &mut [u8]) -> io::Result<()> {
+    file.seek(SeekFrom::Start(at))?;
+    file.read_exact(buf)
+}
+
+fn read_le_u32(b: &[u8], at: &mut usize) -> io::Result<u32> {
+    let bytes = b
+        .get(*at..*at + 4)
+        .ok_or_else(|| invalid("vorbis comment cut short"))?;
+    *at += 4;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn read_vorbis_str(b: &[u8], at: &mut usize) -> io::Result<String> {
+    let len = read_le_u32(b, at)? as usize;
+    let s = b
+        .get(*at..*at + len)
+        .ok_or_else(|| invalid("vorbis comment cut short"))?;
+    *at += len;
+    Ok(String::from_utf8_lossy(s).into_owned())
+}
+
+// (vendor, "KEY=value" comments)
+fn read_vorbis_comment(block: &[u8]) -> io::Result<(String, Vec<String>)> {
+    let mut at = 0;
+    let vendor = read_vorbis_str(block, &mut at)?;
+    let count = read_le_u32(block, &mut at)?;
+    let mut comments = Vec::new();
+    for _ in 0..count {
+        comments.push(read_vorbis_str(block, &mut at)?);
+    }
+    Ok((vendor, comments))
+}
+
+fn vorbis_comment_block(vendor: &str, comments: &[String]) -> Vec<u8> {
+    let mut block = Vec::new();
+    block.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+    block.extend_from_slice(vendor.as_bytes());
+    block.extend_from_slice(&(comments.len() as u32).to_le_bytes());
+    for c in comments {
+        block.extend_from_slice(&(c.len() as u32).to_le_bytes());
+        block.extend_from_slice(c.as_bytes());
+    }
+    block
+}
+
+fn vorbis_comments(values: &TagValues, kept: Vec<String>) -> Vec<String> {
+    let mut comments: Vec<String> = kept
+        .into_iter()
+        .filter(|c| {
+            let key = c.split('=').next().unwrap_or("").to_uppercase();
+            !VORBIS_MANAGED.contains(&key.as_str())
+        })
+        .collect();
+
+    let mut push = |key: &str, value: &str| {
+        if !value.is_empty() {
+            comments.push(format!("{}={}", key, value));
+        }
+    };
+    push("TITLE", &values.title);
+    for a in values.artists.iter() {
+        push("ARTIST", a);
+    }
+    for a in values.remixers.iter() {
+        push("REMIXER", a);
+    }
+    for a in values.producers.iter() {
+        push("PRODUCER", a);
+    }
+    for g in values.genres.iter() {
+        push("GENRE", g);
+    }
+
+    let numbers = [
+        ("TRACKNUMBER", values.track_num),
+        ("TRACKTOTAL", values.track_total),
+        ("DISCNUMBER", values.disc_num),
+        ("DISCTOTAL", values.disc_total),
+    ];
+    for (key, n) in numbers.iter() {
+        if let Some(n) = n {
+            push(key, &n.to_string());
+        }
+    }
+
+    let texts = [
+        ("ALBUM", values.album.clone()),
+        ("ALBUMARTIST", values.album_artist.clone()),
+        ("DATE", values.date.clone()),
+        ("COMPOSER", values.composer.clone()),
+        ("BPM", values.bpm_text()),
+        ("INITIALKEY", values.key_text()),
+        ("LABEL", values.label.clone()),
+        ("CATALOGNUMBER", values.catalog_num.clone()),
+        ("BARCODE", values.barcode.clone()),
+        ("RELEASETYPE", values.release_type.clone()),
+        ("EDITION", values.edition.clone()),
+    ];
+    for (key, text) in texts.iter() {
+        if let Some(t) = text {
+            push(key, t);
+        }
+    }
+
+    comments
+}
+
+// metadata blocks are rebuilt with a new vorbis comment right after
+// STREAMINFO and no padding, the frames after them are copied as is
+fn write_flac(path: &Path, tmp: &Path, values: &TagValues) -> io::Result<()> {
+    let mut file = fs::File::open(path)?;
+    let len = file.metadata()?.len();
+
+    // an ID3v2 tag some taggers put in front, left alone
+    let mut start = 0;
+    let mut header = [0u8; 10];
+    while start + 10 <= len {
+        read_at(&mut file, start, &mut header)?;
+        match parse::id3v2_len(&header) {
+            Some(tag_len) => start += tag_len,
+            None => break,
+        }
+    }
+
+    let mut magic = [0u8; 4];
+    read_at(&mut file, start, &mut magic)?;
+    if &magic != b"fLaC" {
+        return Err(invalid("missing flac stream marker"));
+    }
+
+    let mut blocks = Vec::new();
+    let mut vorbis = None;
+    let mut block_header = [0u8; 4];
+    loop {
+        file.read_exact(&mut block_header)?;
+        let block_type = block_header[0] & !FLAC_LAST_BLOCK;
+        let block_len = u32::from_be_bytes([
+            0,
+            block_header[1],
+            block_header[2],
+            block_header[3],
+        ]);
+        let mut data = vec![0u8; block_len as usize];
+        file.read_exact(&mut data)?;
+
+        match block_type {
+            FLAC_VORBIS_COMMENT => vorbis = Some(read_vorbis_comment(&data)?),
+            FLAC_PADDING => (),
+            _ => blocks.push((block_type, data)),
+        }
+        if block_header[0] & FLAC_LAST_BLOCK != 0 {
+            break;
+        }
+    }
+    let frames_start = file.seek(SeekFrom::Current(0))?;
+    if blocks.is_empty() {
+        return Err(invalid("missing flac STREAMINFO"));
+    }
+
+    let (vendor, kept) =
+        vorbis.unwrap_or_else(|| (String::from("librarian"), Vec::new()));
+    let comments = vorbis_comments(values, kept);
+    let block = vorbis_comment_block(&vendor, &comments);
+    if block.len() >= 1 << 24 {
+        return Err(invalid("vorbis comment too large for a flac block"));
+    }
+    blocks.insert(1, (FLAC_VORBIS_COMMENT, block));
+
+    let mut out = io::BufWriter::new(fs::File::create(tmp)?);
+    file.seek(SeekFrom::Start(0))?;
+    io::copy(&mut (&mut file).take(start), &mut out)?;
+    out.write_all(b"fLaC")?;
+    let last = blocks.len() - 1;
+    for (idx, (block_type, data)) in blocks.iter().enumerate() {
+        let flag = if idx == last { FLAC_LAST_BLOCK } else { 0 };
+        let block_len = (data.len() as u32).to_be_bytes();
+        out.write_all(&[block_type | flag])?;
+        out.write_all(&block_len[1..4])?;
+        out.write_all(data)?;
+    }
+    file.seek(SeekFrom::Start(frames_start))?;
+    io::copy(&mut file, &mut out)?;
+    out.flush()
+}
+
+fn set_id3_text(tag: &mut id3::Tag, frame_id: &str, text: Option<String>) {
+    match text {
+        Some(t) if !t.is_empty() => tag.set_text(frame_id, t),
+        _ => {
+            tag.remove(frame_id);
+        }
+    }
+}
+
+fn set_id3_values(tag: &mut id3::Tag, frame_id: &str, values: &[String]) {
+    match values.is_empty() {
+        true => {
+            tag.remove(frame_id);
+        }
+        false => tag.set_text_values(frame_id, values.iter().cloned()),
+    }
+}
+
+fn set_id3_extended(tag: &mut id3::Tag, description: &str, v: Option<&str>) {
+    tag.remove_extended_text(Some(description), None);
+    if let Some(value) = v.filter(|v| !v.is_empty()) {
+        tag.add_frame(ExtendedText {
+            description: description.to_owned(),
+            value: value.to_owned(),
+        });
+    }
+}
+
+fn apply_to_id3(tag: &mut id3::Tag, values: &TagValues) {
+    set_id3_text(tag, "TIT2", Some(values.title.clone()));
+    set_id3_values(tag, "TPE1", &values.artists);
+    set_id3_text(tag, "TPE2", values.album_artist.clone());
+    set_id3_values(tag, "TPE4", &values.remixers);
+    set_id3_text(tag, "TALB", values.album.clone());
+    // v2.3 dates would be read ahead of nothing, but could outlive a removal
+    tag.remove("TYER");
+    tag.remove("TDAT");
+    set_id3_text(tag, "TDRC", values.date.clone());
+    set_id3_text(tag, "TRCK", num_pair(values.track_num, values.track_total));
+    set_id3_text(tag, "TPOS", num_pair(values.disc_num, values.disc_total));
+    set_id3_values(tag, "TCON", &values.genres);
+    set_id3_text(tag, "TCOM", values.composer.clone());
+    set_id3_text(tag, "TBPM", values.bpm_text());
+    set_id3_text(tag, "TKEY", values.key_text());
+    set_id3_text(tag, "TPUB", values.label.clone());
+    set_id3_extended(tag, "CATALOGNUMBER", values.catalog_num.as_deref());
+    set_id3_extended(tag, "BARCODE", values.barcode.as_deref());
+    set_id3_extended(tag, "RELEASETYPE", values.release_type.as_deref());
+    set_id3_extended(tag, "EDITION", values.edition.as_deref());
+
+    // producers + remixers can also be credited in TIPL, other roles in it
+    // are kept
+    let mut items: Vec<_> = tag
+        .involved_people_lists()
+        .flat_map(|l| l.items.iter())
+        .filter(|p| match p.involvement.to_lowercase().as_str() {
+            "producer" | "remixer" | "mix" => false,
+            _ => true,
+        })
+        .cloned()
+        .collect();
+    tag.remove("TIPL");
+    tag.remove("IPLS");
+    items.extend(values.producers.iter().map(|p| InvolvedPeopleListItem {
+        involvement: String::from("producer"),
+        involvee: p.clone(),
+    }));
+    if !items.is_empty() {
+        tag.add_frame(InvolvedPeopleList { items });
+    }
+}
+
+// the tag is replaced in the copy. ID3v1 is dropped rather than left with
+// stale values, v1 can't hold most of this anyway
+fn write_mp3(path: &Path, tmp: &Path, values: &TagValues) -> io::Result<()> {
+    let mut tag = match id3::Tag::read_from_path(path) {
+        Ok(tag) => tag,
+        Err(e) if matches!(e.kind, id3::ErrorKind::NoTag) => id3::Tag::new(),
+        Err(e) => return Err(id3_err(e)),
+    };
+    apply_to_id3(&mut tag, values);
+
+    fs::copy(path, tmp)?;
+    id3::v1v2::write_to_path(tmp, &tag, Version::Id3v24).map_err(id3_err)
+}
+
+fn info_list(values: &TagValues, kept: Vec<([u8; 4], String)>) -> Vec<u8> {
+    let mut entries: Vec<_> = kept
+        .into_iter()
+        .filter(|(id, _)| !INFO_MANAGED.contains(&id))
+        .collect();
+    let texts = [
+        (b"INAM", Some(values.title.clone())),
+        (b"IART", Some(values.artists.join(", "))),
+        (b"IPRD", values.album.clone()),
+        (b"ICRD", values.date.clone()),
+        (b"ITRK", num_pair(values.track_num, values.track_total)),
+        (b"IGNR", values.genres.first().cloned()),
+    ];
+    for (id, text) in texts.iter() {
+        match text {
+            Some(t) if !t.is_empty() => entries.push((**id, t.clone())),
+            _ => (),
+        }
+    }
+
+    let mut list = b"INFO".to_vec();
+    for (id, text) in entries {
+        // null terminated, padded to an even length
+        let size = text.len() + 1;
+        list.extend_from_slice(&id);
+        list.extend_from_slice(&(size as u32).to_le_bytes());
+        list.extend_from_slice(text.as_bytes());
+        list.push(0);
+        if size & 1 != 0 {
+            list.push(0);
+        }
+    }
+    list
+}
+
+fn write_riff_chunk(
+    out: &mut impl Write,
+    id: &[u8; 4],
+    data: &[u8],
+) -> io::Result<()> {
+    out.write_all(id)?;
+    out.write_all(&(data.len() as u32).to_le_bytes())?;
+    out.write_all(data)?;
+    if data.len() & 1 != 0 {
+        out.write_all(&[0])?;
+    }
+    Ok(())
+}
+
+fn is_info_list(id: &[u8; 4], data: &[u8]) -> bool {
+    id == b"LIST" && data.get(0..4) == Some(&b"INFO"[..])
+}
+
+// chunks are written back in order with the data chunk after them, followed
+// by a new LIST/INFO + id3 chunk in place of the old ones
+fn write_wav(path: &Path, tmp: &Path, values: &TagValues) -> io::Result<()> {
+    let iff = parse::read_iff_chunks(path, false)?;
+    let (data_offset, data_len) = iff
+        .audio_data
+        .ok_or_else(|| invalid("no audio data chunk"))?;
+
+    let info: Vec<_> = iff
+        .chunks
+        .iter()
+        .filter(|(id, data)| is_info_list(id, data))
+        .flat_map(|(_, data)| parse::riff_info_entries(data))
+        .collect();
+    let info = info_list(values, info);
+
+    let mut tag = match iff.get(b"id3 ").or(iff.get(b"ID3 ")) {
+        Some(data) => id3::Tag::read_from2(io::Cursor::new(data))
+            .unwrap_or_else(|_| id3::Tag::new()),
+        None => id3::Tag::new(),
+    };
+    apply_to_id3(&mut tag, values);
+    let mut id3_chunk = Vec::new();
+    tag.write_to(&mut id3_chunk, Version::Id3v24)
+        .map_err(id3_err)?;
+
+    let kept = iff.chunks.iter().filter(|(id, data)| {
+        !is_info_list(id, data) && id != b"id3 " && id != b"ID3 "
+    });
+
+    let mut out = io::BufWriter::new(fs::File::create(tmp)?);
+    out.write_all(b"RIFF")?;
+    // size is fixed up at the end
+    out.write_all(&[0; 4])?;
+    out.write_all(&iff.form_type)?;
+    for (id, data) in kept {
+        write_riff_chunk(&mut out, id, data)?;
+    }
+
+    out.write_all(b"data")?;
+    out.write_all(&(data_len as u32).to_le_bytes())?;
+    let mut file = fs::File::open(path)?;
+    file.seek(SeekFrom::Start(data_offset))?;
+    let copied = io::copy(&mut file.take(data_len), &mut out)?;
+    if copied != data_len {
+        return Err(invalid("wav data chunk runs past end of file"));
+    }
+    if data_len & 1 != 0 {
+        out.write_all(&[0])?;
+    }
+
+    write_riff_chunk(&mut out, b"LIST", &info)?;
+    write_riff_chunk(&mut out, b"id3 ", &id3_chunk)?;
+
+    let mut out = out.into_inner().map_err(io::Error::from)?;
+    let riff_len = out.seek(SeekFrom::End(0))? - 8;
+    if riff_len > u32::MAX as u64 {
+        return Err(invalid("wav too large"));
+    }
+    out.seek(SeekFrom::Start(4))?;
+    out.write_all(&(riff_len as u32).to_le_bytes())
+}
+
+/// rewrites the tags of the file at `path` with `values`, leaving the audio
+/// data untouched. FLAC, MP3 and WAV are supported
+pub fn write_tags(path: &Path, values: &TagValues) -> io::Result<()> {
+    let format = parse::detect_format(path)
+        .ok_or_else(|| invalid("unrecognized audio format"))?;
+    match format {
+        AudioFormat::Flac => {
+            write_atomic(path, |tmp| write_flac(path, tmp, values))
+        }
+        AudioFormat::Mp3 => {
+            write_atomic(path, |tmp| write_mp3(path, tmp, values))
+        }
+        AudioFormat::Wav => {
+            write_atomic(path, |tmp| write_wav(path, tmp, values))
+        }
+        f => Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("writing {:?} tags unsupported", f),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash;
+    use crate::test_util::{fake_audio, temp_file};
+
+    fn values() -> TagValues {
+        TagValues {
+            title: String::from("Night Drive"),
+            artists: vec![String::from("Someone feat. Other")],
+            album: Some(String::from("Roads")),
+            track_num: Some(2),
+            track_total: Some(9),
+            genres: vec![String::from("Techno")],
+            bpm: Some(127.996),
+            key: MusicalKey::parse("8A"),
+            ..TagValues::default()
+        }
+    }
+
+    fn assert_audio_kept(path: &Path, format: AudioFormat) {
+        let before = hash::audio_hash(path, format).unwrap();
+        write_tags(path, &values()).unwrap();
+        assert_eq!(hash::audio_hash(path, format).unwrap(), before);
+        // a second write replaces rather than adds
+        write_tags(path, &values()).unwrap();
+        assert_eq!(hash::audio_hash(path, format).unwrap(), before);
+    }
+
+    #[test]
+    fn flac_audio_unchanged() {
+        let mut file = b"fLaC".to_vec();
+        file.extend_from_slice(&[0, 0, 0, 34]);
+        file.extend_from_slice(&[0; 34]);
+        let comment = vorbis_comment_block(
+            "test",
+            &[String::from("TITLE=Old"), String::from("COMMENT=kept")],
+        );
+        file.push(FLAC_VORBIS_COMMENT);
+        file.extend_from_slice(&(comment.len() as u32).to_be_bytes()[1..4]);
+        file.extend_from_slice(&comment);
+        file.push(FLAC_PADDING | FLAC_LAST_BLOCK);
+        file.extend_from_slice(&[0, 0, 16]);
+        file.extend_from_slice(&[0; 16]);
+        file.extend_from_slice(&fake_audio(4099));
+        let path = temp_file("tags.flac", &file);
+
+        assert_audio_kept(&path, AudioFormat::Flac);
+
+        let written = fs::read(&path).unwrap();
+        let block_len =
+            u32::from_be_bytes([0, written[43], written[44], written[45]]);
+        assert_eq!(written[42], FLAC_VORBIS_COMMENT | FLAC_LAST_BLOCK);
+        let block = &written[46..46 + block_len as usize];
+        let (vendor, comments) = read_vorbis_comment(block).unwrap();
+        assert_eq!(vendor, "test");
+        assert!(comments.contains(&String::from("TITLE=Night Drive")));
+        assert!(comments.contains(&String::from("COMMENT=kept")));
+        assert!(comments.contains(&String::from("BPM=128")));
+        assert!(!comments.contains(&String::from("TITLE=Old")));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn mp3_audio_unchanged() {
+        let mut old = id3::Tag::new();
+        old.set_text("TIT2", "Old");
+        old.set_text("TYER", "1999");
+        let mut file = Vec::new();
+        old.write_to(&mut file, Version::Id3v23).unwrap();
+        // mpeg frame sync so the format is detected
+        file.extend_from_slice(&[0xFF, 0xFB, 0x90, 0x64]);
+        file.extend_from_slice(&fake_audio(4099));
+        let path = temp_file("tags.mp3", &file);
+
+        assert_audio_kept(&path, AudioFormat::Mp3);
+
+        let tag = id3::Tag::read_from_path(&path).unwrap();
+        assert_eq!(tag.version(), Version::Id3v24);
+        assert_eq!(tag.title(), Some("Night Drive"));
+        assert_eq!(tag.track(), Some(2));
+        assert!(tag.get("TYER").is_none());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn wav_audio_unchanged() {
+        let mut fmt = Vec::new();
+        fmt.extend_from_slice(&1u16.to_le_bytes());
+        fmt.extend_from_slice(&2u16.to_le_bytes());
+        fmt.extend_from_slice(&44100u32.to_le_bytes());
+        fmt.extend_from_slice(&(44100u32 * 4).to_le_bytes());
+        fmt.extend_from_slice(&4u16.to_le_bytes());
+        fmt.extend_from_slice(&16u16.to_le_bytes());
+        let info = info_list(
+            &TagValues::default(),
+            vec![(*b"ICMT", String::from("kept"))],
+        );
+
+        let mut chunks = Vec::new();
+        write_riff_chunk(&mut chunks, b"fmt ", &fmt).unwrap();
+        write_riff_chunk(&mut chunks, b"LIST", &info).unwrap();
+        // odd length to cover padding
+        write_riff_chunk(&mut chunks, b"data", &fake_audio(4099)).unwrap();
+        let mut file = b"RIFF".to_vec();
+        file.extend_from_slice(&(chunks.len() as u32 + 4).to_le_bytes());
+        file.extend_from_slice(b"WAVE");
+        file.extend_from_slice(&chunks);
+        let path = temp_file("tags.wav", &file);
+
+        assert_audio_kept(&path, AudioFormat::Wav);
+
+        let iff = parse::read_iff_chunks(&path, false).unwrap();
+        let info = parse::riff_info_entries(iff.get(b"LIST").unwrap());
+        assert!(info.contains(&(*b"INAM", String::from("Night Drive"))));
+        assert!(info.contains(&(*b"ICMT", String::from("kept"))));
+        let tag =
+            id3::Tag::read_from2(io::Cursor::new(iff.get(b"id3 ").unwrap()))
+                .unwrap();
+        assert_eq!(tag.title(), Some("Night Drive"));
+        let riff_len = fs::metadata(&path).unwrap().len() - 8;
+        assert_eq!(
+            &fs::read(&path).unwrap()[4..8],
+            &(riff_len as u32).to_le_bytes()
+        );
+        fs::remove_file(&path).unwrap();
+    }
+}
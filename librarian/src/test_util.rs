@@ -0,0 +1,40 @@
+use std::{fs, path::PathBuf};
+
+/// writes `contents` to a file in the temp dir, unique to this process
+pub fn temp_file(name: &str, contents: &[u8]) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "librarian-{}-{}",
+        std::process::id(),
+        name
+    ));
+    fs::write(&path, contents).unwrap();
+    path
+}
+
+// stands in for frames/samples, nothing reads them but the hash
+pub fn fake_audio(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 7 % 251) as u8).collect()
+}
+
+// 44.1kHz, 2 channels, 16 bit, no audio frames
+pub fn flac_stream(title: &str) -> Vec<u8> {
+    let mut stream = b"fLaC".to_vec();
+    stream.extend_from_slice(&[0, 0, 0, 34]);
+    stream.extend_from_slice(&[0x10, 0, 0x10, 0, 0, 0, 0, 0, 0, 0]);
+    stream.extend_from_slice(&[10, 196, 66, 240, 0, 0, 0, 0]);
+    stream.extend_from_slice(&[0; 16]);
+
+    let vendor = b"test";
+    let entry = format!("TITLE={}", title).into_bytes();
+    let mut comment = Vec::new();
+    comment.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+    comment.extend_from_slice(vendor);
+    comment.extend_from_slice(&1u32.to_le_bytes());
+    comment.extend_from_slice(&(entry.len() as u32).to_le_bytes());
+    comment.extend_from_slice(&entry);
+    // vorbis comment, last block
+    stream.push(0x84);
+    stream.extend_from_slice(&(comment.len() as u32).to_be_bytes()[1..]);
+    stream.extend_from_slice(&comment);
+    stream
+}
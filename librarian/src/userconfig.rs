@@ -1,3 +1,4 @@
+use crate::models::{ArtistMatch, ReleaseMatch};
 use directories_next::UserDirs;
 use serde_derive::{Deserialize, Serialize};
 use std::{
@@ -18,6 +19,16 @@ const DEFAULT_DIR_NAME: &'static str = "recordplayer";
 struct PartialUserConfig {
     pub library_dir: Option<PathBuf>,
     pub copy_on_import: Option<bool>,
+    pub track_name_template: Option<String>,
+    pub release_match: Option<ReleaseMatch>,
+    pub artist_match: Option<ArtistMatch>,
+    pub preferred_bpm_min: Option<f64>,
+    pub preferred_bpm_max: Option<f64>,
+    pub waveform_bands: Option<bool>,
+    pub verify_copies: Option<bool>,
+    pub write_tags: Option<bool>,
+    pub play_threshold_percent: Option<f64>,
+    pub play_threshold_secs: Option<u64>,
 }
 
 #[derive(Serialize)]
@@ -26,6 +37,25 @@ pub struct UserConfig {
     path: PathBuf,
     library_dir: PathBuf,
     copy_on_import: bool,
+    // see `naming::render_track_name`. the original file name is kept if None
+    track_name_template: Option<String>,
+    // how imported tracks get grouped into existing releases
+    release_match: ReleaseMatch,
+    // how tagged artist names are matched to existing artists on import
+    artist_match: ArtistMatch,
+    // detected tempos are doubled / halved to fall in this range
+    preferred_bpm_min: f64,
+    preferred_bpm_max: f64,
+    // low / mid / high energy in waveforms, for coloring
+    waveform_bands: bool,
+    // re-hash copies made on import, see `hash::audio_hash`
+    verify_copies: bool,
+    // edits are written back to the files' tags, see `tagwrite::write_tags`
+    write_tags: bool,
+    // a listen counts as a play once either is reached, or the track ends.
+    // stopping before then is a skip
+    play_threshold_percent: f64,
+    play_threshold_secs: u64,
 }
 
 impl UserConfig {
@@ -45,6 +75,16 @@ impl UserConfig {
         let PartialUserConfig {
             library_dir,
             copy_on_import,
+            track_name_template,
+            release_match,
+            artist_match,
+            preferred_bpm_min,
+            preferred_bpm_max,
+            waveform_bands,
+            verify_copies,
+            write_tags,
+            play_threshold_percent,
+            play_threshold_secs,
         } = toml::from_str(&user_config_str).unwrap();
 
         // config defaults
@@ -58,6 +98,16 @@ impl UserConfig {
                     .join(DEFAULT_DIR_NAME),
             ),
             copy_on_import: copy_on_import.unwrap_or(true),
+            track_name_template,
+            release_match: release_match.unwrap_or(ReleaseMatch::Normal),
+            artist_match: artist_match.unwrap_or(ArtistMatch::IgnoreCase),
+            preferred_bpm_min: preferred_bpm_min.unwrap_or(80.0),
+            preferred_bpm_max: preferred_bpm_max.unwrap_or(160.0),
+            waveform_bands: waveform_bands.unwrap_or(true),
+            verify_copies: verify_copies.unwrap_or(true),
+            write_tags: write_tags.unwrap_or(false),
+            play_threshold_percent: play_threshold_percent.unwrap_or(50.0),
+            play_threshold_secs: play_threshold_secs.unwrap_or(240),
         };
 
         conf.save().unwrap();
@@ -82,4 +132,37 @@ impl UserConfig {
     pub fn copy_on_import(&self) -> bool {
         self.copy_on_import
     }
+
+    pub fn track_name_template(&self) -> Option<&str> {
+        self.track_name_template.as_deref()
+    }
+
+    pub fn release_match(&self) -> ReleaseMatch {
+        self.release_match
+    }
+
+    pub fn artist_match(&self) -> ArtistMatch {
+        self.artist_match
+    }
+
+    pub fn preferred_bpm_range(&self) -> (f64, f64) {
+        (self.preferred_bpm_min, self.preferred_bpm_max)
+    }
+
+    pub fn waveform_bands(&self) -> bool {
+        self.waveform_bands
+    }
+
+    pub fn verify_copies(&self) -> bool {
+        self.verify_copies
+    }
+
+    pub fn write_tags(&self) -> bool {
+        self.write_tags
+    }
+
+    /// percent of the track, seconds
+    pub fn play_threshold(&self) -> (f64, u64) {
+        (self.play_threshold_percent, self.play_threshold_secs)
+    }
 }
@@ -0,0 +1,134 @@
+use crate::parse::{self, ParseResult};
+use log::{debug, error, trace, warn};
+use notify::{DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::mpsc,
+    time::Duration,
+};
+use tokio::sync::mpsc as tokio_mpsc;
+
+// taggers and sync tools tend to write a file in a few goes, wait for them
+// to settle before reading it
+const DEBOUNCE: Duration = Duration::from_secs(2);
+
+/// a settled change to an audio file under a watched dir, see
+/// `Library::apply_fs_change`
+#[derive(Debug)]
+pub enum FsChange {
+    /// a new or modified file
+    Changed(ParseResult),
+    Removed(PathBuf),
+    /// a file moved within the watched dirs. also how taggers that write to a
+    /// temp file save over the original, so the new location is parsed too
+    Moved(PathBuf, ParseResult),
+    DirMoved(PathBuf, PathBuf),
+    /// too much happened at once to keep track of, everything should be
+    /// rescanned
+    Rescan,
+}
+
+/// receives changes until dropped
+pub struct LibraryWatcher {
+    _watcher: RecommendedWatcher,
+    rx: tokio_mpsc::UnboundedReceiver<FsChange>,
+}
+
+impl LibraryWatcher {
+    pub fn new(dirs: &[PathBuf]) -> notify::Result<Self> {
+        let (event_tx, event_rx) = mpsc::channel();
+        let mut watcher = notify::watcher(event_tx, DEBOUNCE)?;
+        for dir in dirs {
+            debug!("watching {:?}", dir);
+            watcher.watch(dir, RecursiveMode::Recursive)?;
+        }
+
+        // parsing happens here, off the async runtime. ends when the watcher
+        // is dropped
+        let (tx, rx) = tokio_mpsc::unbounded_channel();
+        std::thread::spawn(move || {
+            for event in event_rx {
+                trace!("fs event {:?}", event);
+                if !forward_event(&tx, event) {
+                    break;
+                }
+            }
+        });
+
+        Ok(LibraryWatcher {
+            _watcher: watcher,
+            rx,
+        })
+    }
+
+    pub async fn next_change(&mut self) -> Option<FsChange> {
+        self.rx.recv().await
+    }
+}
+
+fn send(tx: &tokio_mpsc::UnboundedSender<FsChange>, change: FsChange) -> bool {
+    tx.send(change).is_ok()
+}
+
+// files in a dir that was created or moved in all at once
+fn send_dir(tx: &tokio_mpsc::UnboundedSender<FsChange>, dir: &Path) -> bool {
+    let entries = match fs::read_dir(dir) {
+        Ok(e) => e,
+        Err(e) => {
+            warn!("failed to read new dir {:?} {:?}", dir, e);
+            return true;
+        }
+    };
+
+    for path in entries.filter_map(|e| e.ok()).map(|e| e.path()) {
+        let sent = match path.is_dir() {
+            true => send_dir(tx, &path),
+            false => match parse::parse_track(path) {
+                Some(result) => send(tx, FsChange::Changed(result)),
+                None => true,
+            },
+        };
+        if !sent {
+            return false;
+        }
+    }
+    true
+}
+
+// false once nothing is listening
+fn forward_event(
+    tx: &tokio_mpsc::UnboundedSender<FsChange>,
+    event: DebouncedEvent,
+) -> bool {
+    match event {
+        DebouncedEvent::Create(path) | DebouncedEvent::Write(path) => {
+            match path.is_dir() {
+                true => send_dir(tx, &path),
+                // not every file is audio
+                false => match parse::parse_track(path) {
+                    Some(result) => send(tx, FsChange::Changed(result)),
+                    None => true,
+                },
+            }
+        }
+        DebouncedEvent::Remove(path) => send(tx, FsChange::Removed(path)),
+        DebouncedEvent::Rename(from, to) => match to.is_dir() {
+            true => send(tx, FsChange::DirMoved(from, to)),
+            false => match parse::parse_track(to) {
+                Some(result) => send(tx, FsChange::Moved(from, result)),
+                // e.g. renamed to something no longer recognized as audio
+                None => send(tx, FsChange::Removed(from)),
+            },
+        },
+        DebouncedEvent::Rescan => send(tx, FsChange::Rescan),
+        DebouncedEvent::Error(e, path) => {
+            error!("watch error {:?} {:?}", path, e);
+            true
+        }
+        // notices come before the debounced event, chmod doesn't matter
+        DebouncedEvent::NoticeWrite(_)
+        | DebouncedEvent::NoticeRemove(_)
+        | DebouncedEvent::Chmod(_) => true,
+    }
+}
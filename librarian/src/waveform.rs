@@ -0,0 +1,311 @@
+use std::{
+    fs,
+    io::{self, Read, Write},
+    path::Path,
+};
+
+const MAGIC: &[u8; 4] = b"RPWF";
+const VERSION: u8 = 1;
+const FLAG_BANDS: u8 = 1;
+
+/// resolution of the finest level
+pub const BINS_PER_SEC: u32 = 20;
+// coarser levels halve the one before until they're about this short
+const MIN_LEVEL_BINS: usize = 64;
+// band split points in Hz, roughly kick / bass, mids, and hats / air
+const LOW_BAND_CUTOFF: f32 = 200.0;
+const HIGH_BAND_CUTOFF: f32 = 2000.0;
+
+/// one slice of a waveform, quantized to keep cache files small
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct WaveformBin {
+    pub min: i8,
+    pub max: i8,
+    pub rms: u8,
+    /// low, mid, high rms. zeroed if bands weren't computed
+    pub bands: [u8; 3],
+}
+
+fn quantize_signed(s: f32) -> i8 {
+    (s.max(-1.0).min(1.0) * 127.0).round() as i8
+}
+
+fn quantize_rms(s: f32) -> u8 {
+    (s.max(0.0).min(1.0) * 255.0).round() as u8
+}
+
+// rms of rms values
+fn merge_rms<I: Iterator<Item = u8>>(values: I) -> u8 {
+    let (sum, count) = values.fold((0.0, 0), |(sum, count), v| {
+        (sum + (v as f32).powi(2), count + 1)
+    });
+    match count {
+        0 => 0,
+        _ => (sum / count as f32).sqrt().round() as u8,
+    }
+}
+
+fn merge_bins(bins: &[WaveformBin]) -> WaveformBin {
+    let mut bands = [0; 3];
+    for (band, merged) in bands.iter_mut().enumerate() {
+        *merged = merge_rms(bins.iter().map(|b| b.bands[band]));
+    }
+    WaveformBin {
+        min: bins.iter().map(|b| b.min).min().unwrap_or(0),
+        max: bins.iter().map(|b| b.max).max().unwrap_or(0),
+        rms: merge_rms(bins.iter().map(|b| b.rms)),
+        bands,
+    }
+}
+
+/// peak / rms overview of a track at a few resolutions, finest first
+#[derive(Debug)]
+pub struct Waveform {
+    pub has_bands: bool,
+    levels: Vec<Vec<WaveformBin>>,
+}
+
+impl Waveform {
+    fn from_bins(bins: Vec<WaveformBin>, has_bands: bool) -> Self {
+        let mut levels = vec![bins];
+        while levels.last().unwrap().len() >= MIN_LEVEL_BINS * 2 {
+            let coarser =
+                levels.last().unwrap().chunks(2).map(merge_bins).collect();
+            levels.push(coarser);
+        }
+
+        Waveform { has_bands, levels }
+    }
+
+    /// bins at the finest resolution
+    pub fn len(&self) -> usize {
+        self.levels[0].len()
+    }
+
+    /// `width` bins covering the whole track, merged from the coarsest level
+    /// that still has enough detail
+    pub fn bins(&self, width: usize) -> Vec<WaveformBin> {
+        let level = self
+            .levels
+            .iter()
+            .rev()
+            .find(|l| l.len() >= width)
+            .unwrap_or(&self.levels[0]);
+        if level.is_empty() {
+            return vec![WaveformBin::default(); width];
+        }
+
+        (0..width)
+            .map(|i| {
+                let from = i * level.len() / width;
+                let to = ((i + 1) * level.len() / width).max(from + 1);
+                merge_bins(&level[from..to.min(level.len())])
+            })
+            .collect()
+    }
+
+    /// (min, max) pairs in -1 - 1
+    pub fn peaks(&self, width: usize) -> Vec<(f32, f32)> {
+        self.bins(width)
+            .iter()
+            .map(|b| (b.min as f32 / 127.0, b.max as f32 / 127.0))
+            .collect()
+    }
+
+    /// 0 - 1
+    pub fn rms(&self, width: usize) -> Vec<f32> {
+        self.bins(width)
+            .iter()
+            .map(|b| b.rms as f32 / 255.0)
+            .collect()
+    }
+
+    /// low, mid, high energy in 0 - 1 for colored waveforms
+    pub fn bands(&self, width: usize) -> Option<Vec<[f32; 3]>> {
+        match self.has_bands {
+            true => Some(
+                self.bins(width)
+                    .iter()
+                    .map(|b| {
+                        let mut bands = [0.0; 3];
+                        for (f, q) in bands.iter_mut().zip(b.bands.iter()) {
+                            *f = *q as f32 / 255.0;
+                        }
+                        bands
+                    })
+                    .collect(),
+            ),
+            false => None,
+        }
+    }
+
+    // only the finest level is stored, the rest are quick to rebuild
+    pub fn write_to(&self, path: &Path) -> io::Result<()> {
+        let flags = match self.has_bands {
+            true => FLAG_BANDS,
+            false => 0,
+        };
+        let mut buf = Vec::with_capacity(10 + self.len() * 6);
+        buf.extend_from_slice(MAGIC);
+        buf.push(VERSION);
+        buf.push(flags);
+        buf.extend_from_slice(&(self.len() as u32).to_le_bytes());
+        for bin in self.levels[0].iter() {
+            buf.push(bin.min as u8);
+            buf.push(bin.max as u8);
+            buf.push(bin.rms);
+            if self.has_bands {
+                buf.extend_from_slice(&bin.bands);
+            }
+        }
+
+        // written next to the target first so readers never see half a file
+        let tmp_path = path.with_extension("tmp");
+        let mut file = fs::File::create(&tmp_path)?;
+        file.write_all(&buf)?;
+        file.sync_all()?;
+        fs::rename(tmp_path, path)
+    }
+
+    pub fn read_from(path: &Path) -> io::Result<Self> {
+        let mut buf = Vec::new();
+        fs::File::open(path)?.read_to_end(&mut buf)?;
+
+        let invalid =
+            |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg);
+        if buf.len() < 10 || &buf[0..4] != MAGIC {
+            return Err(invalid("not a waveform file"));
+        }
+        if buf[4] != VERSION {
+            return Err(invalid("unsupported waveform version"));
+        }
+
+        let has_bands = buf[5] & FLAG_BANDS != 0;
+        let len = u32::from_le_bytes([buf[6], buf[7], buf[8], buf[9]]) as usize;
+        let bin_size = match has_bands {
+            true => 6,
+            false => 3,
+        };
+        let data = &buf[10..];
+        if data.len() != len * bin_size {
+            return Err(invalid("truncated waveform file"));
+        }
+
+        let bins = data
+            .chunks_exact(bin_size)
+            .map(|b| WaveformBin {
+                min: b[0] as i8,
+                max: b[1] as i8,
+                rms: b[2],
+                bands: match has_bands {
+                    true => [b[3], b[4], b[5]],
+                    false => [0; 3],
+                },
+            })
+            .collect();
+
+        Ok(Self::from_bins(bins, has_bands))
+    }
+}
+
+// one pole low pass, cheap enough to run on every sample
+struct LowPass {
+    coeff: f32,
+    value: f32,
+}
+
+impl LowPass {
+    fn new(cutoff: f32, sample_rate: u32) -> Self {
+        let coeff = 1.0
+            - (-2.0 * std::f32::consts::PI * cutoff / sample_rate as f32).exp();
+        LowPass { coeff, value: 0.0 }
+    }
+
+    fn next(&mut self, s: f32) -> f32 {
+        self.value += self.coeff * (s - self.value);
+        self.value
+    }
+}
+
+/// accumulates mono samples at the source rate into waveform bins
+pub struct WaveformBuilder {
+    bin_len: usize,
+    bands: Option<(LowPass, LowPass)>,
+    bins: Vec<WaveformBin>,
+    count: usize,
+    min: f32,
+    max: f32,
+    sum_sq: f32,
+    band_sum_sq: [f32; 3],
+}
+
+impl WaveformBuilder {
+    pub fn new(sample_rate: u32, with_bands: bool) -> Self {
+        let bands = match with_bands {
+            true => Some((
+                LowPass::new(LOW_BAND_CUTOFF, sample_rate),
+                LowPass::new(HIGH_BAND_CUTOFF, sample_rate),
+            )),
+            false => None,
+        };
+        WaveformBuilder {
+            bin_len: (sample_rate / BINS_PER_SEC).max(1) as usize,
+            bands,
+            bins: Vec::new(),
+            count: 0,
+            min: 0.0,
+            max: 0.0,
+            sum_sq: 0.0,
+            band_sum_sq: [0.0; 3],
+        }
+    }
+
+    pub fn push(&mut self, s: f32) {
+        self.min = self.min.min(s);
+        self.max = self.max.max(s);
+        self.sum_sq += s * s;
+        if let Some((low_filter, high_filter)) = self.bands.as_mut() {
+            let low = low_filter.next(s);
+            let below_high = high_filter.next(s);
+            let split = [low, below_high - low, s - below_high];
+            for (sum, band) in self.band_sum_sq.iter_mut().zip(split.iter()) {
+                *sum += band * band;
+            }
+        }
+
+        self.count += 1;
+        if self.count == self.bin_len {
+            self.flush();
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.count == 0 {
+            return;
+        }
+
+        let count = self.count as f32;
+        let mut bands = [0; 3];
+        for (q, sum) in bands.iter_mut().zip(self.band_sum_sq.iter()) {
+            *q = quantize_rms((sum / count).sqrt());
+        }
+        self.bins.push(WaveformBin {
+            min: quantize_signed(self.min),
+            max: quantize_signed(self.max),
+            rms: quantize_rms((self.sum_sq / count).sqrt()),
+            bands,
+        });
+
+        self.count = 0;
+        self.min = 0.0;
+        self.max = 0.0;
+        self.sum_sq = 0.0;
+        self.band_sum_sq = [0.0; 3];
+    }
+
+    pub fn finish(mut self) -> Waveform {
+        self.flush();
+        let has_bands = self.bands.is_some();
+        Waveform::from_bins(self.bins, has_bands)
+    }
+}
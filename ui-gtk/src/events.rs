@@ -54,6 +54,8 @@ pub enum LibraryMsg {
     PlayTrack(i64),
     PlayStream,
     PauseStream,
+    /// records the current listen, then ends the loop
+    Shutdown,
 }
 
 pub type LibEventSender = tokio_mpsc::UnboundedSender<LibraryMsg>;
@@ -65,33 +67,91 @@ pub async fn librarian_event_loop(
     app_chan: glib::Sender<AppMsg>,
 ) {
     let mut listener = listener;
-    while let Some(msg) = listener.recv().await {
-        match msg {
-            LibraryMsg::RefreshTracklist => {
-                let result = lib.get_tracklist().compat().await;
-                app_chan.send(AppMsg::Tracklist(result)).unwrap();
-            }
-            LibraryMsg::ImportDir(path) => {
-                // ideally, this should return tracks in a stream so the UI
-                // is updated with information faster
-                let imported_tracks = lib.import_dir(path).compat().await;
-                {
-                    app_chan
-                        .send(AppMsg::ImportedTracks(imported_tracks))
-                        .unwrap();
+    let mut watcher = match lib.watch().compat().await {
+        Ok(watcher) => Some(watcher),
+        Err(e) => {
+            error!("not watching the library for changes {:?}", e);
+            None
+        }
+    };
+    let mut changes = lib.subscribe();
+    loop {
+        tokio::select! {
+            msg = listener.recv() => match msg {
+                Some(msg) => {
+                    let shutdown = matches!(msg, LibraryMsg::Shutdown);
+                    handle_library_msg(&mut lib, msg, &app_chan).await;
+                    if shutdown {
+                        break;
+                    }
                 }
+                None => break,
+            },
+            // applied here, on the same task as imports. see `Library::watch`
+            Some(change) = next_fs_change(&mut watcher) => {
+                lib.apply_fs_change(change).compat().await;
             }
-            LibraryMsg::PlayTrack(track_id) => {
-                debug!("got track to play {}", track_id);
-                lib.play_track(track_id).compat().await;
-            }
-            LibraryMsg::PlayStream => {
-                lib.play_stream();
+            Ok(change) = changes.recv() => match change {
+                librarian::LibraryChange::StreamFinished(track_id) => {
+                    lib.stream_finished(track_id).compat().await;
+                }
+                change => {
+                    debug!("library changed {:?}", change);
+                    // TODO only update the rows that changed
+                    let result = lib.get_tracklist().compat().await;
+                    app_chan.send(AppMsg::Tracklist(result)).unwrap();
+                }
+            },
+        }
+    }
+}
+
+// never matches without a watcher, leaving the other branches to run
+async fn next_fs_change(
+    watcher: &mut Option<librarian::watch::LibraryWatcher>,
+) -> Option<librarian::watch::FsChange> {
+    match watcher {
+        Some(watcher) => watcher.next_change().await,
+        None => None,
+    }
+}
+
+async fn handle_library_msg(
+    lib: &mut librarian::Library,
+    msg: LibraryMsg,
+    app_chan: &glib::Sender<AppMsg>,
+) {
+    match msg {
+        LibraryMsg::RefreshTracklist => {
+            let result = lib.get_tracklist().compat().await;
+            app_chan.send(AppMsg::Tracklist(result)).unwrap();
+        }
+        LibraryMsg::ImportDir(path) => {
+            // ideally, this should return tracks in a stream so the UI
+            // is updated with information faster
+            let imported_tracks = lib.import_dir(path).compat().await;
+            {
+                app_chan
+                    .send(AppMsg::ImportedTracks(imported_tracks))
+                    .unwrap();
             }
-            LibraryMsg::PauseStream => {
-                debug!("pausing track");
-                lib.pause_stream()
+        }
+        LibraryMsg::PlayTrack(track_id) => {
+            debug!("got track to play {}", track_id);
+            if !lib.play_track(track_id).compat().await {
+                // TODO prompt to relink
+                debug!("track {} is missing its file", track_id);
             }
         }
+        LibraryMsg::PlayStream => {
+            lib.play_stream();
+        }
+        LibraryMsg::PauseStream => {
+            debug!("pausing track");
+            lib.pause_stream()
+        }
+        LibraryMsg::Shutdown => {
+            lib.stop_stream().compat().await;
+        }
     }
 }
@@ -168,7 +168,7 @@ pub async fn main() {
     rx_app.attach(Some(&main_ctx), events::app_event_loop(app_state.clone()));
 
     let lib = librarian::Library::open_or_create().compat().await;
-    tokio::spawn(async move {
+    let lib_task = tokio::spawn(async move {
         events::librarian_event_loop(lib, rx_lib, tx_app).await
     });
 
@@ -191,4 +191,11 @@ pub async fn main() {
 
     let args: Vec<_> = env::args().collect();
     application.run(&args);
+
+    // let the library record what was playing before the runtime goes away
+    // only fails if the loop already ended
+    let _ = tx_lib.send(events::LibraryMsg::Shutdown);
+    if let Err(e) = lib_task.await {
+        error!("library event loop failed {:?}", e);
+    }
 }
@@ -5,6 +5,7 @@ use gtk::{
     ScrolledWindowBuilder, ShadowType, TreeView, TreeViewBuilder,
     TreeViewColumn, TreeViewColumnBuilder, TreeViewGridLines,
 };
+use librarian::models::{ArtistRole, DetailedTrack};
 use log::debug;
 
 // eventually, we'll allow for more configuration with track list columns
@@ -17,6 +18,11 @@ const COLUMNS: &[(&'static str, GLibType)] = &[
     ("Duration", GLibType::String),
     ("Artist", GLibType::String),
     ("Release", GLibType::String),
+    ("Genre", GLibType::String),
+    ("Label", GLibType::String),
+    ("Catalog #", GLibType::String),
+    ("BPM", GLibType::String),
+    ("Key", GLibType::String),
 ];
 
 fn build_column(title: &str, pos: i32, is_visible: bool) -> TreeViewColumn {
@@ -78,21 +84,61 @@ pub fn build_track_list() -> (ScrolledWindow, TreeView, ListStore) {
     (scroll_container, view, list)
 }
 
-pub fn insert_track(list: &ListStore, track: librarian::models::DetailedTrack) {
+fn artist_credit(track: &DetailedTrack) -> String {
+    let names_with_role = |role| {
+        track
+            .track_artists
+            .iter()
+            .filter(|ta| ta.role == role)
+            .map(|ta| ta.artist.name.as_str())
+            .collect::<Vec<_>>()
+    };
+
+    let primary = names_with_role(ArtistRole::Primary);
+    let featured = names_with_role(ArtistRole::Featured);
+
+    // tracks imported before track artists existed only have release artists
+    let mut credit = match primary.is_empty() {
+        true => track
+            .artists
+            .iter()
+            .map(|a| a.name.as_str())
+            .collect::<Vec<_>>()
+            .join(", "),
+        false => primary.join(", "),
+    };
+    if !featured.is_empty() {
+        credit.push_str(" feat. ");
+        credit.push_str(&featured.join(", "));
+    }
+
+    credit
+}
+
+pub fn insert_track(list: &ListStore, track: DetailedTrack) {
     // create a column -> Track property mapping?
     list.insert_with_values(
         None,
-        &[0, 1, 3, 4], // FIXME add duration
+        &[0, 1, 3, 4, 5, 6, 7, 8, 9], // FIXME add duration
         &[
             &track.id,
             &track.name,
+            &artist_credit(&track),
+            &track.release.name,
             &track
-                .artists
-                .into_iter()
-                .map(|a| a.name)
+                .genres
+                .iter()
+                .map(|g| g.name.as_str())
                 .collect::<Vec<_>>()
                 .join(", "),
-            &track.release.name,
+            &track.label.as_ref().map(|l| l.name.as_str()).unwrap_or(""),
+            &track.release.catalog_num.as_deref().unwrap_or(""),
+            &track.bpm.map(|b| format!("{:.1}", b)).unwrap_or_default(),
+            // camelot first, it's what DJs sort by
+            &track
+                .key
+                .map(|k| format!("{} ({})", k.to_camelot(), k))
+                .unwrap_or_default(),
         ],
     );
 }